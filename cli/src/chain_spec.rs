@@ -321,10 +321,7 @@ pub fn testnet_genesis(
 			penalty_rate: 50,
 			minimum_collateral: 0,
 			liquidation_penalty: 1300,
-			collection_account_id: get_account_id_from_seed::<sr25519::Public>("collection_account_id"),
 			liquidation_account: get_account_id_from_seed::<sr25519::Public>("liquidation_account"),
-			pawn_shop: get_account_id_from_seed::<sr25519::Public>("pawn_shop"),
-			profit_pool: get_account_id_from_seed::<sr25519::Public>("profit_pool"),
 			market_dtoken: 0,
 			total_dtoken: 0,
 			saving_interest_rate: 0,
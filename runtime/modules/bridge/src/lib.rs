@@ -11,6 +11,8 @@ use support::{
 };
 use system::{ensure_root, ensure_signed};
 
+use generic_asset::MultiCurrency;
+
 #[cfg(feature = "std")]
 use serde::{Deserialize, Serialize};
 
@@ -59,6 +61,8 @@ pub struct Deposit<AccountId, Balance> {
 
 pub trait Trait: system::Trait + generic_asset::Trait {
     type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+    /// ledger of the shadow BTC asset
+    type Currency: MultiCurrency<Self::AccountId, AssetId = Self::AssetId, Balance = Self::Balance>;
 }
 
 decl_storage! {
@@ -133,7 +137,7 @@ decl_module! {
             let asset_id = Self::asset_id();
             ensure!(Self::has_auth(&author, Auth::Refund), "no refund auth");
             ensure!(Self::pending_withdraws(&who).contains(&amount), "pending withdraw not found");
-            <generic_asset::Module<T>>::make_transfer_with_event(&asset_id, &Self::pending_withdraw_vault(), &who, amount)?;
+            T::Currency::transfer(&asset_id, &Self::pending_withdraw_vault(), &who, amount)?;
             Self::remove_from_pending_withdraws(who.clone(), amount);
             Self::deposit_event(RawEvent::Refund(who, amount));
             Ok(())
@@ -154,7 +158,7 @@ decl_module! {
         pub fn withdraw(origin, amount: T::Balance) -> DispatchResult {
             let who = ensure_signed(origin)?;
             let asset_id = Self::asset_id();
-            <generic_asset::Module<T>>::make_transfer_with_event(&asset_id, &who, &Self::pending_withdraw_vault(), amount)?;
+            T::Currency::transfer(&asset_id, &who, &Self::pending_withdraw_vault(), amount)?;
             if <PendingWithdraws<T>>::contains_key(&who) {
                 <PendingWithdraws<T>>::mutate(&who, |v| {
                     v.push(amount);
@@ -233,11 +237,11 @@ impl<T: Trait> Module<T> {
             account_id: account_id.clone(),
         };
         <DepositHistory<T>>::insert(&tx_hash, dep);
-        <generic_asset::Module<T>>::mint_free(
+        T::Currency::deposit(
             &Self::asset_id(),
             &<sudo::Module<T>>::key(),
             &account_id,
-            &amount,
+            amount,
         )?;
         Self::deposit_event(RawEvent::Deposit(account_id.clone(), amount, tx_hash));
         Ok(())
@@ -193,6 +193,7 @@ impl assets::Trait for TestRuntime {
 }
 impl Trait for TestRuntime {
     type Event = ();
+    type Currency = generic_asset::Module<TestRuntime>;
 }
 
 pub type BridgeTest = Module<TestRuntime>;
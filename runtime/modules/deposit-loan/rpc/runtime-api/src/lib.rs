@@ -0,0 +1,51 @@
+// Copyright (C) 2020 by definex.io
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Runtime API definition for the deposit-loan module.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::{Codec, Decode, Encode};
+#[cfg(feature = "std")]
+use serde::{Deserialize, Serialize};
+use sp_runtime::RuntimeDebug;
+
+/// Cumulative interest figures of one account at a given block.
+/// Diffing two summaries taken at different blocks gives the figures of that block range.
+#[derive(Eq, PartialEq, Encode, Decode, Default, Clone, RuntimeDebug)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+pub struct InterestSummary<Balance, Moment> {
+    /// interest earned on savings, realised by redemptions plus what is still unrealised
+    pub interest_earned: Balance,
+    /// interest paid on all loans ever made by the account
+    pub interest_paid: Balance,
+    /// savings principal still in the pool
+    pub saving_principal: Balance,
+    /// current value of the account's dtoken
+    pub saving_value: Balance,
+    /// timestamp of the block
+    pub now: Moment,
+}
+
+sp_api::decl_runtime_apis! {
+    pub trait DepositLoanApi<AccountId, Balance, Moment> where
+        AccountId: Codec,
+        Balance: Codec,
+        Moment: Codec,
+    {
+        fn interest_summary(who: AccountId) -> InterestSummary<Balance, Moment>;
+    }
+}
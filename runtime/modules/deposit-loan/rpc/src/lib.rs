@@ -0,0 +1,171 @@
+// Copyright (C) 2020 by definex.io
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! RPC interface for the deposit-loan module.
+
+use std::convert::TryInto;
+use std::sync::Arc;
+
+use codec::Codec;
+use jsonrpc_core::{Error as RpcError, ErrorCode, Result};
+use jsonrpc_derive::rpc;
+use serde::{Deserialize, Serialize};
+use sp_api::ProvideRuntimeApi;
+use sp_blockchain::HeaderBackend;
+use sp_runtime::{
+    generic::BlockId,
+    traits::{Block as BlockT, NumberFor, Saturating},
+};
+
+pub use deposit_loan_rpc_runtime_api::{
+    DepositLoanApi as DepositLoanRuntimeApi, InterestSummary,
+};
+
+/// same precision as the interest rates kept by the module
+pub const APY_PREC: u128 = 10000_0000;
+const MILLISECS_PER_YEAR: u128 = 365 * 24 * 60 * 60 * 1000;
+
+/// Interest figures of one account over a block range.
+#[derive(Eq, PartialEq, Default, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InterestReport<Balance> {
+    pub interest_earned: Balance,
+    pub interest_paid: Balance,
+    /// interest earned over the range, annualized and in "APY_PREC", on the mean of the saving
+    /// principal at the start and at the end of the range
+    pub realised_apy: u64,
+}
+
+#[rpc]
+pub trait DepositLoanApi<BlockNumber, AccountId, ResponseType> {
+    /// interest earned and paid by "who" between block "from" and block "to",
+    /// the node must keep the state of both blocks
+    #[rpc(name = "depositLoan_interestReport")]
+    fn interest_report(&self, who: AccountId, from: BlockNumber, to: BlockNumber)
+        -> Result<ResponseType>;
+}
+
+pub struct DepositLoan<C, B> {
+    client: Arc<C>,
+    _marker: std::marker::PhantomData<B>,
+}
+
+impl<C, B> DepositLoan<C, B> {
+    pub fn new(client: Arc<C>) -> Self {
+        DepositLoan {
+            client,
+            _marker: Default::default(),
+        }
+    }
+}
+
+fn runtime_error<E: std::fmt::Debug>(message: &str, e: E) -> RpcError {
+    RpcError {
+        code: ErrorCode::ServerError(1),
+        message: message.into(),
+        data: Some(format!("{:?}", e).into()),
+    }
+}
+
+fn to_u128<T: TryInto<u128>>(value: T, what: &str) -> Result<u128> {
+    value
+        .try_into()
+        .map_err(|_| runtime_error("value out of range", what))
+}
+
+/// "earned" over "start_time".."end_time" in milliseconds, annualized on the mean of the principal
+/// at both ends so that a deposit or a redemption within the range can't skew it without bound
+fn realised_apy(
+    earned: u128,
+    start_principal: u128,
+    end_principal: u128,
+    start_time: u128,
+    end_time: u128,
+) -> Result<u64> {
+    let principal = start_principal.saturating_add(end_principal) / 2;
+    let duration = end_time.saturating_sub(start_time);
+    if principal == 0 || duration == 0 {
+        return Ok(0);
+    }
+    let apy = earned
+        .checked_mul(APY_PREC)
+        .and_then(|v| v.checked_mul(MILLISECS_PER_YEAR))
+        .ok_or_else(|| runtime_error("apy out of range", earned))?
+        / principal
+        / duration;
+    apy.try_into().map_err(|_| runtime_error("apy out of range", apy))
+}
+
+impl<C, Block, AccountId, Balance, Moment>
+    DepositLoanApi<NumberFor<Block>, AccountId, InterestReport<Balance>> for DepositLoan<C, Block>
+where
+    Block: BlockT,
+    C: Send + Sync + 'static + ProvideRuntimeApi<Block> + HeaderBackend<Block>,
+    C::Api: DepositLoanRuntimeApi<Block, AccountId, Balance, Moment>,
+    AccountId: Codec + Clone,
+    Balance: Codec + Copy + Default + Saturating + TryInto<u128>,
+    Moment: Codec + Copy + TryInto<u128>,
+{
+    fn interest_report(
+        &self,
+        who: AccountId,
+        from: NumberFor<Block>,
+        to: NumberFor<Block>,
+    ) -> Result<InterestReport<Balance>> {
+        if from > to {
+            return Err(runtime_error("invalid block range", (from, to)));
+        }
+
+        let api = self.client.runtime_api();
+        let start = api
+            .interest_summary(&BlockId::number(from), who.clone())
+            .map_err(|e| runtime_error("unable to query interest summary", e))?;
+        let end = api
+            .interest_summary(&BlockId::number(to), who)
+            .map_err(|e| runtime_error("unable to query interest summary", e))?;
+
+        let interest_earned = end.interest_earned.saturating_sub(start.interest_earned);
+        let interest_paid = end.interest_paid.saturating_sub(start.interest_paid);
+
+        let realised_apy = realised_apy(
+            to_u128(interest_earned, "interest earned")?,
+            to_u128(start.saving_principal, "saving principal")?,
+            to_u128(end.saving_principal, "saving principal")?,
+            to_u128(start.now, "timestamp")?,
+            to_u128(end.now, "timestamp")?,
+        )?;
+
+        Ok(InterestReport {
+            interest_earned,
+            interest_paid,
+            realised_apy,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apy_is_taken_on_the_mean_principal() {
+        // 5% over a year on 1000 held throughout
+        assert_eq!(realised_apy(50, 1000, 1000, 0, MILLISECS_PER_YEAR), Ok(5000_000));
+        // most of it redeemed at the end doesn't blow the rate up
+        assert_eq!(realised_apy(50, 1000, 0, 0, MILLISECS_PER_YEAR), Ok(10000_000));
+        assert_eq!(realised_apy(50, 0, 0, 0, MILLISECS_PER_YEAR), Ok(0));
+        assert!(realised_apy(u128::max_value(), 1, 1, 0, 1).is_err());
+    }
+}
@@ -1,7 +1,5 @@
-// use new_oracle to get btc price
+// collateral price comes from "T::PriceProvider", see "NewOracleProvider" for the new_oracle backed one
 // Notice：the btc price used here is consered as two assets exchange ratio.
-// let current_price = <new_oracle::Module<T>>::current_price(&token);
-// let price: u64 = TryInto::<u64>::try_into(current_price).unwrap_or(0);
 
 #![cfg_attr(not(feature = "std"), no_std)]
 
@@ -20,21 +18,31 @@ use sp_std::{
 
 #[allow(unused_imports)]
 use sp_runtime::traits::{
-    AtLeast32Bit, Bounded, CheckedAdd, CheckedMul, CheckedSub, MaybeDisplay,
+    AccountIdConversion, AtLeast32Bit, Bounded, CheckedAdd, CheckedMul, CheckedSub, MaybeDisplay,
     MaybeSerializeDeserialize, Member, One, Saturating, Zero,
 };
 
 #[allow(unused_imports)]
-use sp_runtime::{DispatchError, DispatchResult, RuntimeDebug};
+use sp_runtime::{
+    transaction_validity::{InvalidTransaction, TransactionValidity, ValidTransaction},
+    DispatchError, DispatchResult, ModuleId, RuntimeDebug,
+};
+
+use sp_core::crypto::KeyTypeId;
 
 #[allow(unused_imports)]
 use support::{
-    decl_error, decl_event, decl_module, decl_storage, dispatch::Parameter, ensure,
-    weights::SimpleDispatchInfo,
+    debug, decl_error, decl_event, decl_module, decl_storage, dispatch::Parameter, ensure,
+    traits::{BalanceStatus, Get},
+    weights::{ClassifyDispatch, DispatchClass, PaysFee, SimpleDispatchInfo, WeighData, Weight},
 };
 
 #[allow(unused_imports)]
-use frame_system::{self as system, ensure_root, ensure_signed};
+use frame_system::{self as system, ensure_none, ensure_root, ensure_signed, offchain};
+
+use generic_asset::{MultiCurrency, MultiReservableCurrency};
+
+pub use deposit_loan_rpc_runtime_api::InterestSummary;
 
 mod mock;
 mod tests;
@@ -45,17 +53,53 @@ pub const INTEREST_RATE_PREC: u32 = 10000_0000;
 pub const LTV_PREC: u32 = 10000;
 pub const PRICE_PREC: u32 = 10000;
 
+/// weight of reading the price and thresholds in "on_initialize"
+const SCAN_BASE_WEIGHT: Weight = 10_000;
+/// weight of judging one loan or account, and of charging interest on one loan
+const LOAN_CHECK_WEIGHT: Weight = 100_000;
+/// weight of reading one bucket of "LiquidationPriceIndex"
+const INDEX_READ_WEIGHT: Weight = 10_000;
+/// weight of filling, or skipping a cancelled, queued redemption
+const REDEEM_FILL_WEIGHT: Weight = 100_000;
+/// weight of moving one balance to a derived account
+const MIGRATE_BALANCE_WEIGHT: Weight = 100_000;
+/// collection account, pawnshop and profit pool from before they were derived from "ModuleId"
+const OLD_ACCOUNTS: u32 = 3;
+/// reward indices are scaled by it so that small emissions over many shares still count
+const REWARD_INDEX_PREC: u32 = 10000_0000;
+/// a guaranteed loan can name at most this many guarantors
+const MAX_GUARANTORS: usize = 8;
+/// highest annual rate an order can carry, in "INTEREST_RATE_PREC"
+const MAX_FIXED_RATE: u64 = 10 * INTEREST_RATE_PREC as u64;
+
 pub type PriceInUSDT = u64;
 pub type LoanId = u64;
 // pub type CreditLineId = u64;
 pub type LTV = u64;
+pub type RedeemRequestId = u64;
+pub type ApplicationId = u64;
+pub type OrderId = u64;
 pub type LoanResult<T = ()> = result::Result<T, DispatchError>;
 
+pub const KEY_TYPE: KeyTypeId = KeyTypeId(*b"liqd");
+
+pub mod crypto {
+    use super::KEY_TYPE;
+    use sp_runtime::app_crypto::{app_crypto, sr25519};
+    app_crypto!(sr25519, KEY_TYPE);
+}
+
 #[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug)]
 pub enum LoanHealth {
     Well,
     Warning(LTV),
     Liquidating(LTV),
+    /// terminal, only found in "LoanArchive"
+    Repaid,
+    /// terminal, the debt was covered by liquidating the collateral
+    Liquidated,
+    /// terminal, the collateral did not cover the debt and savers took the shortfall
+    Defaulted,
 }
 impl Default for LoanHealth {
     fn default() -> Self {
@@ -63,12 +107,216 @@ impl Default for LoanHealth {
     }
 }
 
+/// what is kept of a loan after it has been closed
+#[derive(Encode, Decode, Clone, Default, PartialEq, Eq, RuntimeDebug)]
+pub struct ClosedLoan<AccountId, Balance, Moment> {
+    pub id: LoanId,
+    pub who: AccountId,
+    /// one of the terminal states
+    pub status: LoanHealth,
+    pub opened_at: Moment,
+    pub closed_at: Moment,
+    pub collateral: Balance,
+    /// debt at the time the loan was closed
+    pub debt: Balance,
+    pub interest_paid: Balance,
+    /// what liquidating the collateral brought in, zero for a repaid loan
+    pub liquidation_proceeds: Balance,
+}
+
+/// repayment history of a borrower, built up as its loans are closed
+#[derive(Encode, Decode, Clone, Default, PartialEq, Eq, RuntimeDebug)]
+pub struct CreditRecord<Balance> {
+    /// loans closed by repaying the debt in full, after running for at least "MinCreditedLoanAge"
+    pub repaid: u32,
+    /// loans closed by liquidating the collateral
+    pub liquidated: u32,
+    /// loans whose collateral did not cover the debt
+    pub defaulted: u32,
+    /// total debt repaid on the loans counted in "repaid"
+    pub volume: Balance,
+}
+
+/// what a borrower needs to have on record to get better terms
+#[derive(Encode, Decode, Clone, Default, PartialEq, Eq, RuntimeDebug)]
+pub struct CreditTier<Balance> {
+    pub min_repaid: u32,
+    pub min_volume: Balance,
+    /// liquidated and defaulted loans taken together
+    pub max_liquidated: u32,
+    /// replaces "GlobalLTVLimit" for new loans and draws, never below it, must stay below "GlobalLiquidationThreshold"
+    pub ltv_limit: LTV,
+    /// in percent, taken off the interest charged on the borrower's loans
+    pub rate_discount: u32,
+}
+
+/// what a guarantor puts up for a loan
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub enum Pledge<Balance> {
+    /// collateral asset, added to the loan's collateral when seized
+    Collateral(Balance),
+    /// dtoken shares, set against the loan's debt when seized
+    Shares(Balance),
+}
+
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug)]
+pub struct Guarantee<AccountId, Balance> {
+    pub guarantor: AccountId,
+    pub pledge: Pledge<Balance>,
+    pub approved: bool,
+    /// savings principal taken along with pledged shares, given back on release
+    pub principal: Balance,
+}
+
+/// a loan waiting for its guarantors to approve their pledges
+#[derive(Encode, Decode, Clone, Default, PartialEq, Eq, RuntimeDebug)]
+pub struct LoanApplication<AccountId, Balance> {
+    pub id: ApplicationId,
+    pub who: AccountId,
+    pub collateral_amount: Balance,
+    pub loan_amount: Balance,
+    /// in the order they are seized
+    pub guarantees: Vec<Guarantee<AccountId, Balance>>,
+}
+
+/// funds a lender offers at a fixed rate, held in the pawnshop until matched or cancelled
+#[derive(Encode, Decode, Clone, Default, PartialEq, Eq, RuntimeDebug)]
+pub struct LendOffer<AccountId, Balance, Moment> {
+    pub id: OrderId,
+    pub lender: AccountId,
+    /// what is left to lend
+    pub amount: Balance,
+    /// annualized, in "INTEREST_RATE_PREC"
+    pub rate: u64,
+    /// the longest loan the lender accepts
+    pub term: Moment,
+}
+
+/// a loan a borrower asks for, its collateral is held in the pawnshop until matched or cancelled
+#[derive(Encode, Decode, Clone, Default, PartialEq, Eq, RuntimeDebug)]
+pub struct BorrowBid<AccountId, Balance, Moment> {
+    pub id: OrderId,
+    pub borrower: AccountId,
+    pub collateral_amount: Balance,
+    pub loan_amount: Balance,
+    /// annualized, in "INTEREST_RATE_PREC"
+    pub max_rate: u64,
+    pub term: Moment,
+}
+
+/// what makes a loan from the order book differ from a pool loan
+#[derive(Encode, Decode, Clone, Default, PartialEq, Eq, RuntimeDebug)]
+pub struct FixedRateTerms<AccountId, Moment> {
+    /// repayments and liquidation proceeds go to the lender instead of the collection account
+    pub lender: AccountId,
+    /// annualized, in "INTEREST_RATE_PREC"
+    pub rate: u64,
+    /// the loan is liquidated when it is not repaid by then
+    pub due: Moment,
+}
+
+/// operations which can be paused one by one
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub enum Operation {
+    /// saving
+    Staking,
+    /// redeeming, cancelling queued redemptions and redeeming in settlement
+    Redeem,
+    /// opening, refinancing and merging loans, switching margin mode
+    Borrow,
+    Draw,
+    /// repaying and settling loans
+    Repay,
+    AddCollateral,
+    Liquidation,
+}
+
+impl Operation {
+    /// repaying and adding collateral only lower the risk of the pool, they go on while the
+    /// whole module is paused and can only be stopped one by one
+    pub fn runs_when_paused(&self) -> bool {
+        match self {
+            Operation::Repay | Operation::AddCollateral => true,
+            _ => false,
+        }
+    }
+}
+
+/// why and when an operation was paused
+#[derive(Encode, Decode, Clone, Default, PartialEq, Eq, RuntimeDebug)]
+pub struct PauseRecord<BlockNumber> {
+    pub reason: Vec<u8>,
+    pub block_number: BlockNumber,
+}
+
+/// assets the pool is configured with
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub enum PoolAsset {
+    Collection,
+    Collateral,
+    Loan,
+    Profit,
+}
+
+/// accounts the pool is configured with
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub enum PoolAccount {
+    Liquidation,
+    Penalty(PenaltyDestination),
+}
+
+/// a governance parameter and its new value
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug)]
+pub enum PoolParameter<Balance, BlockNumber> {
+    GlobalLTVLimit(LTV),
+    GlobalLiquidationThreshold(LTV),
+    GlobalWarningThreshold(LTV),
+    LoanCap(Option<Balance>),
+    PenaltyRate(u32),
+    PenaltySplit(Vec<(PenaltyDestination, u32)>),
+    CloseFactor(u32),
+    OffchainLiquidation(bool),
+    MaxPriceAge(BlockNumber),
+    MaxPriceJump(u32),
+    ScanLimit(u32),
+    CreditTiers(Vec<CreditTier<Balance>>),
+    ReserveCollateral(bool),
+    /// (profit asset emitted per block, percent of it going to savers)
+    RewardEmission(Balance, u32),
+    /// (minimum amount, requests per account, requests in the queue, requests filled per block)
+    RedeemLimits(Balance, u32, u32, u32),
+    /// seconds
+    MinCreditedLoanAge(u32),
+}
+
+/// where a share of the liquidation proceeds left after covering the debt goes
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+pub enum PenaltyDestination {
+    /// the liquidation account which sold the collateral
+    Liquidator,
+    InsuranceFund,
+    Reserve,
+    /// falls back to "ProfitPool" when no treasury account is set
+    Treasury,
+    /// paid into the collection account and shared by all savers
+    Savers,
+    LoanOwner,
+}
+
 #[derive(Encode, Decode, Clone, Default, PartialEq, Eq, RuntimeDebug)]
 pub struct CollateralLoan<Balance> {
     pub collateral_amount: Balance,
     pub loan_amount: Balance,
 }
 
+/// a redemption which could not be filled from the free balance of the collection account
+#[derive(Encode, Decode, Clone, Default, PartialEq, Eq, RuntimeDebug)]
+pub struct RedeemRequest<AccountId, Balance> {
+    pub id: RedeemRequestId,
+    pub who: AccountId,
+    pub amount: Balance,
+}
+
 #[derive(Encode, Decode, Clone, Default, PartialEq, Eq, RuntimeDebug)]
 pub struct Loan<AccountId, Balance> {
     pub id: LoanId,
@@ -107,58 +355,198 @@ where
     }
 }
 
-pub trait Trait:
-    frame_system::Trait + timestamp::Trait + generic_asset::Trait + new_oracle::Trait
+/// weight of "on_initialize", it is asked for after the hook has run so it is the weight actually used
+pub struct ScanWeight<T, I>(sp_std::marker::PhantomData<(T, I)>);
+
+impl<T: Trait<I>, I: Instance> WeighData<T::BlockNumber> for ScanWeight<T, I> {
+    fn weigh_data(&self, _height: T::BlockNumber) -> Weight {
+        <Module<T, I>>::last_scan_weight()
+    }
+}
+
+/// weight of "merge_loans", every merged loan is read, checked and written
+pub struct MergeWeight;
+
+impl<'a> WeighData<(&'a Vec<LoanId>,)> for MergeWeight {
+    fn weigh_data(&self, (loan_ids,): (&'a Vec<LoanId>,)) -> Weight {
+        SCAN_BASE_WEIGHT.saturating_add(LOAN_CHECK_WEIGHT.saturating_mul(loan_ids.len() as Weight))
+    }
+}
+
+impl<'a> ClassifyDispatch<(&'a Vec<LoanId>,)> for MergeWeight {
+    fn classify_dispatch(&self, _: (&'a Vec<LoanId>,)) -> DispatchClass {
+        DispatchClass::Normal
+    }
+}
+
+impl PaysFee for MergeWeight {
+    fn pays_fee(&self) -> bool {
+        true
+    }
+}
+
+/// a price together with the block and moment it was produced and the scale it is expressed in
+#[derive(Encode, Decode, Clone, Default, PartialEq, Eq, RuntimeDebug)]
+pub struct Price<BlockNumber, Moment> {
+    pub value: PriceInUSDT,
+    pub block_number: BlockNumber,
+    pub timestamp: Moment,
+    /// "value" / "scale" is the real price
+    pub scale: u64,
+}
+
+/// where the price of the collateral asset comes from
+pub trait PriceProvider<AssetId, BlockNumber, Moment> {
+    /// a missing price must be an error, never zero
+    fn price(asset_id: AssetId) -> result::Result<Price<BlockNumber, Moment>, DispatchError>;
+}
+
+/// the first provider is asked first, the second one only when it has no price
+impl<AssetId: Copy, BlockNumber, Moment, A, B> PriceProvider<AssetId, BlockNumber, Moment>
+    for (A, B)
+where
+    A: PriceProvider<AssetId, BlockNumber, Moment>,
+    B: PriceProvider<AssetId, BlockNumber, Moment>,
+{
+    fn price(asset_id: AssetId) -> result::Result<Price<BlockNumber, Moment>, DispatchError> {
+        A::price(asset_id).or_else(|_| B::price(asset_id))
+    }
+}
+
+/// reads "new_oracle::CurrentPrice" using the symbol of the asset in generic_asset as the key
+pub struct NewOracleProvider<T>(sp_std::marker::PhantomData<T>);
+
+impl<T> PriceProvider<T::AssetId, T::BlockNumber, T::Moment> for NewOracleProvider<T>
+where
+    T: new_oracle::Trait + generic_asset::Trait,
+{
+    fn price(
+        asset_id: T::AssetId,
+    ) -> result::Result<Price<T::BlockNumber, T::Moment>, DispatchError> {
+        let token = <generic_asset::Module<T>>::symbols(asset_id);
+        ensure!(
+            <new_oracle::Module<T>>::is_token_known(&token),
+            "price not available"
+        );
+        let value = TryInto::<u64>::try_into(<new_oracle::Module<T>>::current_price(&token))
+            .map_err(|_| "price overflow")?;
+        ensure!(!value.is_zero(), "price not available");
+
+        let (block_number, timestamp) = <new_oracle::Module<T>>::price_updated_at(&token);
+        Ok(Price {
+            value,
+            block_number,
+            timestamp,
+            scale: new_oracle::PRICE_SCALE,
+        })
+    }
+}
+
+/// always the same price, for tests or pegged assets, it is never stale. Every asset gets that one
+/// price, so it can't price "InterestAssets" next to the collateral
+pub struct FixedPrice<T, P>(sp_std::marker::PhantomData<(T, P)>);
+
+impl<T, P, AssetId> PriceProvider<AssetId, T::BlockNumber, T::Moment> for FixedPrice<T, P>
+where
+    T: timestamp::Trait,
+    P: Get<PriceInUSDT>,
 {
-    type Event: From<Event<Self>> + Into<<Self as frame_system::Trait>::Event>;
+    fn price(
+        _asset_id: AssetId,
+    ) -> result::Result<Price<T::BlockNumber, T::Moment>, DispatchError> {
+        let value = P::get();
+        ensure!(!value.is_zero(), "price not available");
+        Ok(Price {
+            value,
+            block_number: <frame_system::Module<T>>::block_number(),
+            timestamp: <timestamp::Module<T>>::get(),
+            scale: PRICE_PREC as u64,
+        })
+    }
+}
+
+pub trait Trait<I: Instance = DefaultInstance>: frame_system::Trait + timestamp::Trait {
+    type Event: From<Event<Self, I>> + Into<<Self as frame_system::Trait>::Event>;
+
+    /// ledger holding the collateral, loan and dtoken assets
+    type Currency: MultiReservableCurrency<Self::AccountId>;
+
+    /// price of the collateral asset and of the "InterestAssets", each by its own asset id
+    type PriceProvider: PriceProvider<AssetIdOf<Self, I>, Self::BlockNumber, Self::Moment>;
+
+    type Call: From<Call<Self, I>>;
+
+    /// used by the offchain worker to submit the loans it found liquidatable
+    type SubmitUnsignedTransaction: offchain::SubmitUnsignedTransaction<
+        Self,
+        <Self as Trait<I>>::Call,
+    >;
+
+    /// at most this many loans are carried by one "liquidate" transaction
+    type MaxLiquidationBatch: Get<u32>;
+
+    /// at most this many loans are combined by one "merge_loans" call
+    type MaxMergeLoans: Get<u32>;
+
+    /// the collection account, pawnshop and profit pool are derived from it, nobody holds their keys
+    type ModuleId: Get<ModuleId>;
 }
 
+/// balance type of the ledger behind "Currency"
+pub type BalanceOf<T, I> =
+    <<T as Trait<I>>::Currency as MultiCurrency<<T as frame_system::Trait>::AccountId>>::Balance;
+/// asset id type of the ledger behind "Currency"
+pub type AssetIdOf<T, I> =
+    <<T as Trait<I>>::Currency as MultiCurrency<<T as frame_system::Trait>::AccountId>>::AssetId;
+
 decl_storage! {
-    trait Store for Module<T: Trait> as Saving {
+    trait Store for Module<T: Trait<I>, I: Instance = DefaultInstance> as Saving {
 
         /// module level switch
         Paused get(paused) : bool = false;
+        /// operations paused on their own, "Paused" still stops everything
+        pub PausedOperations get(paused_operations) : map hasher(blake2_256) Operation => Option<PauseRecord<T::BlockNumber>>;
 
         /// the asset that user saves into our program
-        CollectionAssetId get(collection_asset_id) config() : T::AssetId;
+        CollectionAssetId get(collection_asset_id) config() : AssetIdOf<T, I>;
 
-        /// the account where user saves go and it can be either a normal account which held by or a totally random account
-        /// probably need to be supervised by the public
-        CollectionAccountId get(collection_account_id) build(|config: &GenesisConfig<T>| {
-            config.collection_account_id.clone()
-        }) : T::AccountId;
+        /// where savings went before the account was derived from "ModuleId", emptied by the migration
+        CollectionAccountId : Option<T::AccountId>;
 
         /// User will get dtoken when make saving
         /// This will be used to calculate the amount when redeem.
-        pub UserDtoken get(user_dtoken) : linked_map hasher(blake2_256) T::AccountId => T::Balance;
+        pub UserDtoken get(user_dtoken) : linked_map hasher(blake2_256) T::AccountId => BalanceOf<T, I>;
 
         // Total market dtoken generated
-        pub MarketDtoken get(market_dtoken) config(): T::Balance;
+        pub MarketDtoken get(market_dtoken) config(): BalanceOf<T, I>;
 
         // Total dtoken amount
-        pub TotalDtoken get(total_dtoken) config(): T::Balance;
+        pub TotalDtoken get(total_dtoken) config(): BalanceOf<T, I>;
 
         /// time of last distribution of interest
         BonusTime get(bonus_time) : T::Moment;
 
         /// Annualized interest rate of loan
-        pub LoanInterestRateCurrent get(loan_interest_rate_current) config(): T::Balance;
+        pub LoanInterestRateCurrent get(loan_interest_rate_current) config(): BalanceOf<T, I>;
 
         /// use "ProfitAsset" for bonus
-        ProfitAssetId get(profit_asset_id) config() : T::AssetId;
+        ProfitAssetId get(profit_asset_id) config() : AssetIdOf<T, I>;
 
-        /// use a specific account as "ProfitPool"
-        /// might be supervised by the public
-        ProfitPool get(profit_pool) config() : T::AccountId;
+        /// the profit pool before it was derived from "ModuleId", emptied by the migration
+        ProfitPool : Option<T::AccountId>;
 
-        /// the account that user makes loans from, (and assets are all burnt from this account by design)
-        PawnShop get(pawn_shop) config() : T::AccountId;
+        /// the pawnshop before it was derived from "ModuleId", emptied by the migration
+        PawnShop : Option<T::AccountId>;
+        /// set once the balances of the accounts above have been moved to the derived ones
+        AccountsMigrated get(accounts_migrated) : bool;
+        /// the next of the accounts above to move in "on_initialize", they are done at "OLD_ACCOUNTS"
+        AccountMigrationStep get(account_migration_step) : u32;
 
         /// the asset that user uses as collateral when making loans
-        CollateralAssetId get(collateral_asset_id) config() : T::AssetId;
+        CollateralAssetId get(collateral_asset_id) config() : AssetIdOf<T, I>;
 
         /// the asset that defi
-        LoanAssetId get(loan_asset_id) config() : T::AssetId;
+        LoanAssetId get(loan_asset_id) config() : AssetIdOf<T, I>;
 
         /// the maximum LTV that a loan package can be set initially
         pub GlobalLTVLimit get(global_ltv_limit) config() : LTV;
@@ -173,288 +561,902 @@ decl_storage! {
         NextLoanId get(next_loan_id) config() : LoanId;
 
         /// currently running loans
-        pub Loans get(get_loan_by_id) : linked_map hasher(blake2_256) LoanId => Loan<T::AccountId, T::Balance>;
+        pub Loans get(get_loan_by_id) : linked_map hasher(blake2_256) LoanId => Loan<T::AccountId, BalanceOf<T, I>>;
 
         /// loan id aggregated by account
         pub LoansByAccount get(loans_by_account) : map hasher(blake2_256) T::AccountId => Vec<LoanId>;
 
         /// total balance of loan asset in circulation
-        pub TotalLoan get(total_loan) : T::Balance;
+        pub TotalLoan get(total_loan) : BalanceOf<T, I>;
 
         /// total balance of collateral asset locked in the pawnshop
-        pub TotalCollateral get(total_collateral) : T::Balance;
+        pub TotalCollateral get(total_collateral) : BalanceOf<T, I>;
 
         /// when a loan is overdue, a small portion of its collateral will be cut as penalty
         pub PenaltyRate get(penalty_rate) config() : u32;
 
-        /// the official account take charge of selling the collateral asset of liquidating loans
+        /// the official account take charge of selling the collateral asset of liquidating loans,
+        /// it signs "mark_liquidated" and trades off chain so unlike the pool accounts it keeps its keys
         LiquidationAccount get(liquidation_account) config() : T::AccountId;
 
         /// loans which are in liquidating, these loans will not be in "Loans" & "LoansByAccount"
         pub LiquidatingLoans get(liquidating_loans) : Vec<LoanId>;
 
+        /// when on, "on_initialize" no longer scans the loan book, the offchain worker finds
+        /// loans crossing "GlobalLiquidationThreshold" and submits them with "liquidate_unsigned"
+        pub OffchainLiquidation get(offchain_liquidation) : bool = false;
+
+        /// borrowing and withdrawing collateral are refused when the price is older than this many blocks, 0 for no limit
+        pub MaxPriceAge get(max_price_age) : T::BlockNumber;
+        /// in percent, liquidations are suspended when the price moves more than this between two
+        /// aggregation rounds until governance calls "confirm_price", 0 for no limit
+        pub MaxPriceJump get(max_price_jump) : u32;
+        /// (price in "PRICE_PREC", block it was produced at) of the last aggregation round seen
+        pub LastPriceRound get(last_price_round) : Option<(PriceInUSDT, T::BlockNumber)>;
+        /// the price which tripped "MaxPriceJump", liquidations are suspended while it is set
+        pub UnconfirmedPrice get(unconfirmed_price) : Option<PriceInUSDT>;
+
+        /// set by "global_settle", the collateral price is fixed to it and the pool is wound down
+        pub SettlementPrice get(settlement_price) : Option<PriceInUSDT>;
+        /// collateral kept from loans closed by "settle_loan", it belongs to the savers
+        pub SettlementCollateral get(settlement_collateral) : BalanceOf<T, I>;
+
+        /// at most this many loans are judged in one "on_initialize"
+        pub ScanLimit get(scan_limit) : u32 = 200;
+        /// the loan id the next scan starts from, wraps around at "NextLoanId"
+        pub ScanCursor get(scan_cursor) : LoanId;
+        /// the loan id interest is charged from in the next block, wraps around at "NextLoanId"
+        pub InterestCursor get(interest_cursor) : LoanId;
+        /// when interest was last charged on a pool loan
+        pub LoanAccruedAt get(loan_accrued_at) : map hasher(blake2_256) LoanId => Option<T::Moment>;
+        /// weight used by the last "on_initialize"
+        pub LastScanWeight get(last_scan_weight) : Weight;
+        /// loans by bucket of their break-even price, the price at which a loan's LTV reaches 100%,
+        /// see "price_bucket". Loans in the highest buckets are the first to go underwater,
+        /// loans judged together with their account are left out
+        pub LiquidationPriceIndex get(loans_in_price_bucket) : map hasher(blake2_256) u32 => Vec<LoanId>;
+        /// no bucket above it holds a loan
+        pub TopPriceBucket get(top_price_bucket) : u32;
+        /// the break-even price a loan was put in "LiquidationPriceIndex" with
+        pub LoanBreakEvenPrice get(loan_break_even_price) : map hasher(blake2_256) LoanId => Option<PriceInUSDT>;
+
         /// a global cap of loan balance, no caps at all if None
-        pub LoanCap get(loan_cap) : Option<T::Balance>;
+        pub LoanCap get(loan_cap) : Option<BalanceOf<T, I>>;
 
         /// for each loan, the amount of collateral asset must be greater than this
-        pub MinimumCollateral get(minimum_collateral) config() : T::Balance;
+        pub MinimumCollateral get(minimum_collateral) config() : BalanceOf<T, I>;
 
         pub LiquidationPenalty get(liquidation_penalty) config() : u32;
 
-        pub SavingInterestRate get(saving_interest_rate) config() : T::Balance;
-    }
+        pub SavingInterestRate get(saving_interest_rate) config() : BalanceOf<T, I>;
+
+        /// accounts that opted in account-level margin, all their loans are pooled when checking health
+        pub AccountMarginMode get(account_margin_mode) : linked_map hasher(blake2_256) T::AccountId => bool;
+        /// accounts in "AccountMarginMode" by position, so that a few of them can be judged per block
+        MarginAccounts get(margin_account) : map hasher(blake2_256) u32 => Option<T::AccountId>;
+        MarginAccountPosition get(margin_account_position) : map hasher(blake2_256) T::AccountId => Option<u32>;
+        pub MarginAccountCount get(margin_account_count) : u32;
+        /// the position in "MarginAccounts" the next scan starts from
+        MarginAccountCursor get(margin_account_cursor) : u32;
+        /// health of an account-level position when it was last judged, events are only emitted when it changes
+        pub AccountHealth get(account_health) : map hasher(blake2_256) T::AccountId => LoanHealth;
+
+        /// the maximum percentage of a loan's debt that one liquidation may cover,
+        /// a loan needing more than this to get back below "GlobalWarningThreshold" is liquidated as a whole
+        pub CloseFactor get(close_factor) : u32 = 50;
+
+        /// loans which are partially liquidating, with the collateral to sell and the debt it must cover
+        pub PartialLiquidations get(partial_liquidations) : map hasher(blake2_256) LoanId => Option<CollateralLoan<BalanceOf<T, I>>>;
+
+        /// how the liquidation proceeds left after covering the debt are split, percentages sum up to 100
+        /// if empty, "LiquidationPenalty" percent goes to the treasury and the rest back to the loan owner
+        pub PenaltySplit get(penalty_split) : Vec<(PenaltyDestination, u32)>;
+
+        /// accounts of the insurance fund, reserve and treasury destinations
+        pub PenaltyAccounts get(penalty_account) : map hasher(blake2_256) PenaltyDestination => Option<T::AccountId>;
+
+        /// increase monotonically
+        NextRedeemRequestId get(next_redeem_request_id) : RedeemRequestId;
+
+        /// redemptions waiting for liquidity, filled first in first out as repayments and new savings arrive
+        pub RedeemQueue get(redeem_request) : map hasher(blake2_256) RedeemRequestId => Option<RedeemRequest<T::AccountId, BalanceOf<T, I>>>;
+        /// every request below it is filled, dropped or cancelled
+        RedeemQueueHead get(redeem_queue_head) : RedeemRequestId;
+        pub RedeemQueueLength get(redeem_queue_length) : u32;
+        /// total amount waiting in the redeem queue, new loans can't take this liquidity away
+        pub QueuedRedeemTotal get(queued_redeem_total) : BalanceOf<T, I>;
+        pub AccountRedeemRequests get(account_redeem_requests) : map hasher(blake2_256) T::AccountId => Vec<RedeemRequestId>;
+        /// a redemption which can't be filled right away must be at least this much
+        pub MinRedeemAmount get(min_redeem_amount) : BalanceOf<T, I>;
+        pub MaxRedeemRequestsPerAccount get(max_redeem_requests_per_account) : u32 = 10;
+        pub MaxRedeemQueueLength get(max_redeem_queue_length) : u32 = 1000;
+        /// queue entries looked at in one block, cancelled ones included
+        pub RedeemBatch get(redeem_batch) : u32 = 50;
+
+        /// cumulative interest paid by an account over all its loans
+        pub InterestPaid get(interest_paid) : map hasher(blake2_256) T::AccountId => BalanceOf<T, I>;
+
+        /// cumulative interest paid on a loan
+        pub LoanInterestPaid get(loan_interest_paid) : map hasher(blake2_256) LoanId => BalanceOf<T, I>;
+
+        /// when each open loan was created
+        pub LoanOpenedAt get(loan_opened_at) : map hasher(blake2_256) LoanId => T::Moment;
+        /// closed loans, see "prune_archive"
+        pub LoanArchive get(archived_loan) : map hasher(blake2_256) LoanId => Option<ClosedLoan<T::AccountId, BalanceOf<T, I>, T::Moment>>;
+        /// ids in "LoanArchive" by the order the loans were closed in, from "ArchiveHead"
+        /// up to "NextArchivePosition"
+        pub ArchivedLoanIds get(archived_loan_at) : map hasher(blake2_256) u64 => Option<LoanId>;
+        pub ArchiveHead get(archive_head) : u64;
+        pub NextArchivePosition get(next_archive_position) : u64;
+        pub ArchivedLoansByAccount get(archived_loans_by_account) : map hasher(blake2_256) T::AccountId => Vec<LoanId>;
 
-    add_extra_genesis {
-        config(collection_account_id): T::AccountId;
+        /// increase monotonically
+        NextApplicationId get(next_application_id) : ApplicationId;
+        /// guaranteed loans waiting for approvals, the loan is opened by the last approval
+        pub LoanApplications get(loan_application) : map hasher(blake2_256) ApplicationId => Option<LoanApplication<T::AccountId, BalanceOf<T, I>>>;
+        /// pledges backing an open loan, seized in order when it is liquidated
+        pub LoanGuarantees get(loan_guarantees) : map hasher(blake2_256) LoanId => Vec<Guarantee<T::AccountId, BalanceOf<T, I>>>;
+
+        /// increase monotonically, shared by offers and bids
+        NextOrderId get(next_order_id) : OrderId;
+        pub LendOffers get(lend_offer) : map hasher(blake2_256) OrderId => Option<LendOffer<T::AccountId, BalanceOf<T, I>, T::Moment>>;
+        pub BorrowBids get(borrow_bid) : map hasher(blake2_256) OrderId => Option<BorrowBid<T::AccountId, BalanceOf<T, I>, T::Moment>>;
+        /// loans made by matching an offer with a bid, they are kept out of "TotalLoan" and the pool's interest
+        pub FixedRateLoans get(fixed_rate_loan) : map hasher(blake2_256) LoanId => Option<FixedRateTerms<T::AccountId, T::Moment>>;
+
+        /// when on, the collateral of new loans stays reserved in the borrower's account instead of moving to "PawnShop"
+        pub ReserveCollateral get(reserve_collateral) : bool = false;
+        /// (lock id, amount) of the reserve holding a loan's collateral, none if it is held by "PawnShop"
+        pub CollateralLocks get(collateral_lock) : map hasher(blake2_256) LoanId => Option<(u128, BalanceOf<T, I>)>;
+        /// (lock id, amount) of the reserve holding a bid's collateral, it becomes the loan's lock when the bid is matched
+        pub BidCollateralLocks get(bid_collateral_lock) : map hasher(blake2_256) OrderId => Option<(u128, BalanceOf<T, I>)>;
+
+        /// repayment history of each borrower
+        pub CreditRecords get(credit_record) : map hasher(blake2_256) T::AccountId => CreditRecord<BalanceOf<T, I>>;
+        /// the best tier a borrower qualifies for sets its LTV limit and interest discount
+        pub CreditTiers get(credit_tiers) : Vec<CreditTier<BalanceOf<T, I>>>;
+        /// seconds a loan has to run before its repayment counts in "CreditRecords",
+        /// so that credit can't be built up by opening and repaying loans in a row
+        pub MinCreditedLoanAge get(min_credited_loan_age) : u32 = 7 * SEC_PER_DAY;
+
+        /// cumulative interest an account has taken out of the pool by redeeming
+        pub InterestEarnedRealised get(interest_earned_realised) : map hasher(blake2_256) T::AccountId => BalanceOf<T, I>;
+
+        /// the part of an account's savings which is principal rather than interest
+        pub SavingPrincipal get(saving_principal) : map hasher(blake2_256) T::AccountId => BalanceOf<T, I>;
+
+        /// profit asset paid out of "ProfitPool" for each block the pool runs
+        pub RewardPerBlock get(reward_per_block) : BalanceOf<T, I>;
+        /// percent of the emission shared by savers, borrowers share the rest
+        pub SaverRewardShare get(saver_reward_share) : u32 = 50;
+        /// (saver, borrower) reward of one dtoken share / one unit of debt since genesis, scaled by "REWARD_INDEX_PREC"
+        pub RewardIndex get(reward_index) : (BalanceOf<T, I>, BalanceOf<T, I>);
+        /// (dtoken, debt) an account earns rewards on, refreshed whenever either of them changes
+        pub RewardWeights get(reward_weights) : map hasher(blake2_256) T::AccountId => (BalanceOf<T, I>, BalanceOf<T, I>);
+        /// sum of "RewardWeights"
+        pub TotalRewardWeights get(total_reward_weights) : (BalanceOf<T, I>, BalanceOf<T, I>);
+        /// "RewardIndex" when the rewards of an account were last settled
+        RewardIndexOf : map hasher(blake2_256) T::AccountId => (BalanceOf<T, I>, BalanceOf<T, I>);
+        /// rewards settled but not claimed yet
+        pub PendingRewards get(pending_rewards) : map hasher(blake2_256) T::AccountId => BalanceOf<T, I>;
+        /// debt of a pool loan as it was last counted in "AccountDebt"
+        RewardedLoanDebt : map hasher(blake2_256) LoanId => BalanceOf<T, I>;
+        /// running sum of the debt of an account's pool loans, the debt side of "RewardWeights" follows it
+        pub AccountDebt get(account_debt) : map hasher(blake2_256) T::AccountId => BalanceOf<T, I>;
+        /// set once the savers and borrowers from before the rewards have been given their weights
+        RewardsSeeded get(rewards_seeded) : bool;
+        /// the next loan whose debt "seed_rewards" counts
+        RewardSeedLoan : LoanId;
+        /// the last saver "seed_rewards" gave a weight to
+        RewardSeedSaver : Option<T::AccountId>;
+
+        /// assets besides the profit asset that "pay_interest" accepts
+        pub InterestAssets get(interest_assets) : Vec<AssetIdOf<T, I>>;
+        /// interest charged to a loan's debt and not paid yet, repayments pay it off first
+        pub LoanInterestAccrued get(loan_interest_accrued) : map hasher(blake2_256) LoanId => BalanceOf<T, I>;
     }
 }
 
 decl_module! {
-    pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+    pub struct Module<T: Trait<I>, I: Instance = DefaultInstance> for enum Call where origin: T::Origin {
         fn deposit_event() = default;
 
+        #[weight = ScanWeight::<T, I>(sp_std::marker::PhantomData)]
         fn on_initialize(height: T::BlockNumber) {
-            if !Self::paused() {
-                Self::on_each_block(height);
-                Self::calculate_loan_interest_rate();
+            let mut weight: Weight = 0;
+            // what an upgrade left to do is spread over the following blocks
+            if !Self::accounts_migrated() && Self::account_migration_step() < OLD_ACCOUNTS {
+                weight = weight.saturating_add(Self::migrate_next_account());
+            }
+            if !Self::rewards_seeded() {
+                weight = weight.saturating_add(Self::seed_rewards(Self::scan_limit()));
+            }
+            if !Self::paused() && Self::settlement_price().is_none() {
+                Self::track_price_round();
+                // warnings are always given on chain, liquidations may be left to the offchain worker
+                let liquidate = !Self::offchain_liquidation()
+                    && Self::unconfirmed_price().is_none()
+                    && !Self::operation_paused(Operation::Liquidation);
+                weight = weight.saturating_add(Self::on_each_block(height, liquidate));
+                weight = weight.saturating_add(Self::calculate_loan_interest_rate());
+                Self::emit_rewards();
+                weight = weight.saturating_add(Self::process_redeem_queue());
             }
+            <LastScanWeight<I>>::put(weight);
+        }
+
+        #[weight = SimpleDispatchInfo::FixedNormal(0)]
+        pub fn set_max_price_age(origin, age: T::BlockNumber) -> LoanResult {
+            ensure_root(origin)?;
+            <MaxPriceAge<T, I>>::put(age);
+            Self::deposit_event(RawEvent::ParameterChanged(PoolParameter::MaxPriceAge(age)));
+            Ok(())
+        }
+
+        #[weight = SimpleDispatchInfo::FixedNormal(0)]
+        pub fn set_max_price_jump(origin, percent: u32) -> LoanResult {
+            ensure_root(origin)?;
+            <MaxPriceJump<I>>::put(percent);
+            Self::deposit_event(RawEvent::ParameterChanged(PoolParameter::MaxPriceJump(percent)));
+            Ok(())
+        }
+
+        /// accept the price move which tripped "MaxPriceJump" and resume liquidations
+        #[weight = SimpleDispatchInfo::FixedNormal(0)]
+        pub fn confirm_price(origin) -> LoanResult {
+            ensure_root(origin)?;
+            let price = <UnconfirmedPrice<I>>::take().ok_or(Error::<T, I>::NoUnconfirmedPrice)?;
+            Self::deposit_event(RawEvent::PriceConfirmed(price));
+            Ok(())
+        }
+
+        /// wind the pool down at a fixed collateral price: no new loans or savings, borrowers
+        /// repay or settle their loans, savers take a pro-rata part of what is left
+        #[weight = SimpleDispatchInfo::FixedNormal(0)]
+        pub fn global_settle(origin, price: PriceInUSDT) -> LoanResult {
+            ensure_root(origin)?;
+            ensure!(Self::settlement_price().is_none(), Error::<T, I>::InSettlement);
+            ensure!(!price.is_zero(), Error::<T, I>::PriceNotAvailable);
+            <SettlementPrice<I>>::put(price);
+            Self::deposit_event(RawEvent::GlobalSettlement(price));
+            Ok(())
+        }
+
+        /// close a loan at the settlement price, collateral worth the debt goes to the savers
+        /// and the rest back to the borrower, anyone can call it. A loan in liquidation is
+        /// taken out of it, its auction is called off
+        #[weight = SimpleDispatchInfo::FixedNormal(10)]
+        pub fn settle_loan(origin, loan_id: LoanId) -> DispatchResult {
+            Self::ensure_operation(Operation::Repay)?;
+            ensure_signed(origin)?;
+            Self::settle_loan_at_fixed_price(loan_id)
+        }
+
+        /// take the caller's pro-rata part of the remaining loan asset and settled collateral.
+        /// While pool loans are still outstanding only the dtoken worth what is paid out is
+        /// burnt, the rest keeps its claim on the loans settled later
+        #[weight = SimpleDispatchInfo::FixedNormal(10)]
+        pub fn settlement_redeem(origin) -> DispatchResult {
+            Self::ensure_operation(Operation::Redeem)?;
+            let who = ensure_signed(origin)?;
+            Self::make_settlement_redeem(&who)
+        }
+
+        /// drop at most "limit" archived loans which were closed before "before", oldest first
+        #[weight = SimpleDispatchInfo::FixedNormal(10_000)]
+        pub fn prune_archive(origin, before: T::Moment, limit: u32) -> LoanResult {
+            ensure_root(origin)?;
+            Self::prune_loan_archive(before, limit);
+            Ok(())
+        }
+
+        #[weight = SimpleDispatchInfo::FixedNormal(0)]
+        pub fn set_scan_limit(origin, limit: u32) -> LoanResult {
+            ensure_root(origin)?;
+            <ScanLimit<I>>::put(limit);
+            Self::deposit_event(RawEvent::ParameterChanged(PoolParameter::ScanLimit(limit)));
+            Ok(())
+        }
+
+        #[weight = SimpleDispatchInfo::FixedNormal(0)]
+        pub fn set_redeem_limits(
+            origin,
+            min_amount: BalanceOf<T, I>,
+            per_account: u32,
+            queue_length: u32,
+            per_block: u32,
+        ) -> LoanResult {
+            ensure_root(origin)?;
+            // nothing would ever be filled
+            ensure!(!per_block.is_zero(), Error::<T, I>::InvalidRedeemLimits);
+            <MinRedeemAmount<T, I>>::put(min_amount);
+            <MaxRedeemRequestsPerAccount<I>>::put(per_account);
+            <MaxRedeemQueueLength<I>>::put(queue_length);
+            <RedeemBatch<I>>::put(per_block);
+            Self::deposit_event(RawEvent::ParameterChanged(PoolParameter::RedeemLimits(
+                min_amount,
+                per_account,
+                queue_length,
+                per_block,
+            )));
+            Ok(())
         }
 
         #[weight = SimpleDispatchInfo::FixedNormal(0)]
         pub fn pause(origin) -> DispatchResult {
             ensure_root(origin)?;
-            Paused::mutate(|v| *v = true);
+            <Paused<I>>::mutate(|v| *v = true);
             Ok(())
         }
 
         #[weight = SimpleDispatchInfo::FixedNormal(0)]
         pub fn resume(origin) -> DispatchResult {
             ensure_root(origin)?;
-            Paused::mutate(|v| *v = false);
+            <Paused<I>>::mutate(|v| *v = false);
+            Ok(())
+        }
+
+        #[weight = SimpleDispatchInfo::FixedNormal(0)]
+        pub fn pause_operation(origin, operation: Operation, reason: Vec<u8>) -> DispatchResult {
+            ensure_root(origin)?;
+            Self::_pause(operation, reason);
             Ok(())
         }
 
         #[weight = SimpleDispatchInfo::FixedNormal(0)]
-        pub fn set_collection_asset_id(origin, asset_id: T::AssetId) -> DispatchResult {
+        pub fn resume_operation(origin, operation: Operation) -> DispatchResult {
             ensure_root(origin)?;
-            ensure!(<generic_asset::Module<T>>::asset_id_exists(asset_id), "invalid collection asset id");
-            <CollectionAssetId<T>>::put(asset_id);
+            <PausedOperations<T, I>>::remove(operation);
+            Self::deposit_event(RawEvent::OperationResumed(operation));
             Ok(())
         }
 
         #[weight = SimpleDispatchInfo::FixedNormal(0)]
-        pub fn set_collection_account(origin, account_id: T::AccountId) -> DispatchResult {
+        pub fn set_collection_asset_id(origin, asset_id: AssetIdOf<T, I>) -> DispatchResult {
             ensure_root(origin)?;
-            <CollectionAccountId<T>>::put(account_id.clone());
+            ensure!(T::Currency::asset_exists(&asset_id), "invalid collection asset id");
+            <CollectionAssetId<T, I>>::put(asset_id);
+            Self::deposit_event(RawEvent::AssetChanged(PoolAsset::Collection, asset_id));
             Ok(())
         }
 
         #[weight = SimpleDispatchInfo::FixedNormal(0)]
-        pub fn set_collateral_asset_id(origin, asset_id: T::AssetId) -> LoanResult {
+        pub fn set_collateral_asset_id(origin, asset_id: AssetIdOf<T, I>) -> LoanResult {
             ensure_root(origin)?;
-            <CollateralAssetId<T>>::put(asset_id);
+            <CollateralAssetId<T, I>>::put(asset_id);
+            Self::deposit_event(RawEvent::AssetChanged(PoolAsset::Collateral, asset_id));
             Ok(())
         }
 
         #[weight = SimpleDispatchInfo::FixedNormal(0)]
         pub fn set_global_ltv_limit(origin, limit: LTV) -> LoanResult {
             ensure_root(origin)?;
-            GlobalLTVLimit::put(limit);
+            <GlobalLTVLimit<I>>::put(limit);
+            Self::deposit_event(RawEvent::ParameterChanged(PoolParameter::GlobalLTVLimit(limit)));
+            Ok(())
+        }
+
+        /// only loans opened afterwards are affected, running loans keep their collateral where it is
+        #[weight = SimpleDispatchInfo::FixedNormal(0)]
+        pub fn set_reserve_collateral(origin, enabled: bool) -> LoanResult {
+            ensure_root(origin)?;
+            <ReserveCollateral<I>>::put(enabled);
+            Self::deposit_event(RawEvent::ParameterChanged(PoolParameter::ReserveCollateral(enabled)));
+            Ok(())
+        }
+
+        /// "saver_share" is the percent of "per_block" going to savers, the rest goes to borrowers
+        #[weight = SimpleDispatchInfo::FixedNormal(0)]
+        pub fn set_reward_emission(origin, per_block: BalanceOf<T, I>, saver_share: u32) -> LoanResult {
+            ensure_root(origin)?;
+            ensure!(saver_share <= 100, Error::<T, I>::InvalidRewardShare);
+            <RewardPerBlock<T, I>>::put(per_block);
+            <SaverRewardShare<I>>::put(saver_share);
+            Self::deposit_event(RawEvent::ParameterChanged(PoolParameter::RewardEmission(per_block, saver_share)));
+            Ok(())
+        }
+
+        /// replaces the whole list, the profit asset is always accepted
+        #[weight = SimpleDispatchInfo::FixedNormal(0)]
+        pub fn set_interest_assets(origin, asset_ids: Vec<AssetIdOf<T, I>>) -> LoanResult {
+            ensure_root(origin)?;
+            <InterestAssets<T, I>>::put(asset_ids.clone());
+            Self::deposit_event(RawEvent::InterestAssetsChanged(asset_ids));
+            Ok(())
+        }
+
+        /// move what the upgrade could not move out of the accounts used before the derived ones,
+        /// they are gone through again one per block from the next block on
+        #[weight = SimpleDispatchInfo::FixedNormal(0)]
+        pub fn retry_account_migration(origin) -> LoanResult {
+            ensure_root(origin)?;
+            ensure!(!Self::accounts_migrated(), Error::<T, I>::NothingToMigrate);
+            <AccountMigrationStep<I>>::kill();
+            Ok(())
+        }
+
+        /// replaces the whole tier table, an empty table gives every borrower "GlobalLTVLimit" and the full rate
+        #[weight = SimpleDispatchInfo::FixedNormal(0)]
+        pub fn set_credit_tiers(origin, tiers: Vec<CreditTier<BalanceOf<T, I>>>) -> LoanResult {
+            ensure_root(origin)?;
+            let liquidation_thd = Self::global_liquidation_threshold();
+            for tier in tiers.iter() {
+                ensure!(
+                    tier.ltv_limit < liquidation_thd && tier.rate_discount <= 100,
+                    Error::<T, I>::InvalidCreditTier
+                );
+            }
+            <CreditTiers<T, I>>::put(tiers.clone());
+            Self::deposit_event(RawEvent::ParameterChanged(PoolParameter::CreditTiers(tiers)));
+            Ok(())
+        }
+
+        #[weight = SimpleDispatchInfo::FixedNormal(0)]
+        pub fn set_min_credited_loan_age(origin, seconds: u32) -> LoanResult {
+            ensure_root(origin)?;
+            <MinCreditedLoanAge<I>>::put(seconds);
+            Self::deposit_event(RawEvent::ParameterChanged(PoolParameter::MinCreditedLoanAge(seconds)));
             Ok(())
         }
 
         #[weight = SimpleDispatchInfo::FixedNormal(0)]
-        pub fn set_loan_asset_id(origin, asset_id: T::AssetId) -> LoanResult {
+        pub fn set_loan_asset_id(origin, asset_id: AssetIdOf<T, I>) -> LoanResult {
             ensure_root(origin)?;
-            <LoanAssetId<T>>::put(asset_id);
+            <LoanAssetId<T, I>>::put(asset_id);
+            Self::deposit_event(RawEvent::AssetChanged(PoolAsset::Loan, asset_id));
             Ok(())
         }
 
         #[weight = SimpleDispatchInfo::FixedNormal(0)]
         pub fn set_global_liquidation_threshold(origin, threshold: LTV) -> LoanResult {
             ensure_root(origin)?;
-            GlobalWarningThreshold::put(threshold);
+            <GlobalLiquidationThreshold<I>>::put(threshold);
+            Self::deposit_event(RawEvent::ParameterChanged(PoolParameter::GlobalLiquidationThreshold(threshold)));
             Ok(())
         }
 
         #[weight = SimpleDispatchInfo::FixedNormal(0)]
         pub fn set_global_warning_threshold(origin, threshold: LTV) -> LoanResult {
             ensure_root(origin)?;
-            GlobalLiquidationThreshold::put(threshold);
+            <GlobalWarningThreshold<I>>::put(threshold);
+            Self::deposit_event(RawEvent::ParameterChanged(PoolParameter::GlobalWarningThreshold(threshold)));
             Ok(())
         }
 
         #[weight = SimpleDispatchInfo::FixedNormal(0)]
-        pub fn set_loan_cap(origin, balance: T::Balance) -> LoanResult {
+        pub fn set_loan_cap(origin, balance: BalanceOf<T, I>) -> LoanResult {
             ensure_root(origin)?;
             if balance.is_zero() {
-                <LoanCap<T>>::kill();
+                <LoanCap<T, I>>::kill();
             } else {
-                <LoanCap<T>>::put(balance);
+                <LoanCap<T, I>>::put(balance);
             }
+            Self::deposit_event(RawEvent::ParameterChanged(PoolParameter::LoanCap(Self::loan_cap())));
             Ok(())
         }
 
         #[weight = SimpleDispatchInfo::FixedNormal(0)]
         pub fn set_liquidation_account(origin, account_id: T::AccountId) -> LoanResult {
             ensure_root(origin)?;
-            <LiquidationAccount<T>>::put(account_id);
+            <LiquidationAccount<T, I>>::put(account_id);
+            Self::deposit_event(RawEvent::AccountChanged(PoolAccount::Liquidation, account_id));
             Ok(())
         }
 
         #[weight = SimpleDispatchInfo::FixedNormal(0)]
-        pub fn set_profit_asset_id(origin, asset_id: T::AssetId) -> DispatchResult {
+        pub fn set_profit_asset_id(origin, asset_id: AssetIdOf<T, I>) -> DispatchResult {
             ensure_root(origin)?;
-            ensure!(<generic_asset::Module<T>>::asset_id_exists(asset_id), "invalid collection asset id");
-            <ProfitAssetId<T>>::put(asset_id);
+            ensure!(T::Currency::asset_exists(&asset_id), "invalid collection asset id");
+            <ProfitAssetId<T, I>>::put(asset_id);
+            Self::deposit_event(RawEvent::AssetChanged(PoolAsset::Profit, asset_id));
             Ok(())
         }
 
         #[weight = SimpleDispatchInfo::FixedNormal(0)]
-        pub fn set_profit_pool(origin, account_id: T::AccountId) -> DispatchResult {
+        pub fn set_penalty_rate(origin, rate: u32) -> LoanResult {
             ensure_root(origin)?;
-            <ProfitPool<T>>::put(account_id);
+            <PenaltyRate<I>>::put(rate);
+            Self::deposit_event(RawEvent::ParameterChanged(PoolParameter::PenaltyRate(rate)));
             Ok(())
         }
 
         #[weight = SimpleDispatchInfo::FixedNormal(0)]
-        pub fn set_penalty_rate(origin, rate: u32) -> LoanResult {
+        pub fn set_penalty_split(origin, split: Vec<(PenaltyDestination, u32)>) -> LoanResult {
+            ensure_root(origin)?;
+            if !split.is_empty() {
+                let total: u32 = split.iter().fold(0, |acc, (_, pct)| acc.saturating_add(*pct));
+                ensure!(total == 100, Error::<T, I>::InvalidPenaltySplit);
+                for (i, (dest, _)) in split.iter().enumerate() {
+                    ensure!(
+                        !split[i + 1..].iter().any(|(d, _)| d == dest),
+                        Error::<T, I>::InvalidPenaltySplit
+                    );
+                }
+            }
+            <PenaltySplit<I>>::put(split.clone());
+            Self::deposit_event(RawEvent::ParameterChanged(PoolParameter::PenaltySplit(split)));
+            Ok(())
+        }
+
+        #[weight = SimpleDispatchInfo::FixedNormal(0)]
+        pub fn set_penalty_account(origin, destination: PenaltyDestination, account_id: T::AccountId) -> LoanResult {
+            ensure_root(origin)?;
+            match destination {
+                PenaltyDestination::InsuranceFund
+                | PenaltyDestination::Reserve
+                | PenaltyDestination::Treasury => {
+                    <PenaltyAccounts<T, I>>::insert(destination, account_id.clone());
+                    Self::deposit_event(RawEvent::AccountChanged(PoolAccount::Penalty(destination), account_id));
+                    Ok(())
+                }
+                _ => Err(Error::<T, I>::InvalidPenaltySplit)?,
+            }
+        }
+
+        /// percentage, 0 or 100 and above turns partial liquidation off
+        #[weight = SimpleDispatchInfo::FixedNormal(0)]
+        pub fn set_close_factor(origin, close_factor: u32) -> LoanResult {
             ensure_root(origin)?;
-            PenaltyRate::put(rate);
+            <CloseFactor<I>>::put(close_factor);
+            Self::deposit_event(RawEvent::ParameterChanged(PoolParameter::CloseFactor(close_factor)));
             Ok(())
         }
 
         #[weight = SimpleDispatchInfo::FixedNormal(0)]
-        pub fn staking(origin, asset_id: T::AssetId, amount: T::Balance) -> DispatchResult {
-            ensure!(!Self::paused(), "module is paused");
+        pub fn staking(origin, asset_id: AssetIdOf<T, I>, amount: BalanceOf<T, I>) -> DispatchResult {
+            Self::ensure_operation(Operation::Staking)?;
             let who = ensure_signed(origin)?;
-            ensure!(<CollectionAssetId<T>>::get() == asset_id, "can't collect this asset");
-            ensure!(<generic_asset::Module<T>>::free_balance(&asset_id, &who) >= amount, "insufficient balance");
+            ensure!(<CollectionAssetId<T, I>>::get() == asset_id, "can't collect this asset");
+            ensure!(T::Currency::free_balance(&asset_id, &who) >= amount, "insufficient balance");
             Self::create_staking(who.clone(), asset_id, amount)?;
+            Self::process_redeem_queue();
             Ok(())
         }
 
         #[weight = SimpleDispatchInfo::FixedNormal(0)]
-        pub fn sudo_staking(origin, asset_id: T::AssetId, amount: T::Balance, delegatee: T::AccountId) -> DispatchResult {
-            ensure!(!Self::paused(), "module is paused");
+        pub fn sudo_staking(origin, asset_id: AssetIdOf<T, I>, amount: BalanceOf<T, I>, delegatee: T::AccountId) -> DispatchResult {
+            Self::ensure_operation(Operation::Staking)?;
             ensure_root(origin)?;
-            ensure!(<CollectionAssetId<T>>::get() == asset_id, "can't collect this asset");
-            ensure!(<generic_asset::Module<T>>::free_balance(&asset_id, &delegatee) >= amount, "insufficient balance");
+            ensure!(<CollectionAssetId<T, I>>::get() == asset_id, "can't collect this asset");
+            ensure!(T::Currency::free_balance(&asset_id, &delegatee) >= amount, "insufficient balance");
             Self::create_staking(delegatee.clone(), asset_id, amount)?;
+            Self::process_redeem_queue();
             Ok(())
         }
 
+        /// redeem right away if the collection account has enough free balance,
+        /// otherwise the redemption is queued and filled when liquidity comes back
         #[weight = SimpleDispatchInfo::FixedNormal(0)]
-        pub fn redeem(origin, iou_asset_id: T::AssetId, iou_asset_amount: T::Balance) -> DispatchResult {
-            ensure!(!Self::paused(), "module is paused");
+        pub fn redeem(origin, iou_asset_id: AssetIdOf<T, I>, iou_asset_amount: BalanceOf<T, I>) -> DispatchResult {
+            Self::ensure_operation(Operation::Redeem)?;
             let who = ensure_signed(origin)?;
             let collection_asset_id = Self::collection_asset_id();
-            let collection_account_id = Self::collection_account_id();
             // ensure!(!collection_asset_id.is_zero(), "fail to find collection asset id");
-            ensure!(<generic_asset::Module<T>>::free_balance(&collection_asset_id, &collection_account_id) >= iou_asset_amount, "Not enough to redeem");
             ensure!(collection_asset_id == iou_asset_id, "collection asset id different from iou asset id");
 
-            Self::make_redeem(
-                &who,
-                &collection_asset_id,
-                &collection_account_id,
-                iou_asset_amount,
-            )?;
-            Ok(())
+            Self::request_redeem(who, iou_asset_amount)
         }
 
         #[weight = SimpleDispatchInfo::FixedNormal(0)]
-        pub fn sudo_redeem(origin, iou_asset_id: T::AssetId, iou_asset_amount: T::Balance, delegatee: T::AccountId) -> DispatchResult {
-            ensure!(!Self::paused(), "module is paused");
+        pub fn sudo_redeem(origin, iou_asset_id: AssetIdOf<T, I>, iou_asset_amount: BalanceOf<T, I>, delegatee: T::AccountId) -> DispatchResult {
+            Self::ensure_operation(Operation::Redeem)?;
             ensure_root(origin)?;
             let collection_asset_id = Self::collection_asset_id();
-            let collection_account_id = Self::collection_account_id();
             // ensure!(!collection_asset_id.is_zero(), "fail to find collection asset id");
-            ensure!(<generic_asset::Module<T>>::free_balance(&collection_asset_id, &collection_account_id) >= iou_asset_amount, "Not enough to redeem");
             ensure!(collection_asset_id == iou_asset_id, "collection asset id different from iou asset id");
 
-            Self::make_redeem(
-                &delegatee,
-                &collection_asset_id,
-                &collection_account_id,
-                iou_asset_amount,
-            )?;
+            Self::request_redeem(delegatee, iou_asset_amount)
+        }
+
+        /// redeem by burning exactly "dtoken_amount" of shares
+        #[weight = SimpleDispatchInfo::FixedNormal(0)]
+        pub fn redeem_shares(origin, dtoken_amount: BalanceOf<T, I>) -> DispatchResult {
+            Self::ensure_operation(Operation::Redeem)?;
+            let who = ensure_signed(origin)?;
+            Self::make_redeem_shares(&who, dtoken_amount)
+        }
+
+        /// redeem by burning all the shares the user holds
+        #[weight = SimpleDispatchInfo::FixedNormal(0)]
+        pub fn redeem_all(origin) -> DispatchResult {
+            Self::ensure_operation(Operation::Redeem)?;
+            let who = ensure_signed(origin)?;
+            Self::make_redeem_all(&who)
+        }
+
+        /// take back a redemption that is still waiting in the queue
+        #[weight = SimpleDispatchInfo::FixedNormal(0)]
+        pub fn cancel_redeem(origin, request_id: RedeemRequestId) -> DispatchResult {
+            Self::ensure_operation(Operation::Redeem)?;
+            let who = ensure_signed(origin)?;
+            let request = Self::redeem_request(request_id)
+                .filter(|r| r.who == who)
+                .ok_or(Error::<T, I>::UnknownRedeemRequest)?;
+            Self::dequeue_redeem(&request);
+            Self::deposit_event(RawEvent::RedeemCancelled(request_id, who));
             Ok(())
         }
 
         /// a user can apply for a loan choosing one active loan package, providing the collateral and loan amount he wants,
         #[weight = SimpleDispatchInfo::FixedNormal(10)]
-        pub fn apply_loan(origin, collateral_amount: T::Balance, loan_amount: T::Balance) -> LoanResult {
-            ensure!(!Self::paused(), "module is paused");
+        pub fn apply_loan(origin, collateral_amount: BalanceOf<T, I>, loan_amount: BalanceOf<T, I>) -> LoanResult {
+            Self::ensure_operation(Operation::Borrow)?;
             let who = ensure_signed(origin)?;
             Self::apply_for_loan(who.clone(), collateral_amount, loan_amount)
         }
 
-        /// a user repay a loan he has made before, by providing the loan id and he should make sure there is enough related assets in his account
+        /// apply for a loan backed by guarantors as well as by the caller's own collateral,
+        /// the loan is opened once every guarantor has called "approve_guarantee"
         #[weight = SimpleDispatchInfo::FixedNormal(10)]
-        pub fn repay_loan(origin, loan_id: LoanId) -> LoanResult {
-            ensure!(!Self::paused(), "module is paused");
+        pub fn apply_guaranteed_loan(
+            origin,
+            collateral_amount: BalanceOf<T, I>,
+            loan_amount: BalanceOf<T, I>,
+            guarantees: Vec<(T::AccountId, Pledge<BalanceOf<T, I>>)>
+        ) -> DispatchResult {
+            Self::ensure_operation(Operation::Borrow)?;
             let who = ensure_signed(origin)?;
-            Self::repay_for_loan(who.clone(), loan_id)
+            Self::apply_for_guaranteed_loan(who, collateral_amount, loan_amount, guarantees)
         }
 
-        /// when a liquidating loan has been handled well, platform mananger should call "mark_liquidated" to update the chain
-        /// loan id is the loan been handled and auction_balance is what the liquidation got by selling the collateral asset
-        /// auction_balance will be first used to make up the loan, then what so ever left will be returned to the loan's owner account
+        /// a guarantor locks what it pledged for an application
         #[weight = SimpleDispatchInfo::FixedNormal(10)]
-        pub fn mark_liquidated(origin, loan_id: LoanId, auction_balance: T::Balance) -> DispatchResult {
-            ensure!(!Self::paused(), "module is paused");
-            let liquidation_account = ensure_signed(origin)?;
-            ensure!(liquidation_account == Self::liquidation_account(), "liquidation account only");
-            ensure!(<Loans<T>>::contains_key(loan_id), "loan doesn't exists");
-
-            Self::mark_loan_liquidated(&Self::get_loan_by_id(loan_id), liquidation_account, auction_balance)
+        pub fn approve_guarantee(origin, application_id: ApplicationId) -> DispatchResult {
+            Self::ensure_operation(Operation::Borrow)?;
+            let guarantor = ensure_signed(origin)?;
+            Self::approve_loan_guarantee(guarantor, application_id)
         }
 
-        /// when user got a warning of high-risk LTV, user can lower the LTV by add more collateral
+        /// the borrower or any of its guarantors drops an application, approved pledges are given back
         #[weight = SimpleDispatchInfo::FixedNormal(10)]
-        pub fn add_collateral(origin, loan_id: LoanId, amount: T::Balance) -> DispatchResult {
-            ensure!(!Self::paused(), "module is paused");
+        pub fn cancel_application(origin, application_id: ApplicationId) -> DispatchResult {
             let who = ensure_signed(origin)?;
-            ensure!(<Loans<T>>::contains_key(loan_id), "loan doesn't exists");
-            let loan = Self::get_loan_by_id(loan_id);
-            ensure!(who == loan.who, "adding collateral to other's loan is not allowed");
+            let application = Self::loan_application(application_id)
+                .ok_or(Error::<T, I>::UnknownApplication)?;
+            ensure!(
+                application.who == who || application.guarantees.iter().any(|g| g.guarantor == who),
+                Error::<T, I>::UnknownApplication
+            );
+            let mut application = application;
+            for i in 0..application.guarantees.len() {
+                if !application.guarantees[i].approved {
+                    continue;
+                }
+                if let Err(e) = Self::release_pledge(&application.guarantees[i]) {
+                    // what was given back so far is no longer approved, the rest can be retried
+                    <LoanApplications<T, I>>::insert(application_id, application);
+                    return Err(e);
+                }
+                application.guarantees[i].approved = false;
+            }
+            <LoanApplications<T, I>>::remove(application_id);
+            Self::deposit_event(RawEvent::ApplicationCancelled(application_id, who));
+            Ok(())
+        }
+
+        /// retry giving back the pledges of a closed loan which could not be given back when it closed
+        #[weight = SimpleDispatchInfo::FixedNormal(10)]
+        pub fn release_pledges(origin, loan_id: LoanId) -> DispatchResult {
+            ensure_signed(origin)?;
+            ensure!(!<Loans<T, I>>::contains_key(loan_id), "loan is still open");
+            ensure!(
+                !Self::loan_guarantees(loan_id).is_empty(),
+                Error::<T, I>::NothingToRelease
+            );
+            Self::release_guarantees(loan_id);
+            Ok(())
+        }
+
+        /// offer "amount" of the loan asset at a fixed annual "rate" for loans of at most "term"
+        #[weight = SimpleDispatchInfo::FixedNormal(10)]
+        pub fn post_offer(origin, amount: BalanceOf<T, I>, rate: u64, term: T::Moment) -> DispatchResult {
+            Self::ensure_operation(Operation::Borrow)?;
+            let lender = ensure_signed(origin)?;
+            Self::post_lend_offer(lender, amount, rate, term)
+        }
+
+        /// ask for "loan_amount" against "collateral_amount" at an annual rate of at most "max_rate" for "term"
+        #[weight = SimpleDispatchInfo::FixedNormal(10)]
+        pub fn post_bid(
+            origin,
+            collateral_amount: BalanceOf<T, I>,
+            loan_amount: BalanceOf<T, I>,
+            max_rate: u64,
+            term: T::Moment
+        ) -> DispatchResult {
+            Self::ensure_operation(Operation::Borrow)?;
+            let borrower = ensure_signed(origin)?;
+            Self::post_borrow_bid(borrower, collateral_amount, loan_amount, max_rate, term)
+        }
+
+        /// anyone can match an offer with a bid, the bid is filled as a whole at the offer's rate
+        #[weight = SimpleDispatchInfo::FixedNormal(10)]
+        pub fn match_orders(origin, offer_id: OrderId, bid_id: OrderId) -> DispatchResult {
+            Self::ensure_operation(Operation::Borrow)?;
+            ensure_signed(origin)?;
+            Self::match_offer_with_bid(offer_id, bid_id)
+        }
+
+        /// take back what is left of an offer or a bid
+        #[weight = SimpleDispatchInfo::FixedNormal(10)]
+        pub fn cancel_order(origin, order_id: OrderId) -> DispatchResult {
+            let who = ensure_signed(origin)?;
+            Self::cancel_book_order(who, order_id)
+        }
+
+        /// a user repay a loan he has made before, by providing the loan id and he should make sure there is enough related assets in his account
+        #[weight = SimpleDispatchInfo::FixedNormal(10)]
+        pub fn repay_loan(origin, loan_id: LoanId) -> LoanResult {
+            Self::ensure_operation(Operation::Repay)?;
+            let who = ensure_signed(origin)?;
+            Self::repay_for_loan(who.clone(), loan_id)
+        }
+
+        /// repay someone else's loan with the caller's funds, the collateral still goes back to the loan owner
+        #[weight = SimpleDispatchInfo::FixedNormal(10)]
+        pub fn repay_for(origin, loan_id: LoanId, amount: BalanceOf<T, I>) -> DispatchResult {
+            Self::ensure_operation(Operation::Repay)?;
+            let payer = ensure_signed(origin)?;
+            Self::repay_loan_for(payer, loan_id, amount)
+        }
+
+        /// pay off accrued interest of a loan with "amount" of "asset_id" converted at the oracle price,
+        /// the principal stays as it is and anything above the accrued interest is not taken
+        #[weight = SimpleDispatchInfo::FixedNormal(10)]
+        pub fn pay_interest(origin, loan_id: LoanId, asset_id: AssetIdOf<T, I>, amount: BalanceOf<T, I>) -> DispatchResult {
+            Self::ensure_operation(Operation::Repay)?;
+            let payer = ensure_signed(origin)?;
+            Self::pay_loan_interest(payer, loan_id, asset_id, amount)
+        }
+
+        /// when a liquidating loan has been handled well, platform mananger should call "mark_liquidated" to update the chain
+        /// loan id is the loan been handled and auction_balance is what the liquidation got by selling the collateral asset
+        /// auction_balance will be first used to make up the loan, then what so ever left will be returned to the loan's owner account
+        #[weight = SimpleDispatchInfo::FixedNormal(10)]
+        pub fn mark_liquidated(origin, loan_id: LoanId, auction_balance: BalanceOf<T, I>) -> DispatchResult {
+            Self::ensure_operation(Operation::Liquidation)?;
+            let liquidation_account = ensure_signed(origin)?;
+            ensure!(liquidation_account == Self::liquidation_account(), "liquidation account only");
+            ensure!(<Loans<T, I>>::contains_key(loan_id), "loan doesn't exists");
+
+            Self::mark_loan_liquidated(&Self::get_loan_by_id(loan_id), liquidation_account, auction_balance)
+        }
+
+        /// close a liquidating loan whose auction brought in less than its debt, the liquidation account
+        /// hands over "auction_balance" and the savers, or the lender of a fixed-rate loan, take the rest
+        #[weight = SimpleDispatchInfo::FixedNormal(10)]
+        pub fn write_off_loan(origin, loan_id: LoanId, auction_balance: BalanceOf<T, I>) -> DispatchResult {
+            ensure_root(origin)?;
+            ensure!(<Loans<T, I>>::contains_key(loan_id), "loan doesn't exists");
+
+            Self::write_off_shortfall(&Self::get_loan_by_id(loan_id), Self::liquidation_account(), auction_balance)
+        }
+
+        #[weight = SimpleDispatchInfo::FixedNormal(0)]
+        pub fn set_offchain_liquidation(origin, enabled: bool) -> LoanResult {
+            ensure_root(origin)?;
+            <OffchainLiquidation<I>>::put(enabled);
+            Self::deposit_event(RawEvent::ParameterChanged(PoolParameter::OffchainLiquidation(enabled)));
+            Ok(())
+        }
+
+        /// anyone can push loans which crossed the liquidation threshold into liquidating,
+        /// every loan is judged again against the current price, healthy ones are skipped
+        #[weight = SimpleDispatchInfo::FixedNormal(1_000_000)]
+        pub fn liquidate(origin, loan_ids: Vec<LoanId>) -> DispatchResult {
+            Self::ensure_operation(Operation::Liquidation)?;
+            ensure_signed(origin)?;
+            Self::liquidate_loans(loan_ids)
+        }
+
+        #[weight = SimpleDispatchInfo::FixedNormal(1_000_000)]
+        pub fn liquidate_unsigned(origin, _block_number: T::BlockNumber, loan_ids: Vec<LoanId>) -> DispatchResult {
+            Self::ensure_operation(Operation::Liquidation)?;
+            ensure_none(origin)?;
+            Self::liquidate_loans(loan_ids)
+        }
+
+        fn offchain_worker(block_number: T::BlockNumber) {
+            use offchain::SubmitUnsignedTransaction;
+
+            if Self::operation_paused(Operation::Liquidation)
+                || !Self::offchain_liquidation()
+                || Self::unconfirmed_price().is_some()
+                || Self::settlement_price().is_some()
+            {
+                return;
+            }
+
+            let batch = cmp::max(T::MaxLiquidationBatch::get(), 1) as usize;
+            for loan_ids in Self::liquidatable_loans().chunks(batch) {
+                let call = Call::liquidate_unsigned(block_number, loan_ids.to_vec());
+                if let Err(e) = T::SubmitUnsignedTransaction::submit_unsigned(call) {
+                    debug::error!("Fail to submit unsigned transaction for liquidation: {:?}", e);
+                }
+            }
+        }
+
+        /// when user got a warning of high-risk LTV, user can lower the LTV by add more collateral
+        #[weight = SimpleDispatchInfo::FixedNormal(10)]
+        pub fn add_collateral(origin, loan_id: LoanId, amount: BalanceOf<T, I>) -> DispatchResult {
+            Self::ensure_operation(Operation::AddCollateral)?;
+            let who = ensure_signed(origin)?;
+            ensure!(<Loans<T, I>>::contains_key(loan_id), "loan doesn't exists");
+            let loan = Self::get_loan_by_id(loan_id);
+            ensure!(who == loan.who, "adding collateral to other's loan is not allowed");
 
             Self::add_loan_collateral(&loan, loan.who.clone(), amount)
         }
 
-        /// as long as the LTV of this loan is below the "GlobalLTVLimit", user can keep drawing TBD from this loan
+        /// as long as the LTV of this loan is below the LTV limit of its owner, user can keep drawing TBD from this loan
         #[weight = SimpleDispatchInfo::FixedNormal(10)]
-        pub fn draw(origin, loan_id: LoanId, amount: T::Balance) -> DispatchResult {
-            ensure!(!Self::paused(), "module is paused");
+        pub fn draw(origin, loan_id: LoanId, amount: BalanceOf<T, I>) -> DispatchResult {
+            Self::ensure_operation(Operation::Draw)?;
             let who = ensure_signed(origin)?;
+            // "draw_from_loan" also charges interest, only drawing by the borrower needs a fresh price
+            Self::fresh_collateral_price()?;
             Self::draw_from_loan(who, loan_id, amount)
         }
+
+        /// combine several loans of the same account into the first loan in "loan_ids"
+        /// collateral and debt are summed up and the merged loan must stay below the LTV limit of its owner
+        #[weight = MergeWeight]
+        pub fn merge_loans(origin, loan_ids: Vec<LoanId>) -> DispatchResult {
+            Self::ensure_operation(Operation::Borrow)?;
+            let who = ensure_signed(origin)?;
+            Self::merge_account_loans(who, loan_ids)
+        }
+
+        /// move a loan onto new terms by giving the collateral and loan amounts it should end up with,
+        /// the difference is settled between the user, the pawnshop and the collection account
+        #[weight = SimpleDispatchInfo::FixedNormal(10)]
+        pub fn refinance(origin, loan_id: LoanId, collateral_amount: BalanceOf<T, I>, loan_amount: BalanceOf<T, I>) -> DispatchResult {
+            Self::ensure_operation(Operation::Borrow)?;
+            let who = ensure_signed(origin)?;
+            Self::refinance_loan(who, loan_id, collateral_amount, loan_amount)
+        }
+
+        /// collect the profit asset earned by saving and borrowing
+        #[weight = SimpleDispatchInfo::FixedNormal(10)]
+        pub fn claim_rewards(origin) -> DispatchResult {
+            let who = ensure_signed(origin)?;
+            Self::claim_account_rewards(who)
+        }
+
+        /// switch between loan-level and account-level margin,
+        /// in account-level margin the collateral and debt of all loans of the account are judged as one position
+        #[weight = SimpleDispatchInfo::FixedNormal(10)]
+        pub fn set_margin_mode(origin, account_level: bool) -> DispatchResult {
+            Self::ensure_operation(Operation::Borrow)?;
+            let who = ensure_signed(origin)?;
+            Self::set_account_margin_mode(who, account_level)
+        }
     }
 }
 
-impl<T: Trait> Module<T> {
+impl<T: Trait<I>, I: Instance> Module<T, I> {
     pub fn create_staking(
         who: T::AccountId,
-        asset_id: T::AssetId,
-        balance: T::Balance,
+        asset_id: AssetIdOf<T, I>,
+        balance: BalanceOf<T, I>,
     ) -> DispatchResult {
+        ensure!(Self::settlement_price().is_none(), Error::<T, I>::InSettlement);
         ensure!(!balance.is_zero(), "saving can't be zero");
 
         let market_dtoken_amount = Self::market_dtoken();
         let total_dtoken_amount = Self::total_dtoken();
         let collection_account_id = Self::collection_account_id();
 
-        let mut user_dtoken = T::Balance::from(0);
+        let mut user_dtoken = BalanceOf::<T, I>::from(0);
 
-        <generic_asset::Module<T>>::make_transfer_with_event(
+        T::Currency::transfer(
             &asset_id,
             &who,
             &collection_account_id,
             balance,
         )?;
 
-        let ltv_prec_in_balance = T::Balance::from(LTV_PREC);
+        let ltv_prec_in_balance = BalanceOf::<T, I>::from(LTV_PREC);
         if total_dtoken_amount.is_zero() {
             user_dtoken = balance;
         } else {
@@ -464,112 +1466,290 @@ impl<T: Trait> Module<T> {
                 / total_dtoken_amount;
         }
 
-        if <UserDtoken<T>>::contains_key(who.clone()) {
-            <UserDtoken<T>>::mutate(who.clone(), |v| {
+        if <UserDtoken<T, I>>::contains_key(who.clone()) {
+            <UserDtoken<T, I>>::mutate(who.clone(), |v| {
                 *v = v.checked_add(&user_dtoken).expect("overflow");
             });
         } else {
-            <UserDtoken<T>>::insert(&who, user_dtoken);
+            <UserDtoken<T, I>>::insert(&who, user_dtoken);
         }
 
         let market_dtoken = market_dtoken_amount.checked_add(&user_dtoken).unwrap();
         let total_dtoken = market_dtoken_amount.checked_add(&balance).unwrap();
 
-        <MarketDtoken<T>>::put(market_dtoken);
-        <TotalDtoken<T>>::put(total_dtoken);
+        <MarketDtoken<T, I>>::put(market_dtoken);
+        <TotalDtoken<T, I>>::put(total_dtoken);
+        <SavingPrincipal<T, I>>::mutate(&who, |v| *v = v.saturating_add(balance));
 
+        Self::update_rewards(&who);
+        Self::deposit_event(RawEvent::Staked(who, balance, user_dtoken));
         Ok(())
     }
 
+    /// redeem "amount" of the collection asset by burning the shares (dtoken) it is worth
     fn make_redeem(
         who: &T::AccountId,
-        collection_asset_id: &T::AssetId,
+        collection_asset_id: &AssetIdOf<T, I>,
         collection_account_id: &T::AccountId,
-        amount: T::Balance,
+        amount: BalanceOf<T, I>,
     ) -> DispatchResult {
+        ensure!(!amount.is_zero(), "redeem can't be zero");
+        let shares = Self::amount_to_shares(amount)?;
+        ensure!(Self::user_dtoken(who) >= shares, "redeem too much assets!");
+        Self::burn_shares(who, collection_asset_id, collection_account_id, shares, amount)?;
+        Ok(())
+    }
+
+    /// the amount of collection asset that "shares" of dtoken is worth, rounded down
+    pub fn shares_to_amount(shares: BalanceOf<T, I>) -> Result<BalanceOf<T, I>, DispatchError> {
         let market_dtoken_amount = Self::market_dtoken();
+        if market_dtoken_amount.is_zero() {
+            return Ok(BalanceOf::<T, I>::zero());
+        }
+        Ok(shares
+            .checked_mul(&Self::total_dtoken())
+            .ok_or(Error::<T, I>::Overflow)?
+            / market_dtoken_amount)
+    }
+
+    /// the shares of dtoken needed to redeem "amount" of collection asset, rounded up
+    pub fn amount_to_shares(amount: BalanceOf<T, I>) -> Result<BalanceOf<T, I>, DispatchError> {
         let total_dtoken_amount = Self::total_dtoken();
+        ensure!(!total_dtoken_amount.is_zero(), "total dtoken is short");
+        let scaled = amount
+            .checked_mul(&Self::market_dtoken())
+            .ok_or(Error::<T, I>::Overflow)?;
+        let shares = scaled / total_dtoken_amount;
+        if (shares * total_dtoken_amount) < scaled {
+            Ok(shares + One::one())
+        } else {
+            Ok(shares)
+        }
+    }
+
+    /// burn exactly "shares" of the user's dtoken and pay out "amount",
+    /// all checks happen before anything is written so a failure leaves no trace
+    fn burn_shares(
+        who: &T::AccountId,
+        collection_asset_id: &AssetIdOf<T, I>,
+        collection_account_id: &T::AccountId,
+        shares: BalanceOf<T, I>,
+        amount: BalanceOf<T, I>,
+    ) -> DispatchResult {
+        ensure!(!shares.is_zero(), "redeem can't be zero");
+        let user_dtoken_amount = Self::user_dtoken(who);
+        ensure!(user_dtoken_amount >= shares, "not enough dtoken");
+        ensure!(Self::market_dtoken() >= shares, "market dtoken is short");
+        ensure!(Self::total_dtoken() >= amount, "total dtoken is short");
+        ensure!(
+            T::Currency::free_balance(collection_asset_id, collection_account_id)
+                >= amount,
+            "saving balance is short"
+        );
+
+        T::Currency::transfer(
+            collection_asset_id,
+            collection_account_id,
+            who,
+            amount,
+        )?;
+
+        // the burnt shares take their pro-rata part of the principal, whatever is above it is interest
+        let principal = Self::saving_principal(who);
+        let principal_redeemed = if user_dtoken_amount == shares {
+            principal
+        } else {
+            (principal.checked_mul(&shares).ok_or(Error::<T, I>::Overflow)? / user_dtoken_amount)
+                .min(principal)
+        };
 
-        let user_dtoken_amount = Self::user_dtoken(&who);
-        let user_will_get = user_dtoken_amount / (market_dtoken_amount / total_dtoken_amount);
+        if user_dtoken_amount == shares {
+            <UserDtoken<T, I>>::remove(who);
+        } else {
+            <UserDtoken<T, I>>::insert(who, user_dtoken_amount - shares);
+        }
+        <SavingPrincipal<T, I>>::insert(who, principal - principal_redeemed);
+        <InterestEarnedRealised<T, I>>::mutate(who, |v| {
+            *v = v.saturating_add(amount.saturating_sub(principal_redeemed))
+        });
+        <MarketDtoken<T, I>>::mutate(|v| *v -= shares);
+        <TotalDtoken<T, I>>::mutate(|v| *v -= amount);
 
-        ensure!(user_will_get >= amount, "redeem too much assets!");
-        Self::make_redeem_all(&who).unwrap_or_default();
-        Self::create_staking(who.clone(), *collection_asset_id, user_will_get - amount)
-            .unwrap_or_default();
+        Self::update_rewards(who);
+        Self::deposit_event(RawEvent::Redeemed(who.clone(), amount, shares));
         Ok(())
     }
 
-    fn make_redeem_all(who: &T::AccountId) -> DispatchResult {
-        let market_dtoken_amount = Self::market_dtoken();
-        let total_dtoken_amount = Self::total_dtoken();
+    /// burn exactly "shares" of dtoken at the current exchange rate
+    pub fn make_redeem_shares(who: &T::AccountId, shares: BalanceOf<T, I>) -> DispatchResult {
+        ensure!(Self::settlement_price().is_none(), Error::<T, I>::InSettlement);
         let collection_asset_id = Self::collection_asset_id();
         let collection_account_id = Self::collection_account_id();
+        let amount = Self::shares_to_amount(shares)?;
+        ensure!(!amount.is_zero(), "redeem can't be zero");
 
-        let user_dtoken_amount = Self::user_dtoken(&who);
-
-        let user_will_get = user_dtoken_amount / (market_dtoken_amount / total_dtoken_amount);
-
+        // liquidity promised to the redeem queue is not available here
         ensure!(
-            <generic_asset::Module<T>>::free_balance(&collection_asset_id, &collection_account_id)
-                >= user_will_get,
-            "saving balance is short"
+            T::Currency::free_balance(&collection_asset_id, &collection_account_id)
+                >= amount + Self::queued_redeem_total(),
+            "Not enough to redeem"
         );
 
+        Self::burn_shares(
+            who,
+            &collection_asset_id,
+            &collection_account_id,
+            shares,
+            amount,
+        )
+    }
+
+    fn request_redeem(who: T::AccountId, amount: BalanceOf<T, I>) -> DispatchResult {
+        ensure!(Self::settlement_price().is_none(), Error::<T, I>::InSettlement);
+        let collection_asset_id = Self::collection_asset_id();
+        let collection_account_id = Self::collection_account_id();
+        let length = Self::redeem_queue_length();
+
+        // nobody may jump the queue
+        if length.is_zero()
+            && T::Currency::free_balance(&collection_asset_id, &collection_account_id)
+                >= amount
+        {
+            return Self::make_redeem(&who, &collection_asset_id, &collection_account_id, amount);
+        }
+
+        ensure!(!amount.is_zero(), "redeem can't be zero");
+        ensure!(amount >= Self::min_redeem_amount(), Error::<T, I>::RedeemTooSmall);
+        ensure!(length < Self::max_redeem_queue_length(), Error::<T, I>::RedeemQueueFull);
+        let mut requests = Self::account_redeem_requests(&who);
         ensure!(
-            Self::total_dtoken() >= user_dtoken_amount,
-            "total dtoken is short"
-        );
-        ensure!(
-            Self::market_dtoken() >= user_dtoken_amount,
-            "market dtoken is short"
+            (requests.len() as u32) < Self::max_redeem_requests_per_account(),
+            Error::<T, I>::TooManyRedeemRequests
         );
 
-        let total_dtoken = Self::total_dtoken() - user_will_get;
-        let market_dtoken = Self::market_dtoken() - user_dtoken_amount;
+        let user_will_get = Self::shares_to_amount(Self::user_dtoken(&who))?;
+        let queued = requests
+            .iter()
+            .filter_map(|id| Self::redeem_request(id))
+            .fold(BalanceOf::<T, I>::zero(), |acc, r| acc + r.amount);
+        ensure!(queued + amount <= user_will_get, "redeem too much assets!");
 
-        <MarketDtoken<T>>::put(market_dtoken);
-        <TotalDtoken<T>>::put(total_dtoken);
+        let id = <NextRedeemRequestId<I>>::mutate(|v| {
+            let org = *v;
+            *v += 1;
+            org
+        });
+        <RedeemQueue<T, I>>::insert(
+            id,
+            RedeemRequest {
+                id,
+                who: who.clone(),
+                amount,
+            },
+        );
+        requests.push(id);
+        <AccountRedeemRequests<T, I>>::insert(&who, requests);
+        <RedeemQueueLength<I>>::put(length + 1);
+        <QueuedRedeemTotal<T, I>>::mutate(|v| *v = v.saturating_add(amount));
 
-        <generic_asset::Module<T>>::make_transfer_with_event(
-            &collection_asset_id,
-            &collection_account_id,
-            &who,
-            user_will_get,
-        )?;
+        Self::deposit_event(RawEvent::RedeemQueued(id, who, amount, length));
         Ok(())
     }
 
+    /// take a filled, dropped or cancelled request out of the queue
+    fn dequeue_redeem(request: &RedeemRequest<T::AccountId, BalanceOf<T, I>>) {
+        <RedeemQueue<T, I>>::remove(request.id);
+        <AccountRedeemRequests<T, I>>::mutate(&request.who, |v| v.retain(|id| *id != request.id));
+        <RedeemQueueLength<I>>::mutate(|v| *v = v.saturating_sub(1));
+        <QueuedRedeemTotal<T, I>>::mutate(|v| *v = v.saturating_sub(request.amount));
+    }
+
+    /// fill queued redemptions in order until the head one can't be covered,
+    /// at most "RedeemBatch" entries are looked at, returns the weight used
+    fn process_redeem_queue() -> Weight {
+        // in settlement savers are paid by "settlement_redeem" only
+        if Self::redeem_queue_length().is_zero() || Self::settlement_price().is_some() {
+            return 0;
+        }
+
+        let collection_asset_id = Self::collection_asset_id();
+        let collection_account_id = Self::collection_account_id();
+        let next_id = Self::next_redeem_request_id();
+        let batch = Self::redeem_batch();
+        let mut head = Self::redeem_queue_head();
+        let mut looked_at: u32 = 0;
+
+        while head < next_id && looked_at < batch {
+            looked_at += 1;
+            let request = match Self::redeem_request(head) {
+                Some(request) => request,
+                // cancelled
+                None => {
+                    head += 1;
+                    continue;
+                }
+            };
+            if T::Currency::free_balance(&collection_asset_id, &collection_account_id)
+                < request.amount
+            {
+                break;
+            }
+            match Self::make_redeem(
+                &request.who,
+                &collection_asset_id,
+                &collection_account_id,
+                request.amount,
+            ) {
+                Ok(_) => Self::deposit_event(RawEvent::RedeemFilled(
+                    request.id,
+                    request.who.clone(),
+                    request.amount,
+                )),
+                Err(_) => {
+                    Self::deposit_event(RawEvent::RedeemDropped(request.id, request.who.clone()))
+                }
+            }
+            Self::dequeue_redeem(&request);
+            head += 1;
+        }
+
+        <RedeemQueueHead<I>>::put(head);
+        REDEEM_FILL_WEIGHT.saturating_mul(looked_at as Weight)
+    }
+
+    fn make_redeem_all(who: &T::AccountId) -> DispatchResult {
+        ensure!(Self::settlement_price().is_none(), Error::<T, I>::InSettlement);
+        Self::make_redeem_shares(who, Self::user_dtoken(who))
+    }
+
     fn apply_for_loan(
         who: T::AccountId,
-        collateral_amount: T::Balance,
-        loan_amount: T::Balance,
+        collateral_amount: BalanceOf<T, I>,
+        loan_amount: BalanceOf<T, I>,
     ) -> DispatchResult {
+        ensure!(Self::settlement_price().is_none(), Error::<T, I>::InSettlement);
         let collection_asset_id = Self::collection_asset_id();
         let collection_account_id = Self::collection_account_id();
         ensure!(
-            <generic_asset::Module<T>>::free_balance(&collection_asset_id, &collection_account_id)
-                >= loan_amount,
+            T::Currency::free_balance(&collection_asset_id, &collection_account_id)
+                >= loan_amount + Self::queued_redeem_total(),
             "Not enough to loan"
         );
 
-        let collateral_asset_id = Self::collateral_asset_id();
-        let token = <generic_asset::Module<T>>::symbols(collateral_asset_id);
-        let current_price = <new_oracle::Module<T>>::current_price(&token);
-        let btc_price: u64 = TryInto::<u64>::try_into(current_price).unwrap_or(0);
+        let btc_price = Self::fresh_collateral_price()?;
 
         let loan_asset_id = Self::loan_asset_id();
         let collateral_asset_id = Self::collateral_asset_id();
 
-        let shop = <PawnShop<T>>::get();
-        let loan_cap = <LoanCap<T>>::get();
-        let total_loan = <TotalLoan<T>>::get();
+        let shop = Self::pawn_shop();
+        let loan_cap = <LoanCap<T, I>>::get();
+        let total_loan = <TotalLoan<T, I>>::get();
 
         if loan_cap.is_some() && total_loan >= loan_cap.unwrap() {
-            return Err(Error::<T>::ReachLoanCap)?;
+            return Err(Error::<T, I>::ReachLoanCap)?;
         }
 
-        match Self::get_collateral_loan(collateral_amount, loan_amount) {
+        match Self::get_collateral_loan(&who, collateral_amount, loan_amount) {
             Err(err) => Err(err),
             Ok(CollateralLoan {
                 collateral_amount: actual_collateral_amount,
@@ -580,19 +1760,22 @@ impl<T: Trait> Module<T> {
                     "not reach min collateral amount"
                 );
 
-                // transfer collateral to pawnshop
-                <generic_asset::Module<T>>::make_transfer_with_event(
-                    &collateral_asset_id,
-                    &who,
-                    &shop,
-                    actual_collateral_amount,
-                )?;
-
                 let loan_id = Self::get_next_loan_id();
+                if Self::reserve_collateral() {
+                    Self::relock_collateral(loan_id, &who, actual_collateral_amount)?;
+                } else {
+                    // transfer collateral to pawnshop
+                    T::Currency::transfer(
+                        &collateral_asset_id,
+                        &who,
+                        &shop,
+                        actual_collateral_amount,
+                    )?;
+                }
 
                 let collateral_balance_available = actual_collateral_amount
                     - loan_amount
-                        / <T::Balance as TryFrom<u128>>::try_from(btc_price as u128)
+                        / <BalanceOf<T, I> as TryFrom<u128>>::try_from(btc_price as u128)
                             .ok()
                             .unwrap();
 
@@ -605,19 +1788,23 @@ impl<T: Trait> Module<T> {
                     status: Default::default(),
                 };
 
-                <generic_asset::Module<T>>::make_transfer_with_event(
+                T::Currency::transfer(
                     &collection_asset_id,
                     &collection_account_id,
                     &who,
                     loan_amount,
                 )?;
 
-                <Loans<T>>::insert(loan_id, loan.clone());
-                <LoansByAccount<T>>::mutate(&who, |v| {
+                <Loans<T, I>>::insert(loan_id, loan.clone());
+                <LoanOpenedAt<T, I>>::insert(loan_id, <timestamp::Module<T>>::get());
+                <LoanAccruedAt<T, I>>::insert(loan_id, <timestamp::Module<T>>::get());
+                Self::index_loan(loan_id);
+                <LoansByAccount<T, I>>::mutate(&who, |v| {
                     v.push(loan_id);
                 });
-                <TotalLoan<T>>::mutate(|v| *v += actual_loan_amount);
-                <TotalCollateral<T>>::mutate(|v| *v += actual_collateral_amount);
+                <TotalLoan<T, I>>::mutate(|v| *v += actual_loan_amount);
+                <TotalCollateral<T, I>>::mutate(|v| *v += actual_collateral_amount);
+                Self::update_loan_rewards(loan_id, &who);
 
                 Self::deposit_event(RawEvent::LoanCreated(loan));
                 Ok(())
@@ -625,279 +1812,1499 @@ impl<T: Trait> Module<T> {
         }
     }
 
-    pub fn get_collateral_loan(
-        collateral_amount: T::Balance,
-        loan_amount: T::Balance,
-    ) -> Result<CollateralLoan<T::Balance>, DispatchError> {
-        if collateral_amount.is_zero() && loan_amount.is_zero() {
-            return Err(Error::<T>::InvalidCollateralLoanAmounts)?;
-        }
-        let collateral_asset_id = Self::collateral_asset_id();
-
-        // get current btc price
-        let token = <generic_asset::Module<T>>::symbols(collateral_asset_id);
-        let current_price = <new_oracle::Module<T>>::current_price(&token);
-        let btc_price: u64 = TryInto::<u64>::try_into(current_price).unwrap_or(0);
-        let btc_price_in_balance = <T::Balance as TryFrom<u128>>::try_from(btc_price as u128)
-            .ok()
-            .unwrap();
-
-        let price_prec_in_balance = T::Balance::from(PRICE_PREC);
-        let ltv_prec_in_balance = T::Balance::from(LTV_PREC);
+    fn post_lend_offer(
+        lender: T::AccountId,
+        amount: BalanceOf<T, I>,
+        rate: u64,
+        term: T::Moment,
+    ) -> DispatchResult {
+        ensure!(Self::settlement_price().is_none(), Error::<T, I>::InSettlement);
+        ensure!(!amount.is_zero() && !term.is_zero(), Error::<T, I>::InvalidOrder);
+        ensure!(rate <= MAX_FIXED_RATE, Error::<T, I>::InvalidOrder);
+
+        T::Currency::transfer(&Self::loan_asset_id(), &lender, &Self::offers_account(), amount)?;
+
+        let id = Self::get_next_order_id();
+        <LendOffers<T, I>>::insert(
+            id,
+            LendOffer {
+                id,
+                lender: lender.clone(),
+                amount,
+                rate,
+                term,
+            },
+        );
+        Self::deposit_event(RawEvent::OfferPosted(id, lender, amount, rate, term));
+        Ok(())
+    }
 
-        let ltv = GlobalLTVLimit::get();
-        let ltv_in_balance = <T::Balance as TryFrom<u64>>::try_from(ltv).ok().unwrap();
+    fn post_borrow_bid(
+        borrower: T::AccountId,
+        collateral_amount: BalanceOf<T, I>,
+        loan_amount: BalanceOf<T, I>,
+        max_rate: u64,
+        term: T::Moment,
+    ) -> DispatchResult {
+        ensure!(Self::settlement_price().is_none(), Error::<T, I>::InSettlement);
+        ensure!(
+            !loan_amount.is_zero() && !term.is_zero() && max_rate <= MAX_FIXED_RATE,
+            Error::<T, I>::InvalidOrder
+        );
+        ensure!(
+            collateral_amount >= Self::minimum_collateral(),
+            "not reach min collateral amount"
+        );
 
-        if collateral_amount.is_zero() {
-            let must_collateral_amount = loan_amount * ltv_prec_in_balance * price_prec_in_balance
-                / (btc_price_in_balance * ltv_in_balance);
-            return Ok(CollateralLoan {
-                collateral_amount: must_collateral_amount,
-                loan_amount: loan_amount,
-            });
-        }
+        let collateral_asset_id = Self::collateral_asset_id();
+        let lock = if Self::reserve_collateral() {
+            ensure!(
+                T::Currency::free_balance(&collateral_asset_id, &borrower) >= collateral_amount,
+                "not enough collateral asset in free balance"
+            );
+            let lock_id = T::Currency::reserve(&collateral_asset_id, &borrower, collateral_amount)?;
+            Some((lock_id, collateral_amount))
+        } else {
+            T::Currency::transfer(
+                &collateral_asset_id,
+                &borrower,
+                &Self::pawn_shop(),
+                collateral_amount,
+            )?;
+            None
+        };
 
-        if loan_amount.is_zero() {
-            let can_loan_amount = (collateral_amount * btc_price_in_balance * ltv_in_balance)
-                / (ltv_prec_in_balance * price_prec_in_balance);
-            return Ok(CollateralLoan {
-                collateral_amount: collateral_amount,
-                loan_amount: can_loan_amount,
-            });
+        let id = Self::get_next_order_id();
+        if let Some(lock) = lock {
+            <BidCollateralLocks<T, I>>::insert(id, lock);
         }
-
-        if (loan_amount * ltv_prec_in_balance) * price_prec_in_balance
-            / (collateral_amount * btc_price_in_balance)
-            >= ltv_in_balance
-        {
-            Err(Error::<T>::OverLTVLimit)?
-        } else {
-            Ok(CollateralLoan {
+        <BorrowBids<T, I>>::insert(
+            id,
+            BorrowBid {
+                id,
+                borrower: borrower.clone(),
                 collateral_amount,
                 loan_amount,
-            })
-        }
+                max_rate,
+                term,
+            },
+        );
+        Self::deposit_event(RawEvent::BidPosted(id, borrower, collateral_amount, loan_amount, max_rate, term));
+        Ok(())
     }
 
-    pub fn repay_for_loan(who: T::AccountId, loan_id: LoanId) -> DispatchResult {
-        let loan_asset_id = Self::loan_asset_id();
-        let collateral_asset_id = Self::collateral_asset_id();
-        let collection_account_id = Self::collection_account_id();
-        let pawn_shop = Self::pawn_shop();
-
-        ensure!(<Loans<T>>::contains_key(loan_id), "invalid loan id");
-        let loan = <Loans<T>>::get(loan_id);
-        ensure!(loan.who == who, "not owner of the loan");
+    fn cancel_book_order(who: T::AccountId, order_id: OrderId) -> DispatchResult {
+        if let Some(offer) = Self::lend_offer(order_id) {
+            ensure!(offer.lender == who, Error::<T, I>::UnknownOrder);
+            T::Currency::transfer(&Self::loan_asset_id(), &Self::offers_account(), &who, offer.amount)?;
+            <LendOffers<T, I>>::remove(order_id);
+        } else {
+            let bid = Self::borrow_bid(order_id).ok_or(Error::<T, I>::UnknownOrder)?;
+            ensure!(bid.borrower == who, Error::<T, I>::UnknownOrder);
+            match Self::bid_collateral_lock(order_id) {
+                Some((lock_id, locked)) => {
+                    T::Currency::unreserve(&Self::collateral_asset_id(), &who, locked, Some(lock_id))?;
+                    <BidCollateralLocks<T, I>>::remove(order_id);
+                }
+                None => T::Currency::transfer(
+                    &Self::collateral_asset_id(),
+                    &Self::pawn_shop(),
+                    &who,
+                    bid.collateral_amount,
+                )?,
+            }
+            <BorrowBids<T, I>>::remove(order_id);
+        }
+        Self::deposit_event(RawEvent::OrderCancelled(order_id, who));
+        Ok(())
+    }
 
+    fn match_offer_with_bid(offer_id: OrderId, bid_id: OrderId) -> DispatchResult {
+        ensure!(Self::settlement_price().is_none(), Error::<T, I>::InSettlement);
+        let mut offer = Self::lend_offer(offer_id).ok_or(Error::<T, I>::UnknownOrder)?;
+        let bid = Self::borrow_bid(bid_id).ok_or(Error::<T, I>::UnknownOrder)?;
         ensure!(
-            <generic_asset::Module<T>>::free_balance(&loan_asset_id, &who)
-                >= loan.loan_balance_total,
-            "not enough asset to repay"
-        );
-        ensure!(
-            <generic_asset::Module<T>>::free_balance(&collateral_asset_id, &pawn_shop)
-                >= loan.collateral_balance_available,
-            "not enough collateral asset in shop"
-        );
-        ensure!(
-            !Self::check_loan_in_liquidation(&loan_id),
-            "loan is in liquidation"
+            offer.rate <= bid.max_rate
+                && bid.term <= offer.term
+                && bid.loan_amount <= offer.amount
+                && offer.lender != bid.borrower,
+            Error::<T, I>::OrdersNotMatching
         );
 
-        <LoansByAccount<T>>::mutate(&who, |v| {
-            *v = v
-                .clone()
-                .into_iter()
-                .filter(|ele| *ele != loan_id)
-                .collect::<Vec<LoanId>>();
-        });
+        let btc_price = Self::fresh_collateral_price()?;
+        let btc_price = <BalanceOf<T, I> as TryFrom<u128>>::try_from(btc_price as u128)
+            .map_err(|_| Error::<T, I>::Overflow)?;
+        // same LTV rules as a pool loan
+        Self::get_collateral_loan(&bid.borrower, bid.collateral_amount, bid.loan_amount)?;
+
+        T::Currency::transfer(
+            &Self::loan_asset_id(),
+            &Self::offers_account(),
+            &bid.borrower,
+            bid.loan_amount,
+        )?;
 
-        let revert_callback = || {
-            <Loans<T>>::insert(&loan.id, &loan);
-            <LoansByAccount<T>>::mutate(&who, |v| {
-                v.push(loan.id);
-            });
-            <TotalLoan<T>>::mutate(|v| *v += loan.loan_balance_total);
-            <TotalCollateral<T>>::mutate(|v| *v += loan.collateral_balance_available);
+        let loan_id = Self::get_next_loan_id();
+        let loan = Loan {
+            id: loan_id,
+            who: bid.borrower.clone(),
+            collateral_balance_original: bid.collateral_amount,
+            collateral_balance_available: bid.collateral_amount - bid.loan_amount / btc_price,
+            loan_balance_total: bid.loan_amount,
+            status: Default::default(),
         };
+        let now = <timestamp::Module<T>>::get();
 
-        <generic_asset::Module<T>>::make_transfer_with_event(
-            &loan_asset_id,
-            &who,
-            &collection_account_id,
-            loan.loan_balance_total,
-        )
-        .or_else(|err| -> DispatchResult {
-            revert_callback();
-            Err(err)
-        })?;
-        <generic_asset::Module<T>>::make_transfer_with_event(
-            &collateral_asset_id,
-            &pawn_shop,
-            &who,
-            loan.collateral_balance_original,
+        offer.amount -= bid.loan_amount;
+        if offer.amount.is_zero() {
+            <LendOffers<T, I>>::remove(offer_id);
+        } else {
+            <LendOffers<T, I>>::insert(offer_id, offer.clone());
+        }
+        <BorrowBids<T, I>>::remove(bid_id);
+        // a reserved bid keeps its reserve, now held for the loan
+        if let Some(lock) = <BidCollateralLocks<T, I>>::take(bid_id) {
+            <CollateralLocks<T, I>>::insert(loan_id, lock);
+        }
+
+        <FixedRateLoans<T, I>>::insert(
+            loan_id,
+            FixedRateTerms {
+                lender: offer.lender,
+                rate: offer.rate,
+                due: now + bid.term,
+            },
+        );
+        <Loans<T, I>>::insert(loan_id, loan.clone());
+        <LoanOpenedAt<T, I>>::insert(loan_id, now);
+        <LoanAccruedAt<T, I>>::insert(loan_id, now);
+        Self::index_loan(loan_id);
+        <LoansByAccount<T, I>>::mutate(&bid.borrower, |v| v.push(loan_id));
+        // the debt is owed to the lender, not to the pool, so "TotalLoan" is left alone
+        <TotalCollateral<T, I>>::mutate(|v| *v += bid.collateral_amount);
+
+        Self::deposit_event(RawEvent::OrdersMatched(offer_id, bid_id, loan_id));
+        Self::deposit_event(RawEvent::LoanCreated(loan));
+        Ok(())
+    }
+
+    /// where savings go and loans are paid from
+    pub fn collection_account_id() -> T::AccountId {
+        T::ModuleId::get().into_account()
+    }
+
+    /// holds the collateral of loans and of the bids of the order book
+    pub fn pawn_shop() -> T::AccountId {
+        T::ModuleId::get().into_sub_account(b"pawnshop")
+    }
+
+    /// holds what lenders put up in the offers of the order book until they are matched or cancelled
+    pub fn offers_account() -> T::AccountId {
+        T::ModuleId::get().into_sub_account(b"offers")
+    }
+
+    /// the profit asset is paid out of it
+    pub fn profit_pool() -> T::AccountId {
+        T::ModuleId::get().into_sub_account(b"profit")
+    }
+
+    /// move everything one of the accounts set in genesis or by root holds to its derived account, an
+    /// old account is only forgotten once all of its balances have moved so that a failed move can be
+    /// retried. One account is done per block, the weight is that of the balances it went through
+    fn migrate_next_account() -> Weight {
+        let step = Self::account_migration_step();
+        let mut asset_ids = vec![
+            Self::collection_asset_id(),
+            Self::loan_asset_id(),
+            Self::collateral_asset_id(),
+            Self::profit_asset_id(),
+        ];
+        asset_ids.extend(Self::interest_assets());
+        asset_ids.sort();
+        asset_ids.dedup();
+
+        let mut weight = SCAN_BASE_WEIGHT;
+        match step {
+            0 => {
+                if let Some(old) = <CollectionAccountId<T, I>>::get() {
+                    if Self::migrate_balances(&old, &Self::collection_account_id(), &asset_ids, &mut weight) {
+                        <CollectionAccountId<T, I>>::kill();
+                    }
+                }
+            }
+            1 => {
+                if let Some(old) = <PawnShop<T, I>>::get() {
+                    if Self::migrate_balances(&old, &Self::pawn_shop(), &asset_ids, &mut weight) {
+                        <PawnShop<T, I>>::kill();
+                    }
+                }
+            }
+            _ => {
+                if let Some(old) = <ProfitPool<T, I>>::get() {
+                    if Self::migrate_balances(&old, &Self::profit_pool(), &asset_ids, &mut weight) {
+                        <ProfitPool<T, I>>::kill();
+                    }
+                }
+            }
+        }
+        <AccountMigrationStep<I>>::put(step + 1);
+
+        // what failed waits for "retry_account_migration"
+        if step + 1 >= OLD_ACCOUNTS
+            && <CollectionAccountId<T, I>>::get().is_none()
+            && <PawnShop<T, I>>::get().is_none()
+            && <ProfitPool<T, I>>::get().is_none()
+        {
+            <AccountsMigrated<I>>::put(true);
+        }
+        weight
+    }
+
+    /// move the free and reserved balances of "asset_ids" from "old" to "new", reserves arrive free,
+    /// returns whether nothing was left behind
+    fn migrate_balances(
+        old: &T::AccountId,
+        new: &T::AccountId,
+        asset_ids: &[AssetIdOf<T, I>],
+        weight: &mut Weight,
+    ) -> bool {
+        if old == new {
+            return true;
+        }
+        let mut done = true;
+        for asset_id in asset_ids {
+            *weight = weight.saturating_add(MIGRATE_BALANCE_WEIGHT);
+            let balance = T::Currency::free_balance(asset_id, old);
+            if !balance.is_zero() {
+                match T::Currency::transfer(asset_id, old, new, balance) {
+                    Ok(_) => Self::deposit_event(RawEvent::BalanceMigrated(
+                        *asset_id,
+                        old.clone(),
+                        new.clone(),
+                        balance,
+                    )),
+                    Err(e) => {
+                        debug::error!("Fail to migrate pool balance: {:?}", e);
+                        done = false;
+                    }
+                }
+            }
+
+            let reserved = T::Currency::reserved_balance(asset_id, old);
+            if !reserved.is_zero() {
+                let remaining = T::Currency::repatriate_reserved(
+                    asset_id,
+                    old,
+                    new,
+                    reserved,
+                    BalanceStatus::Free,
+                    None,
+                );
+                let moved = reserved.saturating_sub(remaining);
+                if !moved.is_zero() {
+                    Self::deposit_event(RawEvent::BalanceMigrated(
+                        *asset_id,
+                        old.clone(),
+                        new.clone(),
+                        moved,
+                    ));
+                }
+                if !remaining.is_zero() {
+                    debug::error!("Fail to migrate reserved pool balance: {:?}", remaining);
+                    done = false;
+                }
+            }
+        }
+        done
+    }
+
+    fn collateral_reserved(loan_id: LoanId) -> bool {
+        <CollateralLocks<T, I>>::contains_key(loan_id)
+    }
+
+    /// keep "total" of the owner's collateral reserved for "loan_id" under a single lock
+    fn relock_collateral(loan_id: LoanId, owner: &T::AccountId, total: BalanceOf<T, I>) -> DispatchResult {
+        let collateral_asset_id = Self::collateral_asset_id();
+        let free = T::Currency::free_balance(&collateral_asset_id, owner);
+        match Self::collateral_lock(loan_id) {
+            Some((lock_id, locked)) => {
+                ensure!(
+                    free + locked >= total,
+                    "not enough collateral asset in free balance"
+                );
+                T::Currency::unreserve(&collateral_asset_id, owner, locked, Some(lock_id))?;
+            }
+            None => ensure!(free >= total, "not enough collateral asset in free balance"),
+        }
+
+        if total.is_zero() {
+            <CollateralLocks<T, I>>::remove(loan_id);
+        } else {
+            let lock_id = T::Currency::reserve(&collateral_asset_id, owner, total)?;
+            <CollateralLocks<T, I>>::insert(loan_id, (lock_id, total));
+        }
+        Ok(())
+    }
+
+    /// add "amount" paid by "from" to the collateral of a loan, wherever the loan keeps it
+    fn hold_collateral(
+        loan: &Loan<T::AccountId, BalanceOf<T, I>>,
+        from: &T::AccountId,
+        amount: BalanceOf<T, I>,
+    ) -> DispatchResult {
+        let collateral_asset_id = Self::collateral_asset_id();
+        match Self::collateral_lock(loan.id) {
+            Some((_, locked)) => {
+                if *from != loan.who {
+                    T::Currency::transfer(&collateral_asset_id, from, &loan.who, amount)?;
+                }
+                Self::relock_collateral(loan.id, &loan.who, locked + amount)
+            }
+            None => T::Currency::transfer(&collateral_asset_id, from, &Self::pawn_shop(), amount),
+        }
+    }
+
+    /// give "amount" of a loan's collateral back to its owner
+    fn release_collateral(loan: &Loan<T::AccountId, BalanceOf<T, I>>, amount: BalanceOf<T, I>) -> DispatchResult {
+        match Self::collateral_lock(loan.id) {
+            Some((_, locked)) => {
+                Self::relock_collateral(loan.id, &loan.who, locked.saturating_sub(amount))
+            }
+            None => T::Currency::transfer(
+                &Self::collateral_asset_id(),
+                &Self::pawn_shop(),
+                &loan.who,
+                amount,
+            ),
+        }
+    }
+
+    /// hand "amount" of a loan's collateral over to "to", a reserve is repatriated from the owner
+    fn seize_collateral(
+        loan: &Loan<T::AccountId, BalanceOf<T, I>>,
+        to: &T::AccountId,
+        amount: BalanceOf<T, I>,
+    ) -> DispatchResult {
+        let collateral_asset_id = Self::collateral_asset_id();
+        match Self::collateral_lock(loan.id) {
+            Some((lock_id, locked)) => {
+                ensure!(
+                    amount <= locked
+                        && T::Currency::reserved_balance(&collateral_asset_id, &loan.who) >= amount,
+                    "not enough collateral asset reserved"
+                );
+                let remaining = T::Currency::repatriate_reserved(
+                    &collateral_asset_id,
+                    &loan.who,
+                    to,
+                    amount,
+                    BalanceStatus::Free,
+                    Some(lock_id),
+                );
+                // the lock only shrinks by what was actually handed over
+                let moved = amount.saturating_sub(remaining);
+                if moved == locked {
+                    <CollateralLocks<T, I>>::remove(loan.id);
+                } else {
+                    <CollateralLocks<T, I>>::insert(loan.id, (lock_id, locked - moved));
+                }
+                ensure!(remaining.is_zero(), "not all collateral asset could be seized");
+                Ok(())
+            }
+            None => T::Currency::transfer(&collateral_asset_id, &Self::pawn_shop(), to, amount),
+        }
+    }
+
+    /// where repayments and liquidation proceeds of a loan go
+    fn loan_creditor(loan_id: LoanId) -> T::AccountId {
+        match Self::fixed_rate_loan(loan_id) {
+            Some(terms) => terms.lender,
+            None => Self::collection_account_id(),
+        }
+    }
+
+    /// only pool loans count in "TotalLoan"
+    fn is_pool_loan(loan_id: LoanId) -> bool {
+        !<FixedRateLoans<T, I>>::contains_key(loan_id)
+    }
+
+    fn loan_overdue(loan_id: LoanId) -> bool {
+        match Self::fixed_rate_loan(loan_id) {
+            Some(terms) => <timestamp::Module<T>>::get() >= terms.due,
+            None => false,
+        }
+    }
+
+    fn get_next_order_id() -> OrderId {
+        <NextOrderId<I>>::mutate(|v| {
+            let org = *v;
+            *v += 1;
+            org
+        })
+    }
+
+    fn apply_for_guaranteed_loan(
+        who: T::AccountId,
+        collateral_amount: BalanceOf<T, I>,
+        loan_amount: BalanceOf<T, I>,
+        guarantees: Vec<(T::AccountId, Pledge<BalanceOf<T, I>>)>,
+    ) -> DispatchResult {
+        ensure!(Self::settlement_price().is_none(), Error::<T, I>::InSettlement);
+        ensure!(!loan_amount.is_zero(), Error::<T, I>::InvalidCollateralLoanAmounts);
+        ensure!(
+            !guarantees.is_empty() && guarantees.len() <= MAX_GUARANTORS,
+            Error::<T, I>::TooManyGuarantors
+        );
+        for (i, (guarantor, pledge)) in guarantees.iter().enumerate() {
+            let amount = match pledge {
+                Pledge::Collateral(amount) | Pledge::Shares(amount) => *amount,
+            };
+            ensure!(
+                *guarantor != who
+                    && !amount.is_zero()
+                    && !guarantees[..i].iter().any(|(g, _)| g == guarantor),
+                Error::<T, I>::InvalidGuarantee
+            );
+        }
+
+        let id = <NextApplicationId<I>>::mutate(|v| {
+            let org = *v;
+            *v += 1;
+            org
+        });
+        let application = LoanApplication {
+            id,
+            who: who.clone(),
+            collateral_amount,
+            loan_amount,
+            guarantees: guarantees
+                .into_iter()
+                .map(|(guarantor, pledge)| Guarantee {
+                    guarantor,
+                    pledge,
+                    approved: false,
+                    principal: Zero::zero(),
+                })
+                .collect(),
+        };
+        <LoanApplications<T, I>>::insert(id, application);
+        Self::deposit_event(RawEvent::LoanApplied(id, who));
+        Ok(())
+    }
+
+    fn approve_loan_guarantee(guarantor: T::AccountId, application_id: ApplicationId) -> DispatchResult {
+        ensure!(Self::settlement_price().is_none(), Error::<T, I>::InSettlement);
+        let mut application = Self::loan_application(application_id)
+            .ok_or(Error::<T, I>::UnknownApplication)?;
+        let position = application
+            .guarantees
+            .iter()
+            .position(|g| g.guarantor == guarantor)
+            .ok_or(Error::<T, I>::UnknownApplication)?;
+        ensure!(
+            !application.guarantees[position].approved,
+            Error::<T, I>::AlreadyApproved
+        );
+
+        // the last approval opens the loan, so the loan is checked before anything is locked
+        application.guarantees[position].approved = true;
+        let complete = application.guarantees.iter().all(|g| g.approved);
+        if complete {
+            Self::check_guaranteed_loan(&application)?;
+        }
+
+        application.guarantees[position].principal =
+            Self::lock_pledge(&guarantor, &application.guarantees[position].pledge)?;
+
+        if complete {
+            // the application stays as it was and the pledge is given back if the loan can't be opened
+            if let Err(e) = Self::open_guaranteed_loan(application.clone()) {
+                Self::release_pledge(&application.guarantees[position])?;
+                return Err(e);
+            }
+            <LoanApplications<T, I>>::remove(application_id);
+        } else {
+            <LoanApplications<T, I>>::insert(application_id, application);
+        }
+        Self::deposit_event(RawEvent::GuaranteeApproved(application_id, guarantor));
+        Ok(())
+    }
+
+    /// checks an application as if all its pledges were locked, returns the price it was checked at
+    fn check_guaranteed_loan(
+        application: &LoanApplication<T::AccountId, BalanceOf<T, I>>,
+    ) -> result::Result<PriceInUSDT, DispatchError> {
+        let collection_asset_id = Self::collection_asset_id();
+        let collection_account_id = Self::collection_account_id();
+        ensure!(
+            T::Currency::free_balance(&collection_asset_id, &collection_account_id)
+                >= application.loan_amount + Self::queued_redeem_total(),
+            "Not enough to loan"
+        );
+        if let Some(loan_cap) = Self::loan_cap() {
+            ensure!(Self::total_loan() < loan_cap, Error::<T, I>::ReachLoanCap);
+        }
+        ensure!(
+            T::Currency::free_balance(&Self::collateral_asset_id(), &application.who)
+                >= application.collateral_amount,
+            "not enough collateral asset in free balance"
+        );
+
+        let btc_price = Self::fresh_collateral_price()?;
+        let (collateral, debt) = Self::pledged_position(
+            application.collateral_amount,
+            application.loan_amount,
+            &application.guarantees,
+        );
+        ensure!(
+            collateral >= Self::minimum_collateral(),
+            "not reach min collateral amount"
+        );
+        ensure!(
+            Self::position_ltv(collateral, debt, btc_price) < Self::ltv_limit_of(&application.who),
+            Error::<T, I>::OverLTVLimit
+        );
+        Ok(btc_price)
+    }
+
+    /// the borrower's own collateral follows "ReserveCollateral", pledged collateral is always
+    /// held in the pawnshop
+    fn open_guaranteed_loan(application: LoanApplication<T::AccountId, BalanceOf<T, I>>) -> DispatchResult {
+        let btc_price = Self::check_guaranteed_loan(&application)?;
+        let price = <BalanceOf<T, I> as TryFrom<u128>>::try_from(btc_price as u128)
+            .map_err(|_| Error::<T, I>::Overflow)?;
+        let who = application.who.clone();
+        let collateral_amount = application.collateral_amount;
+        let loan_amount = application.loan_amount;
+        let collateral_asset_id = Self::collateral_asset_id();
+        let pawn_shop = Self::pawn_shop();
+
+        let loan_id = Self::next_loan_id();
+        if !collateral_amount.is_zero() {
+            if Self::reserve_collateral() {
+                Self::relock_collateral(loan_id, &who, collateral_amount)?;
+            } else {
+                T::Currency::transfer(&collateral_asset_id, &who, &pawn_shop, collateral_amount)?;
+            }
+        }
+        if let Err(e) = T::Currency::transfer(
+            &Self::collection_asset_id(),
+            &Self::collection_account_id(),
+            &who,
+            loan_amount,
+        ) {
+            // the collateral goes back, nothing else has been written yet
+            if Self::collateral_reserved(loan_id) {
+                Self::relock_collateral(loan_id, &who, Zero::zero())?;
+            } else if !collateral_amount.is_zero() {
+                T::Currency::transfer(&collateral_asset_id, &pawn_shop, &who, collateral_amount)?;
+            }
+            return Err(e);
+        }
+
+        let loan_id = Self::get_next_loan_id();
+        let loan = Loan {
+            id: loan_id,
+            who: who.clone(),
+            collateral_balance_original: collateral_amount,
+            collateral_balance_available: collateral_amount.saturating_sub(loan_amount / price),
+            loan_balance_total: loan_amount,
+            status: Default::default(),
+        };
+
+        <LoanGuarantees<T, I>>::insert(loan_id, application.guarantees);
+        <Loans<T, I>>::insert(loan_id, loan.clone());
+        <LoanOpenedAt<T, I>>::insert(loan_id, <timestamp::Module<T>>::get());
+        <LoanAccruedAt<T, I>>::insert(loan_id, <timestamp::Module<T>>::get());
+        Self::index_loan(loan_id);
+        <LoansByAccount<T, I>>::mutate(&who, |v| v.push(loan_id));
+        <TotalLoan<T, I>>::mutate(|v| *v += loan_amount);
+        <TotalCollateral<T, I>>::mutate(|v| *v += collateral_amount);
+        Self::update_loan_rewards(loan_id, &who);
+
+        Self::deposit_event(RawEvent::LoanCreated(loan));
+        Ok(())
+    }
+
+    /// collateral and debt once pledges are counted,
+    /// pledged collateral adds to the collateral and pledged shares are set against the debt
+    fn pledged_position(
+        collateral: BalanceOf<T, I>,
+        debt: BalanceOf<T, I>,
+        guarantees: &[Guarantee<T::AccountId, BalanceOf<T, I>>],
+    ) -> (BalanceOf<T, I>, BalanceOf<T, I>) {
+        guarantees.iter().fold((collateral, debt), |(collateral, debt), g| match g.pledge {
+            Pledge::Collateral(amount) => (collateral.saturating_add(amount), debt),
+            Pledge::Shares(shares) => (
+                collateral,
+                debt.saturating_sub(Self::shares_to_amount(shares).unwrap_or_default()),
+            ),
+        })
+    }
+
+    fn guaranteed_position(loan: &Loan<T::AccountId, BalanceOf<T, I>>) -> (BalanceOf<T, I>, BalanceOf<T, I>) {
+        Self::pledged_position(
+            loan.collateral_balance_available,
+            loan.loan_balance_total,
+            &Self::loan_guarantees(loan.id),
+        )
+    }
+
+    fn position_ltv(collateral: BalanceOf<T, I>, debt: BalanceOf<T, I>, btc_price: PriceInUSDT) -> LTV {
+        if debt.is_zero() {
+            return 0;
+        }
+        if collateral.is_zero() {
+            return LTV::max_value();
+        }
+        <Loan<T::AccountId, BalanceOf<T, I>>>::get_ltv(collateral, debt, btc_price)
+    }
+
+    /// take the pledge away from the guarantor, returns the savings principal taken with pledged shares
+    fn lock_pledge(
+        guarantor: &T::AccountId,
+        pledge: &Pledge<BalanceOf<T, I>>,
+    ) -> result::Result<BalanceOf<T, I>, DispatchError> {
+        match *pledge {
+            Pledge::Collateral(amount) => {
+                T::Currency::transfer(
+                    &Self::collateral_asset_id(),
+                    guarantor,
+                    &Self::pawn_shop(),
+                    amount,
+                )?;
+                <TotalCollateral<T, I>>::mutate(|v| *v += amount);
+                Ok(Zero::zero())
+            }
+            Pledge::Shares(shares) => {
+                let user_dtoken = Self::user_dtoken(guarantor);
+                ensure!(user_dtoken >= shares, "not enough dtoken");
+                let principal = Self::saving_principal(guarantor);
+                let principal_taken = (principal
+                    .checked_mul(&shares)
+                    .ok_or(Error::<T, I>::Overflow)?
+                    / user_dtoken)
+                    .min(principal);
+                if user_dtoken == shares {
+                    <UserDtoken<T, I>>::remove(guarantor);
+                } else {
+                    <UserDtoken<T, I>>::insert(guarantor, user_dtoken - shares);
+                }
+                <SavingPrincipal<T, I>>::insert(guarantor, principal - principal_taken);
+                Self::update_rewards(guarantor);
+                Ok(principal_taken)
+            }
+        }
+    }
+
+    fn release_pledge(guarantee: &Guarantee<T::AccountId, BalanceOf<T, I>>) -> DispatchResult {
+        match guarantee.pledge {
+            Pledge::Collateral(amount) => {
+                T::Currency::transfer(
+                    &Self::collateral_asset_id(),
+                    &Self::pawn_shop(),
+                    &guarantee.guarantor,
+                    amount,
+                )?;
+                <TotalCollateral<T, I>>::mutate(|v| *v = v.saturating_sub(amount));
+            }
+            Pledge::Shares(shares) => {
+                <UserDtoken<T, I>>::mutate(&guarantee.guarantor, |v| *v = v.saturating_add(shares));
+                <SavingPrincipal<T, I>>::mutate(&guarantee.guarantor, |v| {
+                    *v = v.saturating_add(guarantee.principal)
+                });
+                Self::update_rewards(&guarantee.guarantor);
+            }
+        }
+        Ok(())
+    }
+
+    /// give back every pledge still backing a loan which no longer needs them, a pledge which
+    /// can't be given back stays in "LoanGuarantees" for "release_pledges"
+    fn release_guarantees(loan_id: LoanId) {
+        let mut kept = Vec::new();
+        for guarantee in <LoanGuarantees<T, I>>::take(loan_id).into_iter() {
+            match Self::release_pledge(&guarantee) {
+                Ok(()) => Self::deposit_event(RawEvent::GuaranteeReleased(
+                    loan_id,
+                    guarantee.guarantor.clone(),
+                    guarantee.pledge,
+                )),
+                Err(_) => {
+                    Self::deposit_event(RawEvent::GuaranteeReleaseFailed(
+                        loan_id,
+                        guarantee.guarantor.clone(),
+                        guarantee.pledge,
+                    ));
+                    kept.push(guarantee);
+                }
+            }
+        }
+        if !kept.is_empty() {
+            <LoanGuarantees<T, I>>::insert(loan_id, kept);
+        }
+    }
+
+    /// seize pledges in the order they were given until the loan alone is below "target" LTV,
+    /// the pledges left keep backing the loan, returns the loan as it stands afterwards
+    fn seize_guarantees(
+        loan_id: LoanId,
+        btc_price: PriceInUSDT,
+        target: LTV,
+    ) -> Loan<T::AccountId, BalanceOf<T, I>> {
+        let mut loan = Self::get_loan_by_id(loan_id);
+        let mut guarantees = Self::loan_guarantees(loan_id);
+        if guarantees.is_empty() {
+            return loan;
+        }
+
+        while !guarantees.is_empty()
+            && Self::position_ltv(
+                loan.collateral_balance_available,
+                loan.loan_balance_total,
+                btc_price,
+            ) >= target
+        {
+            let guarantee = guarantees[0].clone();
+            let seized = match guarantee.pledge {
+                Pledge::Collateral(amount) => {
+                    // already in the pawnshop and counted in "TotalCollateral", a loan keeping its
+                    // collateral in reserve takes it into the reserve
+                    if Self::collateral_reserved(loan_id)
+                        && Self::hold_collateral(&loan, &Self::pawn_shop(), amount).is_err()
+                    {
+                        break;
+                    }
+                    loan.collateral_balance_original += amount;
+                    loan.collateral_balance_available += amount;
+                    guarantee.pledge
+                }
+                Pledge::Shares(shares) => {
+                    // a pledge which can't be valued is left in place rather than taken for nothing
+                    let value = match Self::shares_to_amount(shares) {
+                        Ok(value) => value,
+                        Err(_) => break,
+                    };
+                    let (used, covered) = if value > loan.loan_balance_total {
+                        let used = match Self::amount_to_shares(loan.loan_balance_total) {
+                            Ok(used) => used.min(shares),
+                            Err(_) => break,
+                        };
+                        (used, loan.loan_balance_total)
+                    } else {
+                        (shares, value)
+                    };
+                    // the shares are burnt against the debt they cover, the pool loses nothing
+                    <MarketDtoken<T, I>>::mutate(|v| *v = v.saturating_sub(used));
+                    <TotalDtoken<T, I>>::mutate(|v| *v = v.saturating_sub(covered));
+                    <TotalLoan<T, I>>::mutate(|v| *v = v.saturating_sub(covered));
+                    loan.loan_balance_total -= covered;
+                    <LoanInterestAccrued<T, I>>::mutate(loan_id, |v| *v = v.saturating_sub(covered));
+                    if used < shares {
+                        let principal_left = guarantee.principal * (shares - used) / shares;
+                        let left = Guarantee {
+                            pledge: Pledge::Shares(shares - used),
+                            principal: principal_left,
+                            ..guarantee.clone()
+                        };
+                        // what isn't given back keeps backing the loan and is released with it
+                        if Self::release_pledge(&left).is_err() {
+                            guarantees.insert(1, left);
+                        }
+                    }
+                    Pledge::Shares(used)
+                }
+            };
+            guarantees.remove(0);
+            Self::deposit_event(RawEvent::GuaranteeSeized(
+                loan_id,
+                guarantee.guarantor.clone(),
+                seized,
+            ));
+        }
+
+        <LoanGuarantees<T, I>>::insert(loan_id, guarantees);
+        <Loans<T, I>>::insert(loan_id, loan.clone());
+        Self::update_loan_rewards(loan_id, &loan.who);
+        loan
+    }
+
+    pub fn get_collateral_loan(
+        who: &T::AccountId,
+        collateral_amount: BalanceOf<T, I>,
+        loan_amount: BalanceOf<T, I>,
+    ) -> Result<CollateralLoan<BalanceOf<T, I>>, DispatchError> {
+        if collateral_amount.is_zero() && loan_amount.is_zero() {
+            return Err(Error::<T, I>::InvalidCollateralLoanAmounts)?;
+        }
+        // get current btc price
+        let btc_price = Self::collateral_price()?;
+        let btc_price_in_balance = <BalanceOf<T, I> as TryFrom<u128>>::try_from(btc_price as u128)
+            .ok()
+            .unwrap();
+
+        let price_prec_in_balance = BalanceOf::<T, I>::from(PRICE_PREC);
+        let ltv_prec_in_balance = BalanceOf::<T, I>::from(LTV_PREC);
+
+        let ltv = Self::ltv_limit_of(who);
+        let ltv_in_balance = <BalanceOf<T, I> as TryFrom<u64>>::try_from(ltv).ok().unwrap();
+
+        if collateral_amount.is_zero() {
+            let must_collateral_amount = loan_amount * ltv_prec_in_balance * price_prec_in_balance
+                / (btc_price_in_balance * ltv_in_balance);
+            return Ok(CollateralLoan {
+                collateral_amount: must_collateral_amount,
+                loan_amount: loan_amount,
+            });
+        }
+
+        if loan_amount.is_zero() {
+            let can_loan_amount = (collateral_amount * btc_price_in_balance * ltv_in_balance)
+                / (ltv_prec_in_balance * price_prec_in_balance);
+            return Ok(CollateralLoan {
+                collateral_amount: collateral_amount,
+                loan_amount: can_loan_amount,
+            });
+        }
+
+        if (loan_amount * ltv_prec_in_balance) * price_prec_in_balance
+            / (collateral_amount * btc_price_in_balance)
+            >= ltv_in_balance
+        {
+            Err(Error::<T, I>::OverLTVLimit)?
+        } else {
+            Ok(CollateralLoan {
+                collateral_amount,
+                loan_amount,
+            })
+        }
+    }
+
+    pub fn repay_for_loan(who: T::AccountId, loan_id: LoanId) -> DispatchResult {
+        ensure!(<Loans<T, I>>::contains_key(loan_id), "invalid loan id");
+        Self::accrue_loan_interest(loan_id);
+        let loan = <Loans<T, I>>::get(loan_id);
+        ensure!(loan.who == who, "not owner of the loan");
+
+        Self::close_loan(&who, &loan)
+    }
+
+    /// what "amount" of "asset_id" is worth in the loan asset, which is priced in USDT
+    fn value_in_loan_asset(
+        asset_id: AssetIdOf<T, I>,
+        amount: BalanceOf<T, I>,
+    ) -> result::Result<BalanceOf<T, I>, DispatchError> {
+        if asset_id == Self::loan_asset_id() {
+            return Ok(amount);
+        }
+        let price = T::PriceProvider::price(asset_id)?;
+        ensure!(!price.scale.is_zero(), Error::<T, I>::PriceNotAvailable);
+        let max_age = Self::max_price_age();
+        if !max_age.is_zero() {
+            let now = <frame_system::Module<T>>::block_number();
+            ensure!(
+                now.saturating_sub(price.block_number) <= max_age,
+                Error::<T, I>::PriceStale
+            );
+        }
+        let amount = TryInto::<u128>::try_into(amount).map_err(|_| Error::<T, I>::Overflow)?;
+        let value = amount
+            .checked_mul(price.value as u128)
+            .ok_or(Error::<T, I>::Overflow)?
+            / (price.scale as u128);
+        <BalanceOf<T, I> as TryFrom<u128>>::try_from(value).map_err(|_| Error::<T, I>::Overflow.into())
+    }
+
+    /// interest paid in the loan asset goes to the creditor of the loan like a repayment, for a pool loan
+    /// paid in any other asset it goes to "ProfitPool", which pays what it covers into the collection account
+    /// so that the savers are paid in full. The lender of a fixed-rate loan only takes the loan asset
+    pub fn pay_loan_interest(
+        payer: T::AccountId,
+        loan_id: LoanId,
+        asset_id: AssetIdOf<T, I>,
+        amount: BalanceOf<T, I>,
+    ) -> DispatchResult {
+        ensure!(Self::settlement_price().is_none(), Error::<T, I>::InSettlement);
+        ensure!(<Loans<T, I>>::contains_key(loan_id), "invalid loan id");
+        ensure!(
+            !Self::check_loan_in_liquidation(&loan_id),
+            "loan is in liquidation"
+        );
+        ensure!(
+            asset_id == Self::profit_asset_id() || Self::interest_assets().contains(&asset_id),
+            Error::<T, I>::InterestAssetNotAccepted
+        );
+        let through_profit_pool = asset_id != Self::loan_asset_id();
+        ensure!(
+            !through_profit_pool || Self::is_pool_loan(loan_id),
+            Error::<T, I>::InterestAssetNotAccepted
+        );
+        Self::accrue_loan_interest(loan_id);
+        let accrued = Self::loan_interest_accrued(loan_id);
+        ensure!(!accrued.is_zero(), Error::<T, I>::NoAccruedInterest);
+
+        let value = Self::value_in_loan_asset(asset_id, amount)?;
+        ensure!(!value.is_zero(), Error::<T, I>::InvalidInterestPayment);
+        // only what covers the accrued interest is taken, rounded up so that the interest is paid in full
+        let (paid, covered) = if value > accrued {
+            let paid = amount
+                .checked_mul(&accrued)
+                .and_then(|v| v.checked_add(&(value - One::one())))
+                .ok_or(Error::<T, I>::Overflow)?
+                / value;
+            (cmp::min(paid, amount), accrued)
+        } else {
+            (amount, value)
+        };
+        ensure!(
+            T::Currency::free_balance(&asset_id, &payer) >= paid,
+            "insufficient balance"
+        );
+
+        let collection_asset_id = Self::collection_asset_id();
+        let profit_pool = Self::profit_pool();
+        if through_profit_pool {
+            ensure!(
+                T::Currency::free_balance(&collection_asset_id, &profit_pool) >= covered,
+                Error::<T, I>::InsufficientProfitPool
+            );
+            T::Currency::transfer(&asset_id, &payer, &profit_pool, paid)?;
+            T::Currency::transfer(
+                &collection_asset_id,
+                &profit_pool,
+                &Self::collection_account_id(),
+                covered,
+            )?;
+        } else {
+            T::Currency::transfer(&asset_id, &payer, &Self::loan_creditor(loan_id), paid)?;
+        }
+
+        <LoanInterestAccrued<T, I>>::insert(loan_id, accrued - covered);
+        <Loans<T, I>>::mutate(loan_id, |v| {
+            v.loan_balance_total = v.loan_balance_total.saturating_sub(covered)
+        });
+        if Self::is_pool_loan(loan_id) {
+            <TotalLoan<T, I>>::mutate(|v| *v = v.saturating_sub(covered));
+        }
+        Self::index_loan(loan_id);
+        let loan = Self::get_loan_by_id(loan_id);
+        Self::update_loan_rewards(loan_id, &loan.who);
+
+        Self::deposit_event(RawEvent::InterestPaidIn(loan_id, payer, asset_id, paid, covered));
+        Ok(())
+    }
+
+    /// "payer" repays up to "amount" of someone else's loan, a full repayment returns the collateral to the loan owner
+    pub fn repay_loan_for(payer: T::AccountId, loan_id: LoanId, amount: BalanceOf<T, I>) -> DispatchResult {
+        ensure!(<Loans<T, I>>::contains_key(loan_id), "invalid loan id");
+        ensure!(!amount.is_zero(), "repay can't be zero");
+        ensure!(
+            !Self::check_loan_in_liquidation(&loan_id),
+            "loan is in liquidation"
+        );
+        Self::accrue_loan_interest(loan_id);
+        let loan = <Loans<T, I>>::get(loan_id);
+
+        if amount >= loan.loan_balance_total {
+            Self::close_loan(&payer, &loan)?;
+            Self::deposit_event(RawEvent::LoanRepaidFor(
+                loan_id,
+                payer,
+                loan.who,
+                loan.loan_balance_total,
+            ));
+            return Ok(());
+        }
+
+        let loan_asset_id = Self::loan_asset_id();
+        let creditor = Self::loan_creditor(loan_id);
+
+        ensure!(
+            T::Currency::free_balance(&loan_asset_id, &payer) >= amount,
+            "not enough asset to repay"
+        );
+
+        let btc_price = <BalanceOf<T, I> as TryFrom<u128>>::try_from(Self::collateral_price()? as u128)
+            .map_err(|_| Error::<T, I>::Overflow)?;
+        let loan_balance_total = loan
+            .loan_balance_total
+            .checked_sub(&amount)
+            .ok_or(Error::<T, I>::Overflow)?;
+        let total_loan = if Self::is_pool_loan(loan_id) {
+            Some(Self::total_loan().checked_sub(&amount).ok_or(Error::<T, I>::Overflow)?)
+        } else {
+            None
+        };
+
+        T::Currency::transfer(
+            &loan_asset_id,
+            &payer,
+            &creditor,
+            amount,
+        )?;
+
+        // reverse of "draw_from_loan"
+        <Loans<T, I>>::mutate(loan_id, |v| {
+            v.loan_balance_total = loan_balance_total;
+            v.collateral_balance_available = cmp::min(
+                v.collateral_balance_available.saturating_add(amount / btc_price),
+                v.collateral_balance_original,
+            );
+        });
+        Self::credit_repaid_interest(loan_id, &loan.who, amount);
+        Self::index_loan(loan_id);
+        if let Some(total_loan) = total_loan {
+            <TotalLoan<T, I>>::put(total_loan);
+        }
+
+        Self::update_loan_rewards(loan_id, &loan.who);
+        Self::deposit_event(RawEvent::LoanRepaidFor(loan_id, payer, loan.who, amount));
+        Self::process_redeem_queue();
+        Ok(())
+    }
+
+    /// a repayment of "amount" pays off the interest drawn on the loan first, what it covers is
+    /// recorded as paid
+    fn credit_repaid_interest(loan_id: LoanId, who: &T::AccountId, amount: BalanceOf<T, I>) {
+        let accrued = Self::loan_interest_accrued(loan_id);
+        let interest = cmp::min(amount, accrued);
+        if interest.is_zero() {
+            return;
+        }
+        <LoanInterestAccrued<T, I>>::insert(loan_id, accrued - interest);
+        <InterestPaid<T, I>>::mutate(who, |v| *v = v.saturating_add(interest));
+        <LoanInterestPaid<T, I>>::mutate(loan_id, |v| *v = v.saturating_add(interest));
+    }
+
+    /// the loan asset comes from "payer" and the collateral goes back to the loan owner
+    fn close_loan(payer: &T::AccountId, loan: &Loan<T::AccountId, BalanceOf<T, I>>) -> DispatchResult {
+        let loan_asset_id = Self::loan_asset_id();
+        let collateral_asset_id = Self::collateral_asset_id();
+        let creditor = Self::loan_creditor(loan.id);
+        let pawn_shop = Self::pawn_shop();
+        let loan_id = loan.id;
+        let who = loan.who.clone();
+
+        ensure!(
+            T::Currency::free_balance(&loan_asset_id, payer)
+                >= loan.loan_balance_total,
+            "not enough asset to repay"
+        );
+        ensure!(
+            Self::collateral_reserved(loan_id)
+                || T::Currency::free_balance(&collateral_asset_id, &pawn_shop)
+                    >= loan.collateral_balance_available,
+            "not enough collateral asset in shop"
+        );
+        ensure!(
+            !Self::check_loan_in_liquidation(&loan_id),
+            "loan is in liquidation"
+        );
+
+        <LoansByAccount<T, I>>::mutate(&who, |v| {
+            *v = v
+                .clone()
+                .into_iter()
+                .filter(|ele| *ele != loan_id)
+                .collect::<Vec<LoanId>>();
+        });
+
+        let revert_callback = || {
+            <Loans<T, I>>::insert(&loan.id, loan);
+            <LoansByAccount<T, I>>::mutate(&who, |v| {
+                v.push(loan.id);
+            });
+            <TotalLoan<T, I>>::mutate(|v| *v += loan.loan_balance_total);
+            <TotalCollateral<T, I>>::mutate(|v| *v += loan.collateral_balance_available);
+        };
+
+        T::Currency::transfer(
+            &loan_asset_id,
+            payer,
+            &creditor,
+            loan.loan_balance_total,
         )
         .or_else(|err| -> DispatchResult {
             revert_callback();
-            <generic_asset::Module<T>>::make_transfer_with_event(
+            Err(err)
+        })?;
+        Self::release_collateral(loan, loan.collateral_balance_original)
+        .or_else(|err| -> DispatchResult {
+            revert_callback();
+            T::Currency::transfer(
                 &loan_asset_id,
-                &collection_account_id,
-                &who,
+                &creditor,
+                payer,
                 loan.loan_balance_total,
             )?;
             Err(err)
         })?;
 
-        <Loans<T>>::remove(&loan.id);
-        <TotalLoan<T>>::mutate(|v| *v -= loan.loan_balance_total);
-        // <TotalCollateral<T>>::mutate(|v| *v -= loan.collateral_balance_available);
-        <TotalCollateral<T>>::mutate(|v| *v -= loan.collateral_balance_original);
-
+        <Loans<T, I>>::remove(&loan.id);
+        Self::unindex_loan(loan.id);
+        if Self::is_pool_loan(loan_id) {
+            <TotalLoan<T, I>>::mutate(|v| *v -= loan.loan_balance_total);
+        }
+        // <TotalCollateral<T, I>>::mutate(|v| *v -= loan.collateral_balance_available);
+        <TotalCollateral<T, I>>::mutate(|v| *v -= loan.collateral_balance_original);
+
+        Self::release_guarantees(loan_id);
+        <FixedRateLoans<T, I>>::remove(loan_id);
+        Self::credit_repaid_interest(loan_id, &who, loan.loan_balance_total);
+        Self::archive_loan(loan, LoanHealth::Repaid, Zero::zero());
+        Self::update_loan_rewards(loan_id, &who);
         Self::deposit_event(RawEvent::LoanRepaid(
             loan_id,
             loan.loan_balance_total,
             loan.collateral_balance_available,
         ));
+        Self::process_redeem_queue();
         Ok(())
     }
 
     fn check_loan_in_liquidation(loan_id: &LoanId) -> bool {
-        LiquidatingLoans::get().contains(loan_id)
+        <LiquidatingLoans<I>>::get().contains(loan_id)
+    }
+
+    pub fn mark_loan_liquidated(
+        loan: &Loan<T::AccountId, BalanceOf<T, I>>,
+        liquidation_account: T::AccountId,
+        auction_balance: BalanceOf<T, I>,
+    ) -> DispatchResult {
+
+        ensure!(
+            Self::check_loan_in_liquidation(&loan.id),
+            "loan id not in liquidating"
+        );
+
+        if let Some(plan) = Self::partial_liquidations(loan.id) {
+            return Self::mark_loan_partially_liquidated(
+                loan,
+                liquidation_account,
+                auction_balance,
+                plan,
+            );
+        }
+
+        let creditor = Self::loan_creditor(loan.id);
+        let loan_asset_id = Self::loan_asset_id();
+
+        ensure!(
+            T::Currency::free_balance(&loan_asset_id, &liquidation_account)
+                >= auction_balance,
+            "not enough asset to liquidate"
+        );
+        // a shortfall is only ever taken by the savers through "write_off_loan"
+        ensure!(
+            auction_balance >= loan.loan_balance_total,
+            "Not enough for loan liquidate"
+        );
+        Self::seize_collateral(loan, &liquidation_account, loan.collateral_balance_original)?;
+
+        T::Currency::transfer(
+            &loan_asset_id,
+            &liquidation_account,
+            &creditor,
+            loan.loan_balance_total,
+        )?;
+
+        let leftover = auction_balance - loan.loan_balance_total;
+        Self::distribute_liquidation_proceeds(loan, &liquidation_account, leftover)?;
+
+        Self::close_liquidated_loan(loan, LoanHealth::Liquidated, auction_balance);
+        Ok(())
+    }
+
+    /// default a liquidating loan on an auction short of its debt, the creditor takes the shortfall
+    fn write_off_shortfall(
+        loan: &Loan<T::AccountId, BalanceOf<T, I>>,
+        liquidation_account: T::AccountId,
+        auction_balance: BalanceOf<T, I>,
+    ) -> DispatchResult {
+        ensure!(
+            Self::check_loan_in_liquidation(&loan.id),
+            "loan id not in liquidating"
+        );
+        ensure!(
+            auction_balance < loan.loan_balance_total,
+            Error::<T, I>::NoShortfall
+        );
+
+        let creditor = Self::loan_creditor(loan.id);
+        let loan_asset_id = Self::loan_asset_id();
+
+        ensure!(
+            T::Currency::free_balance(&loan_asset_id, &liquidation_account)
+                >= auction_balance,
+            "not enough asset to liquidate"
+        );
+        Self::seize_collateral(loan, &liquidation_account, loan.collateral_balance_original)?;
+
+        T::Currency::transfer(
+            &loan_asset_id,
+            &liquidation_account,
+            &creditor,
+            auction_balance,
+        )?;
+
+        // a lender takes the shortfall of its own loan
+        if Self::is_pool_loan(loan.id) {
+            let shortfall = loan.loan_balance_total - auction_balance;
+            <TotalDtoken<T, I>>::mutate(|v| *v = v.saturating_sub(shortfall));
+        }
+        <PartialLiquidations<T, I>>::remove(loan.id);
+
+        Self::close_liquidated_loan(loan, LoanHealth::Defaulted, auction_balance);
+        Ok(())
+    }
+
+    /// take a loan whose auction has been settled out of the loan book
+    fn close_liquidated_loan(
+        loan: &Loan<T::AccountId, BalanceOf<T, I>>,
+        status: LoanHealth,
+        auction_balance: BalanceOf<T, I>,
+    ) {
+        Self::release_guarantees(loan.id);
+        if Self::is_pool_loan(loan.id) {
+            <TotalLoan<T, I>>::mutate(|v| *v = v.saturating_sub(loan.loan_balance_total));
+        }
+        <TotalCollateral<T, I>>::mutate(|v| {
+            *v = v.saturating_sub(loan.collateral_balance_original)
+        });
+        <FixedRateLoans<T, I>>::remove(loan.id);
+        Self::archive_loan(loan, status, auction_balance);
+        <Loans<T, I>>::remove(&loan.id);
+        Self::unindex_loan(loan.id);
+        <LoansByAccount<T, I>>::mutate(&loan.who, |v| {
+            *v = v
+                .clone()
+                .into_iter()
+                .filter(|ele| ele != &loan.id)
+                .collect::<Vec<LoanId>>();
+        });
+        <LiquidatingLoans<I>>::mutate(|v| {
+            *v = v
+                .clone()
+                .into_iter()
+                .filter(|ele| ele != &loan.id)
+                .collect::<Vec<LoanId>>();
+        });
+        Self::update_loan_rewards(loan.id, &loan.who);
+        Self::deposit_event(RawEvent::Liquidated(
+            loan.id,
+            loan.collateral_balance_original,
+            loan.collateral_balance_available,
+            auction_balance,
+            loan.loan_balance_total,
+        ));
+        Self::process_redeem_queue();
     }
 
-    pub fn mark_loan_liquidated(
-        loan: &Loan<T::AccountId, T::Balance>,
+    fn mark_loan_partially_liquidated(
+        loan: &Loan<T::AccountId, BalanceOf<T, I>>,
         liquidation_account: T::AccountId,
-        auction_balance: T::Balance,
+        auction_balance: BalanceOf<T, I>,
+        plan: CollateralLoan<BalanceOf<T, I>>,
     ) -> DispatchResult {
-
-        ensure!(
-            Self::check_loan_in_liquidation(&loan.id),
-            "loan id not in liquidating"
-        );
-
         let pawnshop = Self::pawn_shop();
         let collateral_asset_id = Self::collateral_asset_id();
-        let collection_asset_id = Self::collection_asset_id();
-        let collection_account_id = Self::collection_account_id();
+        let creditor = Self::loan_creditor(loan.id);
         let loan_asset_id = Self::loan_asset_id();
 
         ensure!(
-            <generic_asset::Module<T>>::free_balance(&loan_asset_id, &liquidation_account)
+            T::Currency::free_balance(&loan_asset_id, &liquidation_account)
                 >= auction_balance,
             "not enough asset to liquidate"
         );
-
         ensure!(
-            auction_balance >= loan.loan_balance_total, "Not enough for loan liquidate"
+            auction_balance >= plan.loan_amount,
+            "Not enough for loan liquidate"
+        );
+        ensure!(
+            Self::collateral_reserved(loan.id)
+                || T::Currency::free_balance(&collateral_asset_id, &pawnshop)
+                    >= plan.collateral_amount,
+            "not enough collateral asset in shop"
         );
 
-        <generic_asset::Module<T>>::make_transfer_with_event(
+        T::Currency::transfer(
             &loan_asset_id,
             &liquidation_account,
-            &collection_account_id,
-            loan.loan_balance_total,
+            &creditor,
+            plan.loan_amount,
         )?;
-
-        let leftover = auction_balance.checked_sub(&loan.loan_balance_total);
-
-        if leftover.is_some() && leftover.unwrap() > T::Balance::zero() {
-            let penalty_rate = Self::liquidation_penalty();
-            let penalty =
-                leftover.unwrap() * T::Balance::from(penalty_rate) / 100.into();
-
-                <generic_asset::Module<T>>::make_transfer_with_event(
-                &loan_asset_id,
-                &collection_account_id,
-                &Self::profit_pool(),   // TODO: can change to team account
-                penalty,
-            )
-            .or_else(|err| -> DispatchResult {
-                <generic_asset::Module<T>>::make_transfer_with_event(
-                    &loan_asset_id,
-                    &pawnshop,
-                    &liquidation_account,
-                    loan.loan_balance_total,
-                )?;
-                Err(err)
-            })?;
-            // part of the penalty will transfer to the loan owner
-            <generic_asset::Module<T>>::make_transfer_with_event(
-                &loan_asset_id,
-                &collection_account_id,
-                &loan.who,
-                leftover.unwrap() - penalty,
-            )
-            .or_else(|err| -> DispatchResult {
-                <generic_asset::Module<T>>::make_transfer_with_event(
-                    &loan_asset_id,
-                    &Self::profit_pool(),
-                    &liquidation_account,
-                    penalty,
-                )?;
-
-                // TODO: ensure pawnshop have enough collateral_asset
-                <generic_asset::Module<T>>::make_transfer_with_event(
-                    &collateral_asset_id,
-                    &pawnshop,
-                    &liquidation_account,
-                    loan.collateral_balance_original,
-                )?;
-                Err(err)
-            })?;
-        }
-        <Loans<T>>::remove(&loan.id);
-        <LoansByAccount<T>>::mutate(&loan.who, |v| {
-            *v = v
-                .clone()
-                .into_iter()
-                .filter(|ele| ele != &loan.id)
-                .collect::<Vec<LoanId>>();
+        Self::seize_collateral(loan, &liquidation_account, plan.collateral_amount)?;
+
+        let leftover = auction_balance - plan.loan_amount;
+        Self::distribute_liquidation_proceeds(loan, &liquidation_account, leftover)?;
+
+        <Loans<T, I>>::mutate(loan.id, |v| {
+            v.collateral_balance_original = v
+                .collateral_balance_original
+                .saturating_sub(plan.collateral_amount);
+            v.collateral_balance_available = v
+                .collateral_balance_available
+                .saturating_sub(plan.collateral_amount);
+            v.loan_balance_total = v.loan_balance_total.saturating_sub(plan.loan_amount);
+            v.status = LoanHealth::Well;
         });
-        LiquidatingLoans::mutate(|v| {
+        <LoanInterestAccrued<T, I>>::mutate(loan.id, |v| *v = v.saturating_sub(plan.loan_amount));
+        if Self::is_pool_loan(loan.id) {
+            <TotalLoan<T, I>>::mutate(|v| *v = v.saturating_sub(plan.loan_amount));
+        }
+        <TotalCollateral<T, I>>::mutate(|v| *v = v.saturating_sub(plan.collateral_amount));
+
+        <PartialLiquidations<T, I>>::remove(loan.id);
+        <LiquidatingLoans<I>>::mutate(|v| {
             *v = v
                 .clone()
                 .into_iter()
                 .filter(|ele| ele != &loan.id)
                 .collect::<Vec<LoanId>>();
         });
-        Self::deposit_event(RawEvent::Liquidated(
+        // back in the loan book with what is left
+        Self::index_loan(loan.id);
+        Self::update_loan_rewards(loan.id, &loan.who);
+
+        Self::deposit_event(RawEvent::PartiallyLiquidated(
             loan.id,
-            loan.collateral_balance_original,
-            loan.collateral_balance_available,
+            plan.collateral_amount,
+            plan.loan_amount,
             auction_balance,
-            loan.loan_balance_total,
         ));
+        Self::process_redeem_queue();
+
+        Ok(())
+    }
+
+    fn penalty_destination_account(
+        destination: PenaltyDestination,
+        loan: &Loan<T::AccountId, BalanceOf<T, I>>,
+        liquidation_account: &T::AccountId,
+    ) -> T::AccountId {
+        match destination {
+            PenaltyDestination::Liquidator => liquidation_account.clone(),
+            PenaltyDestination::Savers => Self::collection_account_id(),
+            PenaltyDestination::LoanOwner => loan.who.clone(),
+            _ => Self::penalty_account(destination).unwrap_or_else(Self::profit_pool),
+        }
+    }
+
+    /// split what is left of the auction after covering the debt according to "PenaltySplit",
+    /// rounding dust goes to the last destination
+    fn distribute_liquidation_proceeds(
+        loan: &Loan<T::AccountId, BalanceOf<T, I>>,
+        liquidation_account: &T::AccountId,
+        leftover: BalanceOf<T, I>,
+    ) -> DispatchResult {
+        let mut split = Self::penalty_split();
+        if split.is_empty() {
+            let penalty_rate = cmp::min(Self::liquidation_penalty(), 100);
+            split = vec![
+                (PenaltyDestination::Treasury, penalty_rate),
+                (PenaltyDestination::LoanOwner, 100 - penalty_rate),
+            ];
+        }
+
+        let loan_asset_id = Self::loan_asset_id();
+        let mut remaining = leftover;
+        let mut shares = Vec::with_capacity(split.len());
+
+        for (i, (destination, pct)) in split.iter().enumerate() {
+            let amount = if i + 1 == split.len() {
+                remaining
+            } else {
+                cmp::min(leftover * BalanceOf::<T, I>::from(*pct) / 100.into(), remaining)
+            };
+            remaining -= amount;
+
+            // every destination is reported, with a zero share when nothing was left over
+            let account = Self::penalty_destination_account(*destination, loan, liquidation_account);
+            if !amount.is_zero() {
+                T::Currency::transfer(
+                    &loan_asset_id,
+                    liquidation_account,
+                    &account,
+                    amount,
+                )?;
+                if *destination == PenaltyDestination::Savers {
+                    <TotalDtoken<T, I>>::mutate(|v| *v = v.saturating_add(amount));
+                }
+            }
+            shares.push((*destination, account, amount));
+        }
 
+        Self::deposit_event(RawEvent::LiquidationProceedsSplit(loan.id, shares));
         Ok(())
     }
 
+    /// the smallest debt (and the collateral sold for it) that brings the loan back below
+    /// "GlobalWarningThreshold", or None when the whole loan has to be liquidated
+    fn partial_liquidation_plan(
+        loan: &Loan<T::AccountId, BalanceOf<T, I>>,
+        btc_price: u64,
+    ) -> Option<CollateralLoan<BalanceOf<T, I>>> {
+        let close_factor = Self::close_factor() as u128;
+        if close_factor.is_zero() || close_factor >= 100 || btc_price.is_zero() {
+            return None;
+        }
+
+        let target = Self::global_warning_threshold() as u128;
+        let ltv_prec = LTV_PREC as u128;
+        let price_prec = PRICE_PREC as u128;
+        if target >= ltv_prec {
+            return None;
+        }
+
+        let price = btc_price as u128;
+        let loan_total = TryInto::<u128>::try_into(loan.loan_balance_total).ok()?;
+        let collateral = TryInto::<u128>::try_into(loan.collateral_balance_available).ok()?;
+
+        // (loan_total - d) * PP * LP / ((collateral - d * PP / price) * price) < target
+        let debt_value = loan_total.checked_mul(price_prec)?.checked_mul(ltv_prec)?;
+        let collateral_value = target.checked_mul(collateral)?.checked_mul(price)?;
+        if debt_value <= collateral_value {
+            return None;
+        }
+        let divisor = price_prec * (ltv_prec - target);
+        let debt = (debt_value - collateral_value + divisor - 1) / divisor;
+
+        if debt >= loan_total || debt > loan_total * close_factor / 100 {
+            return None;
+        }
+
+        let collateral_to_sell = (debt.checked_mul(price_prec)? + price - 1) / price;
+        if collateral_to_sell >= collateral {
+            return None;
+        }
+
+        Some(CollateralLoan {
+            collateral_amount: TryFrom::<u128>::try_from(collateral_to_sell).ok()?,
+            loan_amount: TryFrom::<u128>::try_from(debt).ok()?,
+        })
+    }
+
     pub fn add_loan_collateral(
-        loan: &Loan<T::AccountId, T::Balance>,
+        loan: &Loan<T::AccountId, BalanceOf<T, I>>,
         from: T::AccountId,
-        amount: T::Balance,
+        amount: BalanceOf<T, I>,
     ) -> DispatchResult {
-        let pawnshop = Self::pawn_shop();
-        let collateral_asset_id = Self::collection_asset_id();
+        let collateral_asset_id = Self::collateral_asset_id();
 
         ensure!(
-            <generic_asset::Module<T>>::free_balance(&collateral_asset_id, &from) >= amount,
+            T::Currency::free_balance(&collateral_asset_id, &from) >= amount,
             "not enough collateral asset in free balance"
         );
 
-        <generic_asset::Module<T>>::make_transfer_with_event(
-            &collateral_asset_id,
-            &from,
-            &pawnshop,
-            amount,
-        )?;
+        Self::hold_collateral(loan, &from, amount)?;
 
-        <Loans<T>>::mutate(loan.id, |l| {
+        <Loans<T, I>>::mutate(loan.id, |l| {
             l.collateral_balance_original += amount;
             l.collateral_balance_available += amount;
         });
+        Self::index_loan(loan.id);
 
-        <TotalCollateral<T>>::mutate(|c| {
+        <TotalCollateral<T, I>>::mutate(|c| {
             *c += amount;
         });
 
@@ -907,16 +3314,55 @@ impl<T: Trait> Module<T> {
     }
 
     fn check_loan_health(
-        loan: &Loan<T::AccountId, T::Balance>,
+        loan: &Loan<T::AccountId, BalanceOf<T, I>>,
         btc_price: u64,
         liquidation: LTV,
         warning: LTV,
     ) -> LoanHealth {
-        let current_ltv = <Loan<T::AccountId, T::Balance>>::get_ltv(
-            loan.collateral_balance_available,
-            loan.loan_balance_total,
-            btc_price,
-        );
+        let (collateral, debt) = Self::guaranteed_position(loan);
+        let current_ltv = Self::position_ltv(collateral, debt, btc_price);
+
+        if current_ltv >= liquidation || Self::loan_overdue(loan.id) {
+            return LoanHealth::Liquidating(current_ltv);
+        }
+
+        if current_ltv >= warning {
+            return LoanHealth::Warning(current_ltv);
+        }
+
+        LoanHealth::Well
+    }
+
+    /// pool the collateral and debt of every active loan of "who" and judge them as one position
+    fn check_account_health(
+        who: &T::AccountId,
+        btc_price: u64,
+        liquidation: LTV,
+        warning: LTV,
+    ) -> LoanHealth {
+        let mut collateral_total = BalanceOf::<T, I>::zero();
+        let mut loan_total = BalanceOf::<T, I>::zero();
+        for loan_id in Self::loans_by_account(who).iter() {
+            if Self::check_loan_in_liquidation(loan_id)
+                || !<Loans<T, I>>::contains_key(loan_id)
+                || !Self::is_pool_loan(*loan_id)
+            {
+                continue;
+            }
+            let (collateral, debt) = Self::guaranteed_position(&Self::get_loan_by_id(loan_id));
+            collateral_total = collateral_total.saturating_add(collateral);
+            loan_total = loan_total.saturating_add(debt);
+        }
+
+        if loan_total.is_zero() {
+            return LoanHealth::Well;
+        }
+        if collateral_total.is_zero() {
+            return LoanHealth::Liquidating(LTV::max_value());
+        }
+
+        let current_ltv =
+            <Loan<T::AccountId, BalanceOf<T, I>>>::get_ltv(collateral_total, loan_total, btc_price);
 
         if current_ltv >= liquidation {
             return LoanHealth::Liquidating(current_ltv);
@@ -929,117 +3375,792 @@ impl<T: Trait> Module<T> {
         LoanHealth::Well
     }
 
+    pub fn set_account_margin_mode(who: T::AccountId, account_level: bool) -> DispatchResult {
+        if account_level == Self::account_margin_mode(&who) {
+            return Ok(());
+        }
+
+        let loan_ids = Self::loans_by_account(&who);
+        ensure!(
+            !loan_ids.iter().any(|id| Self::check_loan_in_liquidation(id)),
+            "loan is in liquidation"
+        );
+
+        if account_level {
+            <AccountMarginMode<T, I>>::insert(&who, true);
+            Self::add_margin_account(&who);
+            // judged with the account from now on
+            for loan_id in loan_ids.iter() {
+                Self::index_loan(*loan_id);
+            }
+        } else {
+            // every loan must be able to stand on its own before leaving account-level margin
+            let btc_price = Self::collateral_price()?;
+            let liquidation_thd = Self::global_liquidation_threshold();
+            let warning_thd = Self::global_warning_threshold();
+
+            for loan_id in loan_ids.iter() {
+                let loan = Self::get_loan_by_id(loan_id);
+                if let LoanHealth::Liquidating(_) =
+                    Self::check_loan_health(&loan, btc_price, liquidation_thd, warning_thd)
+                {
+                    Err(Error::<T, I>::LoanNotSelfSustained)?
+                }
+            }
+            <AccountMarginMode<T, I>>::remove(&who);
+            <AccountHealth<T, I>>::remove(&who);
+            Self::remove_margin_account(&who);
+            for loan_id in loan_ids.iter() {
+                Self::index_loan(*loan_id);
+            }
+        }
+
+        Self::deposit_event(RawEvent::MarginModeChanged(who, account_level));
+        Ok(())
+    }
+
+    /// move "loan" into liquidating, partially when "CloseFactor" allows it
+    fn start_liquidation(loan: &Loan<T::AccountId, BalanceOf<T, I>>, ltv: LTV, btc_price: u64) {
+        let loan_id = loan.id;
+        Self::accrue_loan_interest(loan_id);
+        // guarantors pay first, the plan below is made on what the loan holds afterwards
+        let loan = &Self::seize_guarantees(loan_id, btc_price, Self::global_warning_threshold());
+        Self::liquidate_loan(loan_id, ltv);
+
+        let plan = if Self::account_margin_mode(&loan.who) {
+            None
+        } else {
+            Self::partial_liquidation_plan(loan, btc_price)
+        };
+        if let Some(plan) = plan {
+            <PartialLiquidations<T, I>>::insert(loan_id, plan.clone());
+            Self::deposit_event(RawEvent::PartiallyLiquidating(
+                loan_id,
+                loan.who.clone(),
+                plan.collateral_amount,
+                plan.loan_amount,
+            ));
+        } else {
+            Self::deposit_event(RawEvent::Liquidating(
+                loan_id,
+                loan.who.clone(),
+                loan.collateral_balance_available,
+                loan.loan_balance_total,
+            ));
+        }
+    }
+
+    /// loans in account-level margin are judged by the health of the whole account
+    fn judge_loan(
+        loan: &Loan<T::AccountId, BalanceOf<T, I>>,
+        btc_price: u64,
+        liquidation: LTV,
+        warning: LTV,
+    ) -> LoanHealth {
+        if Self::account_margin_mode(&loan.who) && Self::is_pool_loan(loan.id) {
+            Self::check_account_health(&loan.who, btc_price, liquidation, warning)
+        } else {
+            Self::check_loan_health(loan, btc_price, liquidation, warning)
+        }
+    }
+
+    /// loans which would go into liquidating at the current price,
+    /// it only reads state so the offchain worker can call it
+    pub fn liquidatable_loans() -> Vec<LoanId> {
+        let btc_price = match Self::collateral_price() {
+            Ok(price) => price,
+            Err(_) => return Vec::new(),
+        };
+        let liquidation_thd = Self::global_liquidation_threshold();
+        let warning_thd = Self::global_warning_threshold();
+
+        <Loans<T, I>>::enumerate()
+            .filter(|(loan_id, _)| !Self::check_loan_in_liquidation(loan_id))
+            .filter(|(_, loan)| {
+                match Self::judge_loan(loan, btc_price, liquidation_thd, warning_thd) {
+                    LoanHealth::Liquidating(_) => true,
+                    _ => false,
+                }
+            })
+            .map(|(loan_id, _)| loan_id)
+            .collect()
+    }
+
+    /// whether every loan of "loan_ids", without duplicates, would go into liquidating at the
+    /// price of the last recorded round
+    fn all_liquidatable(loan_ids: &[LoanId]) -> bool {
+        let btc_price = match Self::last_price_round() {
+            Some((price, _)) => price,
+            None => match Self::collateral_price() {
+                Ok(price) => price,
+                Err(_) => return false,
+            },
+        };
+        let liquidation_thd = Self::global_liquidation_threshold();
+        let warning_thd = Self::global_warning_threshold();
+
+        let mut ids = loan_ids.to_vec();
+        ids.sort();
+        ids.dedup();
+        if ids.len() != loan_ids.len() {
+            return false;
+        }
+        ids.iter().all(|loan_id| {
+            if !<Loans<T, I>>::contains_key(loan_id) || Self::check_loan_in_liquidation(loan_id) {
+                return false;
+            }
+            let loan = Self::get_loan_by_id(loan_id);
+            match Self::judge_loan(&loan, btc_price, liquidation_thd, warning_thd) {
+                LoanHealth::Liquidating(_) => true,
+                _ => false,
+            }
+        })
+    }
+
+    fn liquidate_loans(loan_ids: Vec<LoanId>) -> DispatchResult {
+        ensure!(Self::settlement_price().is_none(), Error::<T, I>::InSettlement);
+        ensure!(
+            Self::unconfirmed_price().is_none(),
+            Error::<T, I>::LiquidationSuspended
+        );
+        ensure!(
+            loan_ids.len() as u32 <= T::MaxLiquidationBatch::get(),
+            Error::<T, I>::TooManyLoans
+        );
+        let btc_price = Self::collateral_price()?;
+        let liquidation_thd = Self::global_liquidation_threshold();
+        let warning_thd = Self::global_warning_threshold();
+
+        for loan_id in loan_ids {
+            if !<Loans<T, I>>::contains_key(loan_id) || Self::check_loan_in_liquidation(&loan_id) {
+                continue;
+            }
+            let loan = Self::get_loan_by_id(loan_id);
+            if let LoanHealth::Liquidating(ltv) =
+                Self::judge_loan(&loan, btc_price, liquidation_thd, warning_thd)
+            {
+                Self::start_liquidation(&loan, ltv, btc_price);
+            }
+        }
+
+        Ok(())
+    }
+
     fn liquidate_loan(loan_id: LoanId, liquidating_ltv: LTV) {
-        <Loans<T>>::mutate(loan_id, |v| {
+        <Loans<T, I>>::mutate(loan_id, |v| {
             v.status = LoanHealth::Liquidating(liquidating_ltv)
         });
-        if LiquidatingLoans::exists() {
-            LiquidatingLoans::mutate(|v| v.push(loan_id));
+        Self::unindex_loan(loan_id);
+        if <LiquidatingLoans<I>>::exists() {
+            <LiquidatingLoans<I>>::mutate(|v| v.push(loan_id));
         } else {
             let ll: Vec<LoanId> = vec![loan_id];
-            LiquidatingLoans::put(ll);
+            <LiquidatingLoans<I>>::put(ll);
         }
     }
 
     pub fn draw_from_loan(
         who: T::AccountId,
         loan_id: LoanId,
-        amount: T::Balance,
+        amount: BalanceOf<T, I>,
     ) -> DispatchResult {
-        ensure!(<Loans<T>>::contains_key(loan_id), "invalid loan id");
+        ensure!(Self::settlement_price().is_none(), Error::<T, I>::InSettlement);
+        ensure!(<Loans<T, I>>::contains_key(loan_id), "invalid loan id");
         let loan = Self::get_loan_by_id(loan_id);
         ensure!(loan.who == who, "can't draw from others loan");
+        ensure!(Self::is_pool_loan(loan_id), Error::<T, I>::FixedRateLoan);
+
+        Self::accrue_loan_interest(loan_id);
+        Self::add_loan_debt(loan_id, amount)?;
+        Self::index_loan(loan_id);
+
+        Self::deposit_event(RawEvent::LoanDrawn(loan_id, amount));
+
+        Ok(())
+    }
+
+    /// add "amount" to the debt of a pool loan within its available credit, no funds are moved
+    /// and the loan keeps its place in "LiquidationPriceIndex" until it is next judged
+    fn add_loan_debt(loan_id: LoanId, amount: BalanceOf<T, I>) -> DispatchResult {
+        let loan = Self::get_loan_by_id(loan_id);
+        let btc_price = Self::collateral_price()?;
+
+        let ltv_limit = Self::ltv_limit_of(&loan.who);
+        let available_credit = loan.collateral_balance_available
+            * BalanceOf::<T, I>::from(btc_price as u32)
+            * BalanceOf::<T, I>::from(ltv_limit as u32)
+            / BalanceOf::<T, I>::from(LTV_PREC)
+            / BalanceOf::<T, I>::from(PRICE_PREC);
+
+        ensure!(amount <= available_credit, "short of available credit");
+
+        <Loans<T, I>>::mutate(loan_id, |v| {
+            v.loan_balance_total = v.loan_balance_total + amount;
+        });
+
+        <Loans<T, I>>::mutate(loan_id, |v| {
+            v.collateral_balance_available =
+                v.collateral_balance_available - amount / BalanceOf::<T, I>::from(btc_price as u32);
+        });
+
+        <TotalLoan<T, I>>::mutate(|v| *v += amount);
+        Self::update_loan_rewards(loan_id, &loan.who);
+
+        Ok(())
+    }
+
+    pub fn merge_account_loans(who: T::AccountId, loan_ids: Vec<LoanId>) -> DispatchResult {
+        ensure!(loan_ids.len() >= 2, Error::<T, I>::NothingToMerge);
+        ensure!(
+            loan_ids.len() as u32 <= T::MaxMergeLoans::get(),
+            Error::<T, I>::TooManyLoansToMerge
+        );
+
+        let mut merged_ids = loan_ids.clone();
+        merged_ids.sort();
+        merged_ids.dedup();
+        ensure!(merged_ids.len() == loan_ids.len(), Error::<T, I>::DuplicatedLoanId);
+
+        for loan_id in loan_ids.iter() {
+            ensure!(<Loans<T, I>>::contains_key(loan_id), "invalid loan id");
+            ensure!(
+                !Self::check_loan_in_liquidation(loan_id),
+                "loan is in liquidation"
+            );
+            ensure!(Self::get_loan_by_id(loan_id).who == who, "not owner of the loan");
+            ensure!(
+                Self::loan_guarantees(loan_id).is_empty(),
+                Error::<T, I>::LoanGuaranteed
+            );
+            ensure!(Self::is_pool_loan(*loan_id), Error::<T, I>::FixedRateLoan);
+        }
+
+        let mut loans: Vec<Loan<T::AccountId, BalanceOf<T, I>>> = Vec::with_capacity(loan_ids.len());
+        for loan_id in loan_ids.iter() {
+            // the loans are merged with what they owe up to now
+            Self::accrue_loan_interest(*loan_id);
+            loans.push(Self::get_loan_by_id(loan_id));
+        }
+
+        let reserved = Self::collateral_reserved(loans[0].id);
+        ensure!(
+            loans.iter().all(|l| Self::collateral_reserved(l.id) == reserved),
+            Error::<T, I>::CollateralCustodyMismatch
+        );
+
+        let mut merged = loans[0].clone();
+        for loan in loans.iter().skip(1) {
+            merged.collateral_balance_original = merged
+                .collateral_balance_original
+                .checked_add(&loan.collateral_balance_original)
+                .ok_or(Error::<T, I>::Overflow)?;
+            merged.collateral_balance_available = merged
+                .collateral_balance_available
+                .checked_add(&loan.collateral_balance_available)
+                .ok_or(Error::<T, I>::Overflow)?;
+            merged.loan_balance_total = merged
+                .loan_balance_total
+                .checked_add(&loan.loan_balance_total)
+                .ok_or(Error::<T, I>::Overflow)?;
+        }
+
+        let btc_price = Self::collateral_price()?;
+
+        let ltv = <Loan<T::AccountId, BalanceOf<T, I>>>::get_ltv(
+            merged.collateral_balance_available,
+            merged.loan_balance_total,
+            btc_price,
+        );
+        ensure!(ltv < Self::ltv_limit_of(&who), Error::<T, I>::OverLTVLimit);
+        merged.status = LoanHealth::Well;
+
+        if reserved {
+            // the locks are released one by one, make sure the merged lock can be taken before
+            let locked = loans.iter().fold(Zero::zero(), |acc: BalanceOf<T, I>, l| {
+                acc.saturating_add(Self::collateral_lock(l.id).map(|(_, v)| v).unwrap_or_default())
+            });
+            ensure!(
+                T::Currency::free_balance(&Self::collateral_asset_id(), &who).saturating_add(locked)
+                    >= merged.collateral_balance_original,
+                "not enough collateral asset in free balance"
+            );
+            for loan in loans.iter().skip(1) {
+                Self::relock_collateral(loan.id, &who, Zero::zero())?;
+            }
+            Self::relock_collateral(merged.id, &who, merged.collateral_balance_original)?;
+        }
+
+        let removed_ids = loan_ids[1..].to_vec();
+        for loan_id in removed_ids.iter() {
+            <Loans<T, I>>::remove(loan_id);
+            Self::unindex_loan(*loan_id);
+            // the merged loan is as young as the youngest loan folded into it, an old loan
+            // can't lend its age to new debt
+            let opened_at = <LoanOpenedAt<T, I>>::take(loan_id);
+            <LoanOpenedAt<T, I>>::mutate(merged.id, |v| *v = cmp::max(*v, opened_at));
+            <LoanAccruedAt<T, I>>::remove(loan_id);
+            let interest_paid = <LoanInterestPaid<T, I>>::take(loan_id);
+            <LoanInterestPaid<T, I>>::mutate(merged.id, |v| *v = v.saturating_add(interest_paid));
+            let interest_accrued = <LoanInterestAccrued<T, I>>::take(loan_id);
+            <LoanInterestAccrued<T, I>>::mutate(merged.id, |v| *v = v.saturating_add(interest_accrued));
+        }
+        <LoansByAccount<T, I>>::mutate(&who, |v| {
+            *v = v
+                .clone()
+                .into_iter()
+                .filter(|ele| !removed_ids.contains(ele))
+                .collect::<Vec<LoanId>>();
+        });
+        <Loans<T, I>>::insert(merged.id, merged.clone());
+        Self::index_loan(merged.id);
+        for loan_id in loan_ids.iter() {
+            Self::count_loan_debt(*loan_id, &who);
+        }
+        Self::update_rewards(&who);
+
+        Self::deposit_event(RawEvent::LoansMerged(merged.id, removed_ids, ltv));
+
+        Ok(())
+    }
+
+    pub fn refinance_loan(
+        who: T::AccountId,
+        loan_id: LoanId,
+        collateral_amount: BalanceOf<T, I>,
+        loan_amount: BalanceOf<T, I>,
+    ) -> DispatchResult {
+        ensure!(Self::settlement_price().is_none(), Error::<T, I>::InSettlement);
+        ensure!(<Loans<T, I>>::contains_key(loan_id), "invalid loan id");
+        Self::accrue_loan_interest(loan_id);
+        let loan = Self::get_loan_by_id(loan_id);
+        ensure!(loan.who == who, "not owner of the loan");
+        ensure!(
+            !Self::check_loan_in_liquidation(&loan_id),
+            "loan is in liquidation"
+        );
+        ensure!(
+            Self::loan_guarantees(loan_id).is_empty(),
+            Error::<T, I>::LoanGuaranteed
+        );
+        ensure!(Self::is_pool_loan(loan_id), Error::<T, I>::FixedRateLoan);
+        ensure!(
+            !collateral_amount.is_zero() && !loan_amount.is_zero(),
+            Error::<T, I>::InvalidCollateralLoanAmounts
+        );
+        ensure!(
+            collateral_amount >= Self::minimum_collateral(),
+            "not reach min collateral amount"
+        );
+
+        // checks the new terms against the borrower's LTV limit
+        Self::get_collateral_loan(&who, collateral_amount, loan_amount)?;
+        let btc_price = Self::fresh_collateral_price()?;
+
+        let collateral_asset_id = Self::collateral_asset_id();
+        let collection_asset_id = Self::collection_asset_id();
+        let collection_account_id = Self::collection_account_id();
+        let loan_asset_id = Self::loan_asset_id();
+        let pawn_shop = Self::pawn_shop();
 
-        let collateral_asset_id = Self::collateral_asset_id();
-        let token = <generic_asset::Module<T>>::symbols(collateral_asset_id);
-        let current_price = <new_oracle::Module<T>>::current_price(&token);
-        let btc_price: u64 = TryInto::<u64>::try_into(current_price).unwrap_or(0);
+        if collateral_amount > loan.collateral_balance_original {
+            ensure!(
+                T::Currency::free_balance(&collateral_asset_id, &who)
+                    >= collateral_amount - loan.collateral_balance_original,
+                "not enough collateral asset in free balance"
+            );
+        } else if !Self::collateral_reserved(loan_id) {
+            ensure!(
+                T::Currency::free_balance(&collateral_asset_id, &pawn_shop)
+                    >= loan.collateral_balance_original - collateral_amount,
+                "not enough collateral asset in shop"
+            );
+        }
 
-        let global_ltv = Self::global_ltv_limit();
-        let available_credit = loan.collateral_balance_available
-            * T::Balance::from(btc_price as u32)
-            * T::Balance::from(global_ltv as u32)
-            / T::Balance::from(LTV_PREC)
-            / T::Balance::from(PRICE_PREC);
+        if loan_amount > loan.loan_balance_total {
+            let extra_loan = loan_amount - loan.loan_balance_total;
+            ensure!(
+                T::Currency::free_balance(&collection_asset_id, &collection_account_id)
+                    >= extra_loan + Self::queued_redeem_total(),
+                "Not enough to loan"
+            );
+            if let Some(cap) = Self::loan_cap() {
+                ensure!(Self::total_loan() + extra_loan <= cap, Error::<T, I>::ReachLoanCap);
+            }
+        } else {
+            ensure!(
+                T::Currency::free_balance(&loan_asset_id, &who)
+                    >= loan.loan_balance_total - loan_amount,
+                "not enough asset to repay"
+            );
+        }
 
-        ensure!(amount <= available_credit, "short of available credit");
+        let price = <BalanceOf<T, I> as TryFrom<u128>>::try_from(btc_price as u128)
+            .map_err(|_| Error::<T, I>::Overflow)?;
+        ensure!(!price.is_zero(), Error::<T, I>::PriceNotAvailable);
+        let collateral_balance_available = collateral_amount
+            .checked_sub(&(loan_amount / price))
+            .ok_or(Error::<T, I>::OverLTVLimit)?;
+        let total_loan = Self::total_loan()
+            .checked_sub(&loan.loan_balance_total)
+            .and_then(|v| v.checked_add(&loan_amount))
+            .ok_or(Error::<T, I>::Overflow)?;
+        let total_collateral = Self::total_collateral()
+            .checked_sub(&loan.collateral_balance_original)
+            .and_then(|v| v.checked_add(&collateral_amount))
+            .ok_or(Error::<T, I>::Overflow)?;
+
+        if collateral_amount > loan.collateral_balance_original {
+            Self::hold_collateral(&loan, &who, collateral_amount - loan.collateral_balance_original)?;
+        } else if collateral_amount < loan.collateral_balance_original {
+            Self::release_collateral(&loan, loan.collateral_balance_original - collateral_amount)?;
+        }
 
-        <Loans<T>>::mutate(loan_id, |v| {
-            v.loan_balance_total = v.loan_balance_total + amount;
-        });
+        let moved = if loan_amount > loan.loan_balance_total {
+            T::Currency::transfer(
+                &collection_asset_id,
+                &collection_account_id,
+                &who,
+                loan_amount - loan.loan_balance_total,
+            )
+        } else if loan_amount < loan.loan_balance_total {
+            T::Currency::transfer(
+                &loan_asset_id,
+                &who,
+                &collection_account_id,
+                loan.loan_balance_total - loan_amount,
+            )
+        } else {
+            Ok(())
+        };
+        // nothing has been written yet, so putting the collateral back is enough
+        if let Err(e) = moved {
+            if collateral_amount > loan.collateral_balance_original {
+                Self::release_collateral(&loan, collateral_amount - loan.collateral_balance_original)?;
+            } else if collateral_amount < loan.collateral_balance_original {
+                Self::hold_collateral(&loan, &who, loan.collateral_balance_original - collateral_amount)?;
+            }
+            return Err(e);
+        }
 
-        <Loans<T>>::mutate(loan_id, |v| {
-            v.collateral_balance_available =
-                v.collateral_balance_available - amount / T::Balance::from(btc_price as u32);
+        <Loans<T, I>>::mutate(loan_id, |v| {
+            v.collateral_balance_original = collateral_amount;
+            v.collateral_balance_available = collateral_balance_available;
+            v.loan_balance_total = loan_amount;
+            v.status = LoanHealth::Well;
         });
+        // a smaller debt than the accrued interest means part of it was paid
+        <LoanInterestAccrued<T, I>>::mutate(loan_id, |v| *v = cmp::min(*v, loan_amount));
+        Self::index_loan(loan_id);
 
-        <TotalLoan<T>>::mutate(|v| *v += amount);
+        <TotalLoan<T, I>>::put(total_loan);
+        <TotalCollateral<T, I>>::put(total_collateral);
+        Self::update_loan_rewards(loan_id, &loan.who);
 
-        Self::deposit_event(RawEvent::LoanDrawn(loan_id, amount));
+        Self::deposit_event(RawEvent::LoanRefinanced(
+            loan_id,
+            collateral_amount,
+            loan_amount,
+        ));
 
         Ok(())
     }
 
-    fn _pause(linum: u32) {
-        Paused::mutate(|v| {
-            *v = true;
-        });
-        Self::deposit_event(RawEvent::Paused(
-            linum,
-            <frame_system::Module<T>>::block_number(),
-            <frame_system::Module<T>>::extrinsic_index().unwrap(),
+    /// pause "operation", recording why and in which block
+    fn _pause(operation: Operation, reason: Vec<u8>) {
+        let block_number = <frame_system::Module<T>>::block_number();
+        <PausedOperations<T, I>>::insert(
+            operation,
+            PauseRecord {
+                reason: reason.clone(),
+                block_number,
+            },
+        );
+        Self::deposit_event(RawEvent::OperationPaused(
+            operation,
+            reason,
+            block_number,
+            <frame_system::Module<T>>::extrinsic_index().unwrap_or_default(),
         ));
     }
 
-    fn on_each_block(_height: T::BlockNumber) {
-        let collateral_asset_id = Self::collateral_asset_id();
+    /// whether "operation" is stopped, the module level "Paused" stops every operation
+    /// which doesn't run when paused
+    pub fn operation_paused(operation: Operation) -> bool {
+        (Self::paused() && !operation.runs_when_paused())
+            || <PausedOperations<T, I>>::contains_key(operation)
+    }
+
+    fn ensure_operation(operation: Operation) -> DispatchResult {
+        ensure!(
+            !Self::paused() || operation.runs_when_paused(),
+            "module is paused"
+        );
+        ensure!(
+            !<PausedOperations<T, I>>::contains_key(operation),
+            Error::<T, I>::OperationPaused
+        );
+        Ok(())
+    }
+
+    /// judges loans whose break-even price says they may be liquidating, then goes on from
+    /// "ScanCursor" until "ScanLimit" loans have been judged, and up to "ScanLimit" accounts in
+    /// account-level margin from "MarginAccountCursor". Loans found liquidating are only moved
+    /// into liquidation when "liquidate" is set, returns the weight used
+    fn on_each_block(_height: T::BlockNumber, liquidate: bool) -> Weight {
         let liquidation_thd = Self::global_liquidation_threshold();
         let warning_thd = Self::global_warning_threshold();
 
-        let token = <generic_asset::Module<T>>::symbols(collateral_asset_id);
-        let current_price = <new_oracle::Module<T>>::current_price(&token);
-        let btc_price: u64 = TryInto::<u64>::try_into(current_price).unwrap_or(0);
+        // without a price no loan can be judged, a missing price must never read as zero
+        let btc_price = match Self::collateral_price() {
+            Ok(price) => price,
+            Err(_) => return SCAN_BASE_WEIGHT,
+        };
 
-        for (loan_id, loan) in <Loans<T>>::enumerate() {
-            if Self::check_loan_in_liquidation(&loan_id) {
-                continue;
+        let limit = Self::scan_limit();
+        let mut checked: u32 = 0;
+        let mut reads: u32 = 0;
+
+        // loans past the liquidation threshold are of no use when they can't be liquidated here
+        if liquidate {
+            // LTV >= threshold <=> break-even price >= price * threshold
+            let at_risk_from = (btc_price as u128) * (liquidation_thd as u128) / (LTV_PREC as u128);
+            let floor = Self::price_bucket(
+                TryInto::<PriceInUSDT>::try_into(at_risk_from).unwrap_or(PriceInUSDT::max_value()),
+            );
+
+            // buckets emptied since the last scan are dropped from the top first
+            let mut bucket = Self::top_price_bucket();
+            while bucket > floor && reads < limit && Self::loans_in_price_bucket(bucket).is_empty() {
+                reads += 1;
+                bucket -= 1;
+            }
+            <TopPriceBucket<I>>::put(bucket);
+
+            while bucket >= floor && checked < limit && reads < limit {
+                reads += 1;
+                // a judged loan is put back at the end of its bucket, the others get their turn next
+                for loan_id in Self::loans_in_price_bucket(bucket)
+                    .into_iter()
+                    .take(limit.saturating_sub(checked) as usize)
+                {
+                    Self::check_loan(loan_id, btc_price, liquidation_thd, warning_thd, liquidate);
+                    checked += 1;
+                }
+                if bucket == 0 {
+                    break;
+                }
+                bucket -= 1;
             }
+        }
 
-            match Self::check_loan_health(&loan, btc_price, liquidation_thd, warning_thd) {
-                LoanHealth::Well => {}
+        let next_loan_id = Self::next_loan_id();
+        let mut cursor = Self::scan_cursor();
+        let rounds = cmp::min(limit.saturating_sub(checked) as u64, next_loan_id);
+        for _ in 0..rounds {
+            if cursor >= next_loan_id {
+                cursor = 0;
+            }
+            Self::check_loan(cursor, btc_price, liquidation_thd, warning_thd, liquidate);
+            cursor += 1;
+            checked += 1;
+        }
+        <ScanCursor<I>>::put(cursor);
+
+        // accounts have a budget of their own, loans at risk can't starve them
+        let count = Self::margin_account_count();
+        let mut position = Self::margin_account_cursor();
+        for _ in 0..cmp::min(limit, count) {
+            if position >= count {
+                position = 0;
+            }
+            let who = Self::margin_account(position);
+            position += 1;
+            checked += 1;
+            let who = match who {
+                Some(who) => who,
+                None => continue,
+            };
+            let health = Self::check_account_health(&who, btc_price, liquidation_thd, warning_thd);
+            let last_health = <AccountHealth<T, I>>::get(&who);
+            <AccountHealth<T, I>>::insert(&who, health.clone());
+            match health {
+                LoanHealth::Well
+                | LoanHealth::Repaid
+                | LoanHealth::Liquidated
+                | LoanHealth::Defaulted => {}
                 LoanHealth::Warning(ltv) => {
-                    if loan.status != LoanHealth::Warning(ltv) {
-                        <Loans<T>>::mutate(&loan.id, |v| v.status = LoanHealth::Warning(ltv));
-                        Self::deposit_event(RawEvent::Warning(loan_id, ltv));
+                    for loan_id in Self::loans_by_account(&who).iter() {
+                        if Self::check_loan_in_liquidation(loan_id) {
+                            continue;
+                        }
+                        <Loans<T, I>>::mutate(loan_id, |v| v.status = LoanHealth::Warning(ltv));
+                    }
+                    // a position staying in warning is only reported when it enters it
+                    match last_health {
+                        LoanHealth::Warning(_) => {}
+                        _ => Self::deposit_event(RawEvent::AccountWarning(who, ltv)),
                     }
                 }
-
+                LoanHealth::Liquidating(_) if !liquidate => {}
                 LoanHealth::Liquidating(l) => {
-                    Self::liquidate_loan(loan_id, l);
-                    Self::deposit_event(RawEvent::Liquidating(
-                        loan_id,
-                        loan.who.clone(),
-                        loan.collateral_balance_available,
-                        loan.loan_balance_total,
-                    ));
+                    for loan_id in Self::loans_by_account(&who).iter() {
+                        if Self::check_loan_in_liquidation(loan_id) || !Self::is_pool_loan(*loan_id) {
+                            continue;
+                        }
+                        Self::start_liquidation(&Self::get_loan_by_id(loan_id), l, btc_price);
+                    }
+                    Self::deposit_event(RawEvent::AccountLiquidating(who, l));
+                }
+            }
+        }
+        <MarginAccountCursor<I>>::put(position);
+
+        SCAN_BASE_WEIGHT
+            .saturating_add(LOAN_CHECK_WEIGHT.saturating_mul(checked as Weight))
+            .saturating_add(INDEX_READ_WEIGHT.saturating_mul(reads as Weight))
+    }
+
+    /// judge one loan and refresh its entry in "LiquidationPriceIndex"
+    fn check_loan(
+        loan_id: LoanId,
+        btc_price: u64,
+        liquidation_thd: LTV,
+        warning_thd: LTV,
+        liquidate: bool,
+    ) {
+        if !<Loans<T, I>>::contains_key(loan_id) || Self::check_loan_in_liquidation(&loan_id) {
+            Self::unindex_loan(loan_id);
+            return;
+        }
+        Self::index_loan(loan_id);
+
+        let loan = Self::get_loan_by_id(loan_id);
+        // judged as a whole position with the account
+        if Self::account_margin_mode(&loan.who) && Self::is_pool_loan(loan_id) {
+            return;
+        }
+
+        match Self::check_loan_health(&loan, btc_price, liquidation_thd, warning_thd) {
+            LoanHealth::Well
+            | LoanHealth::Repaid
+            | LoanHealth::Liquidated
+            | LoanHealth::Defaulted => {}
+            LoanHealth::Warning(ltv) => {
+                if loan.status != LoanHealth::Warning(ltv) {
+                    <Loans<T, I>>::mutate(&loan.id, |v| v.status = LoanHealth::Warning(ltv));
+                    Self::deposit_event(RawEvent::Warning(loan_id, ltv));
                 }
             }
+            LoanHealth::Liquidating(l) => {
+                if liquidate {
+                    Self::start_liquidation(&loan, l, btc_price);
+                }
+            }
+        }
+    }
+
+    /// price at which the debt of "loan" equals its collateral
+    fn break_even_price(loan: &Loan<T::AccountId, BalanceOf<T, I>>) -> PriceInUSDT {
+        let (collateral, debt) = Self::guaranteed_position(loan);
+        let collateral = TryInto::<u128>::try_into(collateral).unwrap_or(0);
+        let debt = TryInto::<u128>::try_into(debt).unwrap_or(u128::max_value());
+        if debt.is_zero() {
+            return 0;
+        }
+        if collateral.is_zero() {
+            return PriceInUSDT::max_value();
+        }
+        TryInto::<PriceInUSDT>::try_into(debt.saturating_mul(PRICE_PREC as u128) / collateral)
+            .unwrap_or(PriceInUSDT::max_value())
+    }
+
+    /// bucket of "LiquidationPriceIndex" for a break-even price, 16 buckets per doubling of the price
+    /// so that the loans of one bucket are within about 6% of each other
+    fn price_bucket(price: PriceInUSDT) -> u32 {
+        if price < 16 {
+            return price as u32;
+        }
+        let bits = 64 - price.leading_zeros();
+        let mantissa = ((price >> (bits - 5)) & 0xf) as u32;
+        (bits - 4) * 16 + mantissa
+    }
+
+    /// (re)place "loan_id" in "LiquidationPriceIndex", loans gone, in liquidating or judged with
+    /// their account are dropped
+    fn index_loan(loan_id: LoanId) {
+        Self::unindex_loan(loan_id);
+        if !<Loans<T, I>>::contains_key(loan_id) || Self::check_loan_in_liquidation(&loan_id) {
+            return;
+        }
+        let loan = Self::get_loan_by_id(loan_id);
+        if Self::account_margin_mode(&loan.who) && Self::is_pool_loan(loan_id) {
+            return;
+        }
+
+        let break_even = Self::break_even_price(&loan);
+        let bucket = Self::price_bucket(break_even);
+        <LiquidationPriceIndex<I>>::mutate(bucket, |v| v.push(loan_id));
+        <TopPriceBucket<I>>::mutate(|v| *v = cmp::max(*v, bucket));
+        <LoanBreakEvenPrice<I>>::insert(loan_id, break_even);
+    }
+
+    fn unindex_loan(loan_id: LoanId) {
+        if let Some(break_even) = <LoanBreakEvenPrice<I>>::take(loan_id) {
+            let bucket = Self::price_bucket(break_even);
+            let mut loan_ids = Self::loans_in_price_bucket(bucket);
+            loan_ids.retain(|id| *id != loan_id);
+            if loan_ids.is_empty() {
+                <LiquidationPriceIndex<I>>::remove(bucket);
+            } else {
+                <LiquidationPriceIndex<I>>::insert(bucket, loan_ids);
+            }
+        }
+    }
+
+    fn add_margin_account(who: &T::AccountId) {
+        if <MarginAccountPosition<T, I>>::contains_key(who) {
+            return;
+        }
+        let count = Self::margin_account_count();
+        <MarginAccounts<T, I>>::insert(count, who);
+        <MarginAccountPosition<T, I>>::insert(who, count);
+        <MarginAccountCount<I>>::put(count + 1);
+    }
+
+    /// the last account takes the place of the removed one
+    fn remove_margin_account(who: &T::AccountId) {
+        let position = match <MarginAccountPosition<T, I>>::take(who) {
+            Some(position) => position,
+            None => return,
+        };
+        let last = Self::margin_account_count().saturating_sub(1);
+        match <MarginAccounts<T, I>>::take(last) {
+            Some(moved) if position != last => {
+                <MarginAccounts<T, I>>::insert(position, &moved);
+                <MarginAccountPosition<T, I>>::insert(&moved, position);
+            }
+            _ => {}
         }
+        <MarginAccountCount<I>>::put(last);
     }
 
-    fn calculate_loan_interest_rate() {
+    /// returns the weight used
+    fn calculate_loan_interest_rate() -> Weight {
+        let mut charged: Weight = 0;
         let collection_asset_id = Self::collection_asset_id();
         let collection_account_id = Self::collection_account_id();
         let total_loan = Self::total_loan();
         let total_loan = TryInto::<u128>::try_into(total_loan).ok().unwrap();
 
         let total_deposit =
-            <generic_asset::Module<T>>::free_balance(&collection_asset_id, &collection_account_id)
+            T::Currency::free_balance(&collection_asset_id, &collection_account_id)
                 + Self::total_loan();
         let total_deposit = TryInto::<u128>::try_into(total_deposit).ok().unwrap();
 
         let current_time = <timestamp::Module<T>>::get();
-        <BonusTime<T>>::put(current_time);
+        // read before it is moved on, otherwise the pool figures below always cover no time
+        let last_bonus_time: T::Moment = Self::bonus_time();
+        <BonusTime<T, I>>::put(current_time);
 
         if !(total_deposit + total_loan).is_zero() {
             let utilization_rate_x = total_loan
@@ -1059,59 +4180,612 @@ impl<T: Trait> Module<T> {
                 (20 * utilization_rate_x * 10_u128.pow(8)) / 10_u128.pow(2)
             };
 
-            let loan_interest_rate_current: T::Balance =
+            let loan_interest_rate_current: BalanceOf<T, I> =
                 TryFrom::<u128>::try_from(loan_interest_rate_current)
                     .ok()
                     .unwrap();
 
-            let last_bonus_time: T::Moment = Self::bonus_time();
+            let time_duration =
+                TryInto::<u64>::try_into(current_time - last_bonus_time).unwrap_or(u64::max_value());
+            let rate = TryInto::<u128>::try_into(loan_interest_rate_current).unwrap_or(0);
+
+            // interest the pool loans were charged over "time_duration" and the annual rate it
+            // makes for savers, both only reported
+            let interest_generated = (time_duration as u128)
+                .saturating_mul(total_loan)
+                .saturating_mul(rate)
+                / (SEC_PER_DAY as u128)
+                / (DAYS_PER_YEAR as u128)
+                / (INTEREST_RATE_PREC as u128);
+            let interest_generated: BalanceOf<T, I> =
+                TryFrom::<u128>::try_from(interest_generated).unwrap_or_else(|_| BalanceOf::<T, I>::max_value());
+
+            <LoanInterestRateCurrent<T, I>>::put(loan_interest_rate_current);
+
+            let current_interest_rate = total_loan.saturating_mul(rate) / total_deposit;
+            let current_interest_rate: BalanceOf<T, I> =
+                TryFrom::<u128>::try_from(current_interest_rate).unwrap_or_else(|_| BalanceOf::<T, I>::max_value());
+
+            <SavingInterestRate<T, I>>::put(current_interest_rate);
+
+            Self::deposit_event(RawEvent::InterestAccrued(
+                interest_generated,
+                loan_interest_rate_current,
+                current_interest_rate,
+            ));
+        }
 
-            let time_duration = TryInto::<u32>::try_into(current_time - last_bonus_time)
-                .ok()
-                .unwrap();
-            let total_loan: T::Balance = TryFrom::<u128>::try_from(total_loan).ok().unwrap();
+        // loans are charged for the time since they were last charged, a few per block,
+        // fixed-rate loans also when the pool itself is empty
+        let next_loan_id = Self::next_loan_id();
+        let mut cursor = Self::interest_cursor();
+        for _ in 0..cmp::min(Self::scan_limit() as u64, next_loan_id) {
+            if cursor >= next_loan_id {
+                cursor = 0;
+            }
+            let loan_id = cursor;
+            cursor += 1;
+            charged += 1;
+            Self::accrue_loan_interest(loan_id);
+        }
+        <InterestCursor<I>>::put(cursor);
 
-            let interest_generated = T::Balance::from(time_duration)
-                * total_loan
-                * loan_interest_rate_current
-                / T::Balance::from(SEC_PER_DAY)
-                / T::Balance::from(DAYS_PER_YEAR);
+        SCAN_BASE_WEIGHT.saturating_add(LOAN_CHECK_WEIGHT.saturating_mul(charged))
+    }
 
-            let profit_pool = Self::profit_pool();
-            let profit_asset = Self::profit_asset_id();
+    /// charge a loan the interest of the time since "LoanAccruedAt", a pool loan at "LoanInterestRateCurrent"
+    /// and a fixed-rate loan at the rate of its terms
+    fn accrue_loan_interest(loan_id: LoanId) {
+        if !<Loans<T, I>>::contains_key(loan_id) || Self::settlement_price().is_some() {
+            return;
+        }
+        let now = <timestamp::Module<T>>::get();
+        let accrued_at = match Self::loan_accrued_at(loan_id) {
+            Some(accrued_at) => accrued_at,
+            None => {
+                <LoanAccruedAt<T, I>>::insert(loan_id, now);
+                return;
+            }
+        };
+        if now <= accrued_at {
+            return;
+        }
 
-            for (loan_id, loan) in <Loans<T>>::enumerate() {
-                let amount = interest_generated * loan.loan_balance_total
-                    / (total_loan * T::Balance::from(10_u32.pow(8)));
+        let loan = Self::get_loan_by_id(loan_id);
+        let elapsed = TryInto::<u64>::try_into(now - accrued_at).unwrap_or(u64::max_value());
+        let balance = TryInto::<u128>::try_into(loan.loan_balance_total).unwrap_or(u128::max_value());
+        let terms = Self::fixed_rate_loan(loan_id);
+        let rate = match terms {
+            Some(ref terms) => terms.rate as u128,
+            None => TryInto::<u128>::try_into(Self::loan_interest_rate_current()).unwrap_or(0),
+        };
+        let amount = (elapsed as u128)
+            .checked_mul(balance)
+            .and_then(|v| v.checked_mul(rate))
+            .map(|v| {
+                v / (SEC_PER_DAY as u128) / (DAYS_PER_YEAR as u128) / (INTEREST_RATE_PREC as u128)
+            })
+            .and_then(|v| <BalanceOf<T, I> as TryFrom<u128>>::try_from(v).ok());
+        let amount = match amount {
+            Some(amount) => amount,
+            // left for the next time the loan is touched
+            None => return,
+        };
+        <LoanAccruedAt<T, I>>::insert(loan_id, now);
 
-                Self::draw_from_loan(loan.who.clone(), loan_id, amount).unwrap_or_default();
+        if terms.is_some() {
+            // owed to the lender on repayment, nothing is moved now
+            if amount.is_zero() {
+                return;
+            }
+            <Loans<T, I>>::mutate(loan_id, |v| {
+                v.loan_balance_total = v.loan_balance_total.saturating_add(amount)
+            });
+            <InterestPaid<T, I>>::mutate(&loan.who, |v| *v = v.saturating_add(amount));
+            <LoanInterestPaid<T, I>>::mutate(loan_id, |v| *v = v.saturating_add(amount));
+            <LoanInterestAccrued<T, I>>::mutate(loan_id, |v| *v = v.saturating_add(amount));
+            return;
+        }
 
-                <generic_asset::Module<T>>::make_transfer_with_event(
-                    &collection_asset_id,
-                    &loan.who,
-                    &collection_account_id,
-                    amount,
-                )
-                .unwrap_or_default();
+        let amount = match Self::credit_tier_of(&loan.who) {
+            Some(tier) => {
+                amount * BalanceOf::<T, I>::from(100 - tier.rate_discount) / BalanceOf::<T, I>::from(100)
+            }
+            None => amount,
+        };
+        if amount.is_zero() {
+            return;
+        }
 
-                <TotalDtoken<T>>::mutate(|v| {
-                    *v = v.checked_add(&amount).expect("Overflow of market dtoken");
-                });
+        let drawn = Self::add_loan_debt(loan_id, amount).is_ok();
+        if drawn {
+            <LoanInterestAccrued<T, I>>::mutate(loan_id, |v| *v = v.saturating_add(amount));
+        }
+
+        // only what actually reached the collection account is paid and owed to savers
+        let collection_asset_id = Self::collection_asset_id();
+        let collection_account_id = Self::collection_account_id();
+        if T::Currency::transfer(&collection_asset_id, &loan.who, &collection_account_id, amount)
+            .is_err()
+        {
+            return;
+        }
+        if drawn {
+            <InterestPaid<T, I>>::mutate(&loan.who, |v| *v = v.saturating_add(amount));
+            <LoanInterestPaid<T, I>>::mutate(loan_id, |v| *v = v.saturating_add(amount));
+        }
+
+        <TotalDtoken<T, I>>::mutate(|v| *v = v.saturating_add(amount));
+    }
+
+    /// cumulative interest figures of "who", see "InterestSummary"
+    pub fn interest_summary(who: T::AccountId) -> InterestSummary<BalanceOf<T, I>, T::Moment> {
+        let saving_principal = Self::saving_principal(&who);
+        let saving_value = Self::shares_to_amount(Self::user_dtoken(&who)).unwrap_or(saving_principal);
+
+        InterestSummary {
+            interest_earned: Self::interest_earned_realised(&who)
+                .saturating_add(saving_value.saturating_sub(saving_principal)),
+            interest_paid: Self::interest_paid(&who),
+            saving_principal,
+            saving_value,
+            now: <timestamp::Module<T>>::get(),
+        }
+    }
+
+    /// price of the collateral asset in "PRICE_PREC"
+    pub fn collateral_price() -> result::Result<PriceInUSDT, DispatchError> {
+        Self::collateral_price_round().map(|(value, _)| value)
+    }
+
+    /// price of the collateral asset in "PRICE_PREC" and the block it was produced at
+    fn collateral_price_round() -> result::Result<(PriceInUSDT, T::BlockNumber), DispatchError> {
+        if let Some(price) = Self::settlement_price() {
+            return Ok((price, <frame_system::Module<T>>::block_number()));
+        }
+        let price = T::PriceProvider::price(Self::collateral_asset_id())?;
+        ensure!(!price.scale.is_zero(), Error::<T, I>::PriceNotAvailable);
+        let value = (price.value as u128) * (PRICE_PREC as u128) / (price.scale as u128);
+        let value = TryInto::<PriceInUSDT>::try_into(value).map_err(|_| Error::<T, I>::Overflow)?;
+        ensure!(!value.is_zero(), Error::<T, I>::PriceNotAvailable);
+        Ok((value, price.block_number))
+    }
+
+    /// same as "collateral_price" but refuses a price older than "MaxPriceAge"
+    pub fn fresh_collateral_price() -> result::Result<PriceInUSDT, DispatchError> {
+        let (value, produced_at) = Self::collateral_price_round()?;
+        let max_age = Self::max_price_age();
+        if !max_age.is_zero() {
+            let now = <frame_system::Module<T>>::block_number();
+            ensure!(
+                now.saturating_sub(produced_at) <= max_age,
+                Error::<T, I>::PriceStale
+            );
+        }
+        Ok(value)
+    }
+
+    /// compare a new aggregation round with the previous one and suspend liquidations when
+    /// the price moved more than "MaxPriceJump"
+    fn track_price_round() {
+        let (value, produced_at) = match Self::collateral_price_round() {
+            Ok(round) => round,
+            Err(_) => return,
+        };
+        let last = Self::last_price_round();
+        if let Some((_, last_produced_at)) = last {
+            if last_produced_at == produced_at {
+                return;
+            }
+        }
+        <LastPriceRound<T, I>>::put((value, produced_at));
+
+        let max_jump = Self::max_price_jump();
+        if let Some((last_value, _)) = last {
+            if max_jump.is_zero() || last_value.is_zero() {
+                return;
+            }
+            let moved = if value > last_value {
+                value - last_value
+            } else {
+                last_value - value
+            };
+            if (moved as u128) * 100 > (last_value as u128) * (max_jump as u128) {
+                <UnconfirmedPrice<I>>::put(value);
+                Self::deposit_event(RawEvent::PriceJumped(last_value, value));
+            }
+        }
+    }
+
+    fn settle_loan_at_fixed_price(loan_id: LoanId) -> DispatchResult {
+        let price = Self::settlement_price().ok_or(Error::<T, I>::NotInSettlement)?;
+        ensure!(<Loans<T, I>>::contains_key(loan_id), "loan doesn't exists");
+        let loan = Self::seize_guarantees(loan_id, price, LTV::from(LTV_PREC));
+        Self::release_guarantees(loan_id);
+
+        let price_in_balance = <BalanceOf<T, I> as TryFrom<u128>>::try_from(price as u128)
+            .map_err(|_| Error::<T, I>::Overflow)?;
+        let debt_in_collateral = loan
+            .loan_balance_total
+            .checked_mul(&BalanceOf::<T, I>::from(PRICE_PREC))
+            .ok_or(Error::<T, I>::Overflow)?
+            / price_in_balance;
+        let kept = cmp::min(debt_in_collateral, loan.collateral_balance_original);
+        let returned = loan.collateral_balance_original - kept;
+
+        if !returned.is_zero() {
+            Self::release_collateral(&loan, returned)?;
+        }
+        // what is kept is paid out of "PawnShop" by "settlement_redeem"
+        if Self::collateral_reserved(loan_id) && !kept.is_zero() {
+            Self::seize_collateral(&loan, &Self::pawn_shop(), kept)?;
+        }
+
+        <Loans<T, I>>::remove(loan_id);
+        Self::unindex_loan(loan_id);
+        // nothing is left for the liquidation account to mark
+        <LiquidatingLoans<I>>::mutate(|v| v.retain(|ele| *ele != loan_id));
+        <PartialLiquidations<T, I>>::remove(loan_id);
+        <LoansByAccount<T, I>>::mutate(&loan.who, |v| v.retain(|ele| *ele != loan_id));
+        <TotalCollateral<T, I>>::mutate(|v| {
+            *v = v.saturating_sub(loan.collateral_balance_original)
+        });
+        // the collateral kept from a fixed-rate loan is the lender's, not the savers'
+        match <FixedRateLoans<T, I>>::take(loan_id) {
+            Some(terms) => {
+                T::Currency::transfer(
+                    &Self::collateral_asset_id(),
+                    &Self::pawn_shop(),
+                    &terms.lender,
+                    kept,
+                )?;
+            }
+            None => {
+                <TotalLoan<T, I>>::mutate(|v| *v = v.saturating_sub(loan.loan_balance_total));
+                <SettlementCollateral<T, I>>::mutate(|v| *v += kept);
+            }
+        }
+
+        // the kept collateral is what liquidating the loan at the settlement price brought in
+        let proceeds = kept
+            .checked_mul(&price_in_balance)
+            .ok_or(Error::<T, I>::Overflow)?
+            / BalanceOf::<T, I>::from(PRICE_PREC);
+        let status = if debt_in_collateral > loan.collateral_balance_original {
+            LoanHealth::Defaulted
+        } else {
+            LoanHealth::Liquidated
+        };
+        Self::archive_loan(&loan, status, cmp::min(proceeds, loan.loan_balance_total));
+        Self::update_loan_rewards(loan_id, &loan.who);
+
+        Self::deposit_event(RawEvent::LoanSettled(loan_id, loan.who, kept, returned));
+        Ok(())
+    }
+
+    fn make_settlement_redeem(who: &T::AccountId) -> DispatchResult {
+        let price = Self::settlement_price().ok_or(Error::<T, I>::NotInSettlement)?;
+
+        let held = Self::user_dtoken(who);
+        ensure!(!held.is_zero(), "not enough dtoken");
+        let market_dtoken = Self::market_dtoken();
+        ensure!(market_dtoken >= held, "market dtoken is short");
+
+        let collection_asset_id = Self::collection_asset_id();
+        let collection_account_id = Self::collection_account_id();
+        let collateral_asset_id = Self::collateral_asset_id();
+
+        let pro_rata = |total: BalanceOf<T, I>| -> result::Result<BalanceOf<T, I>, DispatchError> {
+            Ok(total.checked_mul(&held).ok_or(Error::<T, I>::Overflow)? / market_dtoken)
+        };
+        let cash = T::Currency::free_balance(&collection_asset_id, &collection_account_id);
+        let amount = pro_rata(cash)?;
+        let collateral = pro_rata(Self::settlement_collateral())?;
+
+        // fixed-rate loans are between borrowers and lenders, only pool loans owe the savers.
+        // What they still owe stays with the savers, so only the shares worth the settled part
+        // paid out now are burnt, rounded up in favour of those who stay
+        let outstanding = Self::total_loan();
+        let shares = if outstanding.is_zero() {
+            held
+        } else {
+            let price_in_balance = <BalanceOf<T, I> as TryFrom<u128>>::try_from(price as u128)
+                .map_err(|_| Error::<T, I>::Overflow)?;
+            let settled = Self::settlement_collateral()
+                .checked_mul(&price_in_balance)
+                .ok_or(Error::<T, I>::Overflow)?
+                / BalanceOf::<T, I>::from(PRICE_PREC);
+            let settled = cash.checked_add(&settled).ok_or(Error::<T, I>::Overflow)?;
+            let total = settled
+                .checked_add(&outstanding)
+                .ok_or(Error::<T, I>::Overflow)?;
+            let burnt = held
+                .checked_mul(&settled)
+                .ok_or(Error::<T, I>::Overflow)?
+                .checked_add(&(total - BalanceOf::<T, I>::from(1u32)))
+                .ok_or(Error::<T, I>::Overflow)?
+                / total;
+            cmp::min(burnt, held)
+        };
+        ensure!(!shares.is_zero(), "not enough dtoken");
+        let value = Self::total_dtoken()
+            .checked_mul(&shares)
+            .ok_or(Error::<T, I>::Overflow)?
+            / market_dtoken;
+        let principal = Self::saving_principal(who)
+            .checked_mul(&shares)
+            .ok_or(Error::<T, I>::Overflow)?
+            / held;
+
+        T::Currency::transfer(&collection_asset_id, &collection_account_id, who, amount)?;
+        if !collateral.is_zero() {
+            T::Currency::transfer(&collateral_asset_id, &Self::pawn_shop(), who, collateral)?;
+        }
+
+        if shares == held {
+            <UserDtoken<T, I>>::remove(who);
+            <SavingPrincipal<T, I>>::remove(who);
+        } else {
+            <UserDtoken<T, I>>::insert(who, held - shares);
+            <SavingPrincipal<T, I>>::mutate(who, |v| *v = v.saturating_sub(principal));
+        }
+        <MarketDtoken<T, I>>::mutate(|v| *v -= shares);
+        <TotalDtoken<T, I>>::mutate(|v| *v = v.saturating_sub(value));
+        <SettlementCollateral<T, I>>::mutate(|v| *v -= collateral);
+        Self::update_rewards(who);
+
+        Self::deposit_event(RawEvent::SettlementRedeemed(
+            who.clone(),
+            shares,
+            amount,
+            collateral,
+        ));
+        Ok(())
+    }
+
+    /// add this block's emission to the reward indices, the share of a side nobody is on is not emitted
+    fn emit_rewards() {
+        let reward = Self::reward_per_block();
+        if reward.is_zero() {
+            return;
+        }
+        let saver_reward = reward * BalanceOf::<T, I>::from(Self::saver_reward_share()) / BalanceOf::<T, I>::from(100u32);
+        let borrower_reward = reward - saver_reward;
+        let (total_shares, total_debt) = Self::total_reward_weights();
+        let prec = BalanceOf::<T, I>::from(REWARD_INDEX_PREC);
+        <RewardIndex<T, I>>::mutate(|(saver_index, borrower_index)| {
+            if !total_shares.is_zero() {
+                *saver_index = saver_index.saturating_add(saver_reward.saturating_mul(prec) / total_shares);
+            }
+            if !total_debt.is_zero() {
+                *borrower_index =
+                    borrower_index.saturating_add(borrower_reward.saturating_mul(prec) / total_debt);
+            }
+        });
+    }
+
+    /// bring "AccountDebt" of the owner of a loan in line with what the loan owes now, a closed
+    /// loan owes nothing and fixed-rate loans earn no rewards
+    fn count_loan_debt(loan_id: LoanId, who: &T::AccountId) {
+        let debt = if <Loans<T, I>>::contains_key(loan_id) && Self::is_pool_loan(loan_id) {
+            Self::get_loan_by_id(loan_id).loan_balance_total
+        } else {
+            Zero::zero()
+        };
+        let counted = <RewardedLoanDebt<T, I>>::get(loan_id);
+        if debt == counted {
+            return;
+        }
+        let account_debt = Self::account_debt(who).saturating_sub(counted).saturating_add(debt);
+        if account_debt.is_zero() {
+            <AccountDebt<T, I>>::remove(who);
+        } else {
+            <AccountDebt<T, I>>::insert(who, account_debt);
+        }
+        if debt.is_zero() {
+            <RewardedLoanDebt<T, I>>::remove(loan_id);
+        } else {
+            <RewardedLoanDebt<T, I>>::insert(loan_id, debt);
+        }
+    }
+
+    /// "update_rewards" for the owner of a loan whose debt may have changed
+    fn update_loan_rewards(loan_id: LoanId, who: &T::AccountId) {
+        Self::count_loan_debt(loan_id, who);
+        Self::update_rewards(who);
+    }
+
+    /// give the savers and borrowers from before the rewards existed their weights, at most "limit" of
+    /// them per block: the loans by id first, then the savers in "UserDtoken" after the last one seeded.
+    /// Giving a weight twice changes nothing, so the savers are walked again from the start when the
+    /// last one seeded has left in between
+    fn seed_rewards(limit: u32) -> Weight {
+        let mut weight = SCAN_BASE_WEIGHT;
+        let mut left = limit;
+        let next_loan_id = Self::next_loan_id();
+        let mut loan_id = <RewardSeedLoan<I>>::get();
+        while loan_id < next_loan_id && left > 0 {
+            if <Loans<T, I>>::contains_key(loan_id) {
+                let who = Self::get_loan_by_id(loan_id).who;
+                Self::update_loan_rewards(loan_id, &who);
+                weight = weight.saturating_add(LOAN_CHECK_WEIGHT);
+            } else {
+                weight = weight.saturating_add(INDEX_READ_WEIGHT);
+            }
+            loan_id += 1;
+            left -= 1;
+        }
+        <RewardSeedLoan<I>>::put(loan_id);
+        if loan_id < next_loan_id {
+            return weight;
+        }
+
+        let mut savers = <UserDtoken<T, I>>::enumerate().map(|(who, _)| who);
+        if let Some(last) = <RewardSeedSaver<T, I>>::get() {
+            if <UserDtoken<T, I>>::contains_key(&last) {
+                for who in savers.by_ref() {
+                    weight = weight.saturating_add(INDEX_READ_WEIGHT);
+                    if who == last {
+                        break;
+                    }
+                }
+            }
+        }
+        let mut seeded = 0;
+        for who in savers.by_ref().take(left as usize) {
+            Self::update_rewards(&who);
+            weight = weight.saturating_add(LOAN_CHECK_WEIGHT);
+            <RewardSeedSaver<T, I>>::put(who);
+            seeded += 1;
+        }
+        if seeded < left {
+            <RewardsSeeded<I>>::put(true);
+            <RewardSeedLoan<I>>::kill();
+            <RewardSeedSaver<T, I>>::kill();
+        }
+        weight
+    }
+
+    /// rewards an account could claim now
+    pub fn claimable_rewards(who: &T::AccountId) -> BalanceOf<T, I> {
+        let (shares, debt) = Self::reward_weights(who);
+        let (saver_index, borrower_index) = Self::reward_index();
+        let (saver_checkpoint, borrower_checkpoint) = <RewardIndexOf<T, I>>::get(who);
+        let prec = BalanceOf::<T, I>::from(REWARD_INDEX_PREC);
+        Self::pending_rewards(who)
+            .saturating_add(shares.saturating_mul(saver_index - saver_checkpoint) / prec)
+            .saturating_add(debt.saturating_mul(borrower_index - borrower_checkpoint) / prec)
+    }
+
+    /// settle what an account has earned on its old weights and start it on its current dtoken and debt,
+    /// must be called whenever either of them changes
+    fn update_rewards(who: &T::AccountId) {
+        let pending = Self::claimable_rewards(who);
+        let (old_shares, old_debt) = Self::reward_weights(who);
+        let shares = Self::user_dtoken(who);
+        let debt = Self::account_debt(who);
+        <TotalRewardWeights<T, I>>::mutate(|(total_shares, total_debt)| {
+            *total_shares = total_shares.saturating_sub(old_shares).saturating_add(shares);
+            *total_debt = total_debt.saturating_sub(old_debt).saturating_add(debt);
+        });
+
+        if shares.is_zero() && debt.is_zero() {
+            <RewardWeights<T, I>>::remove(who);
+            <RewardIndexOf<T, I>>::remove(who);
+        } else {
+            <RewardWeights<T, I>>::insert(who, (shares, debt));
+            <RewardIndexOf<T, I>>::insert(who, Self::reward_index());
+        }
+        if pending.is_zero() {
+            <PendingRewards<T, I>>::remove(who);
+        } else {
+            <PendingRewards<T, I>>::insert(who, pending);
+        }
+    }
+
+    fn claim_account_rewards(who: T::AccountId) -> DispatchResult {
+        Self::update_rewards(&who);
+        let amount = Self::pending_rewards(&who);
+        ensure!(!amount.is_zero(), Error::<T, I>::NoRewards);
+
+        let profit_asset_id = Self::profit_asset_id();
+        let profit_pool = Self::profit_pool();
+        ensure!(
+            T::Currency::free_balance(&profit_asset_id, &profit_pool) >= amount,
+            Error::<T, I>::InsufficientRewardFunds
+        );
+        T::Currency::transfer(&profit_asset_id, &profit_pool, &who, amount)?;
+        <PendingRewards<T, I>>::remove(&who);
+
+        Self::deposit_event(RawEvent::RewardsClaimed(who, amount));
+        Ok(())
+    }
+
+    /// keep a compact record of a loan which has just been closed
+    fn archive_loan(
+        loan: &Loan<T::AccountId, BalanceOf<T, I>>,
+        status: LoanHealth,
+        liquidation_proceeds: BalanceOf<T, I>,
+    ) {
+        let record = ClosedLoan {
+            id: loan.id,
+            who: loan.who.clone(),
+            status: status.clone(),
+            opened_at: <LoanOpenedAt<T, I>>::take(loan.id),
+            closed_at: <timestamp::Module<T>>::get(),
+            collateral: loan.collateral_balance_original,
+            debt: loan.loan_balance_total,
+            interest_paid: <LoanInterestPaid<T, I>>::take(loan.id),
+            liquidation_proceeds,
+        };
+        <LoanInterestAccrued<T, I>>::remove(loan.id);
+        <LoanAccruedAt<T, I>>::remove(loan.id);
+        let min_age = T::Moment::from(Self::min_credited_loan_age());
+        let credited = record.closed_at.saturating_sub(record.opened_at) >= min_age;
+        <CreditRecords<T, I>>::mutate(&loan.who, |v| match status {
+            LoanHealth::Repaid if !credited => {}
+            LoanHealth::Repaid => {
+                v.repaid = v.repaid.saturating_add(1);
+                v.volume = v.volume.saturating_add(loan.loan_balance_total);
             }
+            LoanHealth::Defaulted => v.defaulted = v.defaulted.saturating_add(1),
+            _ => v.liquidated = v.liquidated.saturating_add(1),
+        });
+        <LoanArchive<T, I>>::insert(loan.id, record);
+        let position = Self::next_archive_position();
+        <ArchivedLoanIds<I>>::insert(position, loan.id);
+        <NextArchivePosition<I>>::put(position + 1);
+        <ArchivedLoansByAccount<T, I>>::mutate(&loan.who, |v| v.push(loan.id));
+        Self::deposit_event(RawEvent::LoanArchived(loan.id, loan.who.clone(), status));
+    }
+
+    /// the qualifying tier with the highest LTV limit
+    pub fn credit_tier_of(who: &T::AccountId) -> Option<CreditTier<BalanceOf<T, I>>> {
+        let record = Self::credit_record(who);
+        Self::credit_tiers()
+            .into_iter()
+            .filter(|tier| {
+                record.repaid >= tier.min_repaid
+                    && record.volume >= tier.min_volume
+                    && record.liquidated.saturating_add(record.defaulted) <= tier.max_liquidated
+            })
+            .max_by_key(|tier| tier.ltv_limit)
+    }
 
-            <LoanInterestRateCurrent<T>>::put(loan_interest_rate_current);
-            let current_interest_rate = interest_generated
-                / T::Balance::from(total_deposit as u32)
-                * T::Balance::from(DAYS_PER_YEAR)
-                * T::Balance::from(SEC_PER_DAY)
-                * T::Balance::from(10_u32.pow(8));
+    pub fn ltv_limit_of(who: &T::AccountId) -> LTV {
+        let global_ltv = Self::global_ltv_limit();
+        match Self::credit_tier_of(who) {
+            Some(tier) if tier.ltv_limit > global_ltv => tier.ltv_limit,
+            _ => global_ltv,
+        }
+    }
 
-            <SavingInterestRate<T>>::put(current_interest_rate);
+    fn prune_loan_archive(before: T::Moment, limit: u32) {
+        let end = Self::next_archive_position();
+        let mut head = Self::archive_head();
+        let mut pruned: u32 = 0;
+        while pruned < limit && head < end {
+            if let Some(loan_id) = Self::archived_loan_at(head) {
+                if let Some(record) = Self::archived_loan(loan_id) {
+                    if record.closed_at >= before {
+                        break;
+                    }
+                    <ArchivedLoansByAccount<T, I>>::mutate(&record.who, |v| {
+                        v.retain(|ele| *ele != loan_id)
+                    });
+                    <LoanArchive<T, I>>::remove(loan_id);
+                }
+                <ArchivedLoanIds<I>>::remove(head);
+            }
+            head += 1;
+            pruned += 1;
         }
+        <ArchiveHead<I>>::put(head);
+        Self::deposit_event(RawEvent::ArchivePruned(pruned));
     }
 
     fn get_next_loan_id() -> LoanId {
-        NextLoanId::mutate(|v| {
+        <NextLoanId<I>>::mutate(|v| {
             let org = *v;
             *v += 1;
             org
@@ -1120,28 +4794,74 @@ impl<T: Trait> Module<T> {
 }
 
 decl_error! {
-    pub enum Error for Module<T: Trait> {
+    pub enum Error for Module<T: Trait<I>, I: Instance> {
         TotalCollateralUnderflow,
         ReachLoanCap,
         InvalidCollateralLoanAmounts,
         OverLTVLimit,
+        NothingToMerge,
+        DuplicatedLoanId,
+        Overflow,
+        LoanNotSelfSustained,
+        InvalidPenaltySplit,
+        UnknownRedeemRequest,
+        PriceNotAvailable,
+        TooManyLoans,
+        PriceStale,
+        LiquidationSuspended,
+        NoUnconfirmedPrice,
+        InSettlement,
+        NotInSettlement,
+        LoansOutstanding,
+        OperationPaused,
+        InvalidCreditTier,
+        TooManyGuarantors,
+        InvalidGuarantee,
+        UnknownApplication,
+        AlreadyApproved,
+        LoanGuaranteed,
+        InvalidOrder,
+        UnknownOrder,
+        OrdersNotMatching,
+        FixedRateLoan,
+        CollateralCustodyMismatch,
+        InvalidRewardShare,
+        NoRewards,
+        InsufficientRewardFunds,
+        InterestAssetNotAccepted,
+        NoAccruedInterest,
+        InvalidInterestPayment,
+        RedeemTooSmall,
+        RedeemQueueFull,
+        TooManyRedeemRequests,
+        InvalidRedeemLimits,
+        NoShortfall,
+        NothingToRelease,
+        NothingToMigrate,
+        InsufficientProfitPool,
+        TooManyLoansToMerge,
     }
 }
 
 decl_event!(
     #[rustfmt::skip]
-    pub enum Event<T>
+    pub enum Event<T, I = DefaultInstance>
     where
         AccountId = <T as frame_system::Trait>::AccountId,
-        Balance = <T as generic_asset::Trait>::Balance,
-        Loan = Loan<<T as frame_system::Trait>::AccountId, <T as generic_asset::Trait>::Balance>,
-        CollateralBalanceOriginal = <T as generic_asset::Trait>::Balance,
-        CollateralBalanceAvailable = <T as generic_asset::Trait>::Balance,
-        AuctionBalance = <T as generic_asset::Trait>::Balance,
-        TotalLoanBalance = <T as generic_asset::Trait>::Balance,
+        Balance = BalanceOf<T, I>,
+        Loan = Loan<<T as frame_system::Trait>::AccountId, BalanceOf<T, I>>,
+        CollateralBalanceOriginal = BalanceOf<T, I>,
+        CollateralBalanceAvailable = BalanceOf<T, I>,
+        AuctionBalance = BalanceOf<T, I>,
+        TotalLoanBalance = BalanceOf<T, I>,
         LineNumber = u32,
         BlockNumber = <T as frame_system::Trait>::BlockNumber,
         ExtrinsicIndex = u32,
+        PenaltyShares = Vec<(PenaltyDestination, <T as frame_system::Trait>::AccountId, BalanceOf<T, I>)>,
+        AssetId = AssetIdOf<T, I>,
+        PoolParameter = PoolParameter<BalanceOf<T, I>, <T as frame_system::Trait>::BlockNumber>,
+        Pledge = Pledge<BalanceOf<T, I>>,
+        Moment = <T as timestamp::Trait>::Moment,
     {
         LoanCreated(Loan),
         LoanDrawn(LoanId, Balance),
@@ -1161,5 +4881,122 @@ decl_event!(
         ),
 
         AddCollateral(LoanId, Balance),
+
+        LoansMerged(LoanId, Vec<LoanId>, LTV),
+        LoanRefinanced(LoanId, Balance, Balance),
+
+        MarginModeChanged(AccountId, bool),
+        AccountWarning(AccountId, LTV),
+        AccountLiquidating(AccountId, LTV),
+
+        PartiallyLiquidating(LoanId, AccountId, CollateralBalanceAvailable, TotalLoanBalance),
+        PartiallyLiquidated(LoanId, CollateralBalanceOriginal, TotalLoanBalance, AuctionBalance),
+        LiquidationProceedsSplit(LoanId, PenaltyShares),
+
+        RedeemQueued(RedeemRequestId, AccountId, Balance, u32),
+        RedeemFilled(RedeemRequestId, AccountId, Balance),
+        RedeemDropped(RedeemRequestId, AccountId),
+        RedeemCancelled(RedeemRequestId, AccountId),
+
+        LoanApplied(ApplicationId, AccountId),
+        GuaranteeApproved(ApplicationId, AccountId),
+        /// (application id, who cancelled it)
+        ApplicationCancelled(ApplicationId, AccountId),
+        /// (loan id, guarantor, what was taken)
+        GuaranteeSeized(LoanId, AccountId, Pledge),
+        GuaranteeReleased(LoanId, AccountId, Pledge),
+
+        /// (offer id, lender, amount, rate, term)
+        OfferPosted(OrderId, AccountId, Balance, u64, Moment),
+        /// (bid id, borrower, collateral, loan amount, highest rate, term)
+        BidPosted(OrderId, AccountId, Balance, Balance, u64, Moment),
+        /// (offer id, bid id, loan id)
+        OrdersMatched(OrderId, OrderId, LoanId),
+        OrderCancelled(OrderId, AccountId),
+
+        /// (loan id, payer, loan owner, amount repaid)
+        LoanRepaidFor(LoanId, AccountId, AccountId, Balance),
+
+        /// (previous price, new price), liquidations are suspended until "confirm_price"
+        PriceJumped(PriceInUSDT, PriceInUSDT),
+        PriceConfirmed(PriceInUSDT),
+
+        GlobalSettlement(PriceInUSDT),
+        /// (loan id, owner, collateral kept for savers, collateral returned)
+        LoanSettled(LoanId, AccountId, Balance, Balance),
+        /// (loan id, owner, terminal state)
+        LoanArchived(LoanId, AccountId, LoanHealth),
+        /// number of archived loans dropped
+        ArchivePruned(u32),
+        /// (who, dtoken burnt, loan asset paid, collateral paid)
+        SettlementRedeemed(AccountId, Balance, Balance, Balance),
+        /// (who, profit asset paid)
+        RewardsClaimed(AccountId, Balance),
+        InterestAssetsChanged(Vec<AssetId>),
+        /// (loan id, payer, asset paid in, amount paid, interest paid off)
+        InterestPaidIn(LoanId, AccountId, AssetId, Balance, Balance),
+
+        /// (who, amount saved, dtoken minted)
+        Staked(AccountId, Balance, Balance),
+        /// (who, amount paid out, dtoken burnt)
+        Redeemed(AccountId, Balance, Balance),
+        /// (interest generated, loan interest rate, saving interest rate)
+        InterestAccrued(Balance, Balance, Balance),
+        ParameterChanged(PoolParameter),
+        AssetChanged(PoolAsset, AssetId),
+        AccountChanged(PoolAccount, AccountId),
+        /// (asset, old account, derived account, amount)
+        BalanceMigrated(AssetId, AccountId, AccountId, Balance),
+
+        /// (loan id, guarantor, pledge kept for "release_pledges")
+        GuaranteeReleaseFailed(LoanId, AccountId, Pledge),
+
+        /// (operation, reason, block, extrinsic)
+        OperationPaused(Operation, Vec<u8>, BlockNumber, ExtrinsicIndex),
+        OperationResumed(Operation),
     }
 );
+
+impl<T: Trait<I>, I: Instance> support::unsigned::ValidateUnsigned for Module<T, I> {
+    type Call = Call<T, I>;
+
+    fn validate_unsigned(call: &Self::Call) -> TransactionValidity {
+        match call {
+            Call::liquidate_unsigned(block, loan_ids) => {
+                if !Self::offchain_liquidation()
+                    || Self::operation_paused(Operation::Liquidation)
+                    || Self::unconfirmed_price().is_some()
+                    || Self::settlement_price().is_some()
+                    || loan_ids.is_empty()
+                    || loan_ids.len() as u32 > T::MaxLiquidationBatch::get()
+                {
+                    return InvalidTransaction::Call.into();
+                }
+
+                // only what the offchain worker of the current or the previous block found
+                let now = <frame_system::Module<T>>::block_number();
+                if *block > now {
+                    return InvalidTransaction::Future.into();
+                }
+                if now - *block > One::one() {
+                    return InvalidTransaction::Stale.into();
+                }
+
+                // the transaction is free, so every loan in it must be liquidatable right now
+                if !Self::all_liquidatable(loan_ids) {
+                    return InvalidTransaction::Call.into();
+                }
+
+                Ok(ValidTransaction {
+                    priority: 0,
+                    requires: vec![],
+                    // a loan is carried by one transaction at a time
+                    provides: loan_ids.iter().map(|id| (&b"liquidate"[..], id).encode()).collect(),
+                    longevity: 3,
+                    propagate: true,
+                })
+            }
+            _ => InvalidTransaction::Call.into(),
+        }
+    }
+}
@@ -3,25 +3,24 @@
 
 use super::*;
 use crate::{GenesisConfig, Module, Trait};
-use balances;
 use sp_core::H256;
 pub use sp_core::{sr25519, Pair, Public};
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 use support::{
     impl_outer_dispatch, impl_outer_event, impl_outer_origin, parameter_types, weights::Weight,
 };
 
 #[allow(unused_imports)]
 use sp_runtime::{
-    testing::Header,
+    testing::{Header, TestXt},
     traits::{BlakeTwo256, ConvertInto, IdentityLookup, OnFinalize, OnInitialize},
-    Perbill,
+    ModuleId, Perbill,
 };
 
 thread_local! {
-    pub(crate) static EXISTENTIAL_DEPOSIT: RefCell<u128> = RefCell::new(0);
-    static TRANSFER_FEE: RefCell<u128> = RefCell::new(0);
-    static CREATION_FEE: RefCell<u128> = RefCell::new(0);
+    static COLLATERAL_PRICE: RefCell<PriceInUSDT> = RefCell::new(10000 * PRICE_PREC as PriceInUSDT);
+    static ASSET_PRICES: RefCell<BTreeMap<u32, (PriceInUSDT, u64)>> = RefCell::new(BTreeMap::new());
 }
 
 pub mod constants {
@@ -29,8 +28,8 @@ pub mod constants {
     pub const DECIMALS: u128 = 100000000; // satoshi
     pub const USDT: <Test as generic_asset::Trait>::AssetId = 0;
     pub const BTC: <Test as generic_asset::Trait>::AssetId = 1;
-
-    //TODO:     pub const DAVE: <Test as frame_system::Trait>::AccountId = 5;
+    pub const DOT: <Test as generic_asset::Trait>::AssetId = 2;
+    pub const INITIAL_BALANCE: u128 = 1_000_000_000;
 }
 
 use self::constants::*;
@@ -39,6 +38,23 @@ impl_outer_origin! {
     pub enum Origin for Test {}
 }
 
+mod deposit_loan {
+    pub use crate::Event;
+}
+
+impl_outer_event! {
+    pub enum TestEvent for Test {
+        system<T>,
+        deposit_loan<T>,
+    }
+}
+
+impl_outer_dispatch! {
+    pub enum Call for Test where origin: Origin {
+        deposit_loan::DepositLoanTest,
+    }
+}
+
 #[derive(Clone, Eq, PartialEq)]
 pub struct Test;
 parameter_types! {
@@ -54,10 +70,11 @@ impl system::Trait for Test {
     type BlockNumber = u64;
     type Hash = H256;
     type Hashing = BlakeTwo256;
-    type AccountId = sp_core::sr25519::Public; // <<Signature as Verify>::Signer as IdentifyAccount>::AccountId; // sp_core::sr25519::Public;
+    // pool accounts are derived from "LoanModuleId", they need more room than a u64
+    type AccountId = sp_core::sr25519::Public;
     type Lookup = IdentityLookup<Self::AccountId>;
     type Header = Header;
-    type Event = ();
+    type Event = TestEvent;
     type BlockHashCount = BlockHashCount;
     type MaximumBlockWeight = MaximumBlockWeight;
     type MaximumBlockLength = MaximumBlockLength;
@@ -69,26 +86,7 @@ impl system::Trait for Test {
     type OnReapAccount = ();
 }
 
-// type Extrinsic = TestXt<new_oracle::Call<Test>, ()>;
-type SubmitTransaction =
-    system::offchain::TransactionSubmitter<new_oracle::crypto::Public, Test, Extrinsic>;
-
-// impl system::offchain::CreateTransaction<Test, Extrinsic> for Test {
-//     type Public = sp_core::sr25519::Public;
-//     type Signature = sp_core::sr25519::Signature;
-
-//     fn create_transaction<F: system::offchain::Signer<Self::Public, Self::Signature>>(
-//         call: <Extrinsic as ExtrinsicsT>::Call,
-//         _public: Self::Public,
-//         _account: <Test as system::Trait>::AccountId,
-//         nonce: <Test as system::Trait>::Index,
-//     ) -> Option<(
-//         <Extrinsic as ExtrinsicsT>::Call,
-//         <Extrinsic as ExtrinsicsT>::SignaturePayload,
-//     )> {
-//         Some((call, (nonce, ())))
-//     }
-// }
+pub type Extrinsic = TestXt<Call, ()>;
 
 parameter_types! {
     pub const MinimumPeriod: u64 = 5;
@@ -100,7 +98,7 @@ impl timestamp::Trait for Test {
 }
 impl sudo::Trait for Test {
     type Event = ();
-    type Call = Call<Test>;
+    type Call = Call;
 }
 impl generic_asset::Trait for Test {
     type Event = ();
@@ -108,43 +106,70 @@ impl generic_asset::Trait for Test {
     type AssetId = u32;
 }
 
-type BlockNumber = u64;
+type SubmitLiquidationTransaction =
+    system::offchain::TransactionSubmitter<crypto::Public, Test, Extrinsic>;
 
-parameter_types! {
-    pub const AggregateInterval: BlockNumber = 5;
+/// price of the collateral, tests move it with "set_collateral_price"
+pub struct CollateralPrice;
+impl Get<PriceInUSDT> for CollateralPrice {
+    fn get() -> PriceInUSDT {
+        COLLATERAL_PRICE.with(|v| *v.borrow())
+    }
 }
-impl new_oracle::Trait for Test {
-    type Event = ();
-    type Call = new_oracle::Call<Test>;
-    type SubmitUnsignedTransaction = SubmitTransaction;
-    type SubmitSignedTransaction = SubmitTransaction;
-    type AggregateInterval = AggregateInterval;
-    type PriceInUSDT = u64;
+
+pub fn set_collateral_price(price: PriceInUSDT) {
+    COLLATERAL_PRICE.with(|v| *v.borrow_mut() = price);
 }
 
-impl Trait for Test {
-    type Event = ();
+/// price "asset_id" as produced in the current block
+pub fn set_asset_price(asset_id: u32, price: PriceInUSDT) {
+    let block_number = SystemTest::block_number();
+    ASSET_PRICES.with(|v| v.borrow_mut().insert(asset_id, (price, block_number)));
+}
+
+/// the collateral is priced like "FixedPrice" does, every other asset by "set_asset_price"
+pub struct AssetPrices;
+impl PriceProvider<u32, u64, u64> for AssetPrices {
+    fn price(asset_id: u32) -> Result<Price<u64, u64>, DispatchError> {
+        if asset_id == BTC {
+            return FixedPrice::<Test, CollateralPrice>::price(asset_id);
+        }
+        let (value, block_number) = ASSET_PRICES
+            .with(|v| v.borrow().get(&asset_id).cloned())
+            .ok_or("price not available")?;
+        Ok(Price {
+            value,
+            block_number,
+            timestamp: TimestampTest::get(),
+            scale: PRICE_PREC as u64,
+        })
+    }
+}
+
+parameter_types! {
+    pub const MaxLiquidationBatch: u32 = 20;
+    pub const MaxMergeLoans: u32 = 8;
+    pub const LoanModuleId: ModuleId = ModuleId(*b"dfx/loan");
 }
 
-type Balances = balances::Module<Test>;
-type System = system::Module<Test>;
-type Sudo = sudo::Module<Test>;
+impl Trait for Test {
+    type Event = TestEvent;
+    type Currency = generic_asset::Module<Test>;
+    type PriceProvider = AssetPrices;
+    type Call = Call;
+    type SubmitUnsignedTransaction = SubmitLiquidationTransaction;
+    type MaxLiquidationBatch = MaxLiquidationBatch;
+    type MaxMergeLoans = MaxMergeLoans;
+    type ModuleId = LoanModuleId;
+}
 
 pub type DepositLoanTest = Module<Test>;
+pub type LoanError = Error<Test, DefaultInstance>;
 pub type SystemTest = system::Module<Test>;
 pub type GenericAssetTest = generic_asset::Module<Test>;
+pub type TimestampTest = timestamp::Module<Test>;
 
-pub struct ExtBuilder {}
-impl Default for ExtBuilder {
-    fn default() -> Self {
-        Self {}
-    }
-}
-impl ExtBuilder {
-    pub fn build(self) -> sp_io::TestExternalities {
-        new_test_ext()
-    }
-}
+pub type AccountId = <Test as system::Trait>::AccountId;
 
 /// Helper function to generate a crypto pair from seed
 pub fn get_from_seed<TPublic: Public>(seed: &str) -> <TPublic::Pair as Pair>::Public {
@@ -153,83 +178,101 @@ pub fn get_from_seed<TPublic: Public>(seed: &str) -> <TPublic::Pair as Pair>::Pu
         .public()
 }
 
-pub fn new_test_ext() -> sp_io::TestExternalities {
-    let mut t = system::GenesisConfig::default()
-        .build_storage::<Test>()
+pub fn root() -> AccountId {
+    get_from_seed::<sr25519::Public>("Root")
+}
+
+/// a saver
+pub fn alice() -> AccountId {
+    get_from_seed::<sr25519::Public>("Alice")
+}
+
+/// a borrower
+pub fn bob() -> AccountId {
+    get_from_seed::<sr25519::Public>("Bob")
+}
+
+/// a second borrower, or a guarantor / lender
+pub fn charlie() -> AccountId {
+    get_from_seed::<sr25519::Public>("Charlie")
+}
+
+pub fn liquidation_account() -> AccountId {
+    get_from_seed::<sr25519::Public>("liquidation_account")
+}
+
+pub struct ExtBuilder {
+    ltv_limit: LTV,
+    warning_threshold: LTV,
+    liquidation_threshold: LTV,
+}
+impl Default for ExtBuilder {
+    fn default() -> Self {
+        Self {
+            ltv_limit: 7000,
+            warning_threshold: 8000,
+            liquidation_threshold: 9000,
+        }
+    }
+}
+impl ExtBuilder {
+    pub fn ltv_limit(mut self, ltv_limit: LTV) -> Self {
+        self.ltv_limit = ltv_limit;
+        self
+    }
+
+    pub fn build(self) -> sp_io::TestExternalities {
+        set_collateral_price(10000 * PRICE_PREC as PriceInUSDT);
+        ASSET_PRICES.with(|v| v.borrow_mut().clear());
+
+        let mut t = system::GenesisConfig::default()
+            .build_storage::<Test>()
+            .unwrap();
+
+        sudo::GenesisConfig::<Test> { key: root() }
+            .assimilate_storage(&mut t)
+            .unwrap();
+
+        generic_asset::GenesisConfig::<Test> {
+            next_asset_id: 3,
+            assets: vec![USDT, BTC, DOT],
+            initial_balance: INITIAL_BALANCE,
+            endowed_accounts: vec![alice(), bob(), charlie(), liquidation_account()],
+            symbols: vec![],
+        }
+        .assimilate_storage(&mut t)
         .unwrap();
 
-    let root: <Test as system::Trait>::AccountId = get_from_seed::<sr25519::Public>("Root");
-    let market_dtoken_account_id: <Test as system::Trait>::AccountId =
-        get_from_seed::<sr25519::Public>("market_dtoken_account_id");
-    let total_dtoken_account_id: <Test as system::Trait>::AccountId =
-        get_from_seed::<sr25519::Public>("total_dtoken_account_id");
-    let collection_account_id: <Test as system::Trait>::AccountId =
-        get_from_seed::<sr25519::Public>("collection_account_id");
-    let profit_pool: <Test as system::Trait>::AccountId =
-        get_from_seed::<sr25519::Public>("profit_pool");
-    let pawn_shop: <Test as system::Trait>::AccountId =
-        get_from_seed::<sr25519::Public>("pawn_shop");
-    let liquidation_account: <Test as system::Trait>::AccountId =
-        get_from_seed::<sr25519::Public>("liquidation_account");
-
-    sudo::GenesisConfig::<Test> { key: root }
+        GenesisConfig::<Test> {
+            collection_asset_id: USDT,
+            profit_asset_id: USDT,
+            collateral_asset_id: BTC,
+            loan_asset_id: USDT,
+
+            market_dtoken: 0,
+            total_dtoken: 0,
+
+            global_ltv_limit: self.ltv_limit,
+            global_liquidation_threshold: self.liquidation_threshold,
+            global_warning_threshold: self.warning_threshold,
+
+            loan_interest_rate_current: 0,
+            next_loan_id: 0,
+            penalty_rate: 0,
+            minimum_collateral: 1,
+            liquidation_penalty: 0,
+            saving_interest_rate: 0,
+            liquidation_account: liquidation_account(),
+        }
         .assimilate_storage(&mut t)
         .unwrap();
 
-    // new_oracle::GenesisConfig::<Test> {
-    //     crypto_price_sources: vec![],
-    //     current_price: vec![
-    //         (b"BTC".to_vec(), 10000 * new_oracle::PRICE_SCALE),
-    //         (b"DUSD".to_vec(), 1 * new_oracle::PRICE_SCALE),
-    //     ],
-    // }
-    // .assimilate_storage(&mut t)
-    // .unwrap();
-
-    generic_asset::GenesisConfig::<Test> {
-        next_asset_id: 2,
-        assets: vec![],
-        initial_balance: 0,
-        endowed_accounts: vec![],
-        symbols: vec![
-            (0, "DUSD".as_bytes().to_vec()),
-            (1, "BTC".as_bytes().to_vec()),
-        ],
-    }
-    .assimilate_storage(&mut t)
-    .unwrap();
-
-    GenesisConfig::<Test> {
-        market_dtoken_account_id: market_dtoken_account_id,
-        total_dtoken_account_id: total_dtoken_account_id,
-        collection_account_id: collection_account_id,
-        profit_pool: profit_pool,
-        pawn_shop: pawn_shop,
-        liquidation_account: liquidation_account,
-
-        collection_asset_id: 0,
-        dtoken_asset_id: 1,
-        market_dtoken_asset_id: 2,
-        total_dtoken_asset_id: 3,
-
-        profit_asset_id: 0,
-
-        collateral_asset_id: 4,
-        loan_asset_id: 0,
-
-        global_ltv_limit: 100000000,
-        global_liquidation_threshold: 100000000,
-        global_warning_threshold: 800000000,
-
-        loan_interest_rate_current: 10,
-        next_loan_id: 0,
-        current_btc_price: 8899000,
-        penalty_rate: 12,
-        minimum_collateral: 1,
-        liquidation_penalty: 12,
+        let mut ext: sp_io::TestExternalities = t.into();
+        ext.execute_with(|| SystemTest::set_block_number(1));
+        ext
     }
-    .assimilate_storage(&mut t)
-    .unwrap();
+}
 
-    t.into()
+pub fn new_test_ext() -> sp_io::TestExternalities {
+    ExtBuilder::default().build()
 }
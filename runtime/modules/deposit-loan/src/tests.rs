@@ -2,7 +2,9 @@
 #![allow(dead_code)]
 
 use crate::*;
-use support::{assert_noop, assert_ok};
+use generic_asset::MultiCurrency;
+use sp_runtime::transaction_validity::{InvalidTransaction, TransactionValidity};
+use support::{assert_noop, assert_ok, unsigned::ValidateUnsigned};
 
 #[allow(unused_imports)]
 use sp_runtime::{
@@ -24,22 +26,1095 @@ fn next_n_block(n: <Test as system::Trait>::BlockNumber) {
     DepositLoanTest::on_finalize(SystemTest::block_number());
 }
 
-// #[test]
-// fn deposit_works() {
-//     let root: <Test as system::Trait>::AccountId = get_from_seed::<sr25519::Public>("Root");
-//     let eve: <Test as system::Trait>::AccountId = get_from_seed::<sr25519::Public>("Eve");
-//     let dave: <Test as system::Trait>::AccountId = get_from_seed::<sr25519::Public>("Dave");
-
-//     ExtBuilder::default().build().execute_with(|| {
-//         assert_ok!(GenericAssetTest::mint_free(
-//             &USDT,
-//             &root,
-//             &dave,
-//             &<<Test as generic_asset::Trait>::Balance as TryFrom<u64>>::try_from(1000_00000000)
-//                 .ok()
-//                 .unwrap(),
-//         ));
-
-//         assert_ok!(DepositLoanTest::staking(DAVE, USDT.clone(), 1000,));
-//     });
-// }
+/// run "on_initialize" for the next "n" blocks
+fn run_blocks(n: <Test as system::Trait>::BlockNumber) {
+    for _ in 0..n {
+        let block_number = SystemTest::block_number() + 1;
+        SystemTest::set_block_number(block_number);
+        DepositLoanTest::on_initialize(block_number);
+    }
+}
+
+fn loan_events() -> Vec<Event<Test>> {
+    SystemTest::events()
+        .into_iter()
+        .filter_map(|r| match r.event {
+            TestEvent::deposit_loan(e) => Some(e),
+            _ => None,
+        })
+        .collect()
+}
+
+fn free(asset_id: u32, who: &AccountId) -> u128 {
+    GenericAssetTest::free_balance(&asset_id, who)
+}
+
+/// alice saves enough for the loans of the test to be served
+fn fund_pool(amount: u128) {
+    assert_ok!(DepositLoanTest::staking(Origin::signed(alice()), USDT, amount));
+}
+
+#[test]
+fn refinance_moves_the_difference_and_totals() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 500_000));
+
+        assert_ok!(DepositLoanTest::refinance(Origin::signed(bob()), 0, 100, 600_000));
+        assert_eq!(DepositLoanTest::get_loan_by_id(0).loan_balance_total, 600_000);
+        assert_eq!(DepositLoanTest::total_loan(), 600_000);
+        assert_eq!(free(USDT, &bob()), INITIAL_BALANCE + 600_000);
+
+        assert_ok!(DepositLoanTest::refinance(Origin::signed(bob()), 0, 80, 400_000));
+        let loan = DepositLoanTest::get_loan_by_id(0);
+        assert_eq!(loan.collateral_balance_original, 80);
+        assert_eq!(loan.loan_balance_total, 400_000);
+        assert_eq!(DepositLoanTest::total_loan(), 400_000);
+        assert_eq!(DepositLoanTest::total_collateral(), 80);
+        assert_eq!(free(USDT, &bob()), INITIAL_BALANCE + 400_000);
+        assert_eq!(free(BTC, &bob()), INITIAL_BALANCE - 80);
+        assert_eq!(free(BTC, &DepositLoanTest::pawn_shop()), 80);
+    });
+}
+
+#[test]
+fn refinance_over_ltv_limit_changes_nothing() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 500_000));
+
+        assert_noop!(
+            DepositLoanTest::refinance(Origin::signed(bob()), 0, 100, 800_000),
+            LoanError::OverLTVLimit
+        );
+    });
+}
+
+#[test]
+fn refinance_rejects_zero_price() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 500_000));
+
+        set_collateral_price(0);
+        assert_noop!(
+            DepositLoanTest::refinance(Origin::signed(bob()), 0, 100, 400_000),
+            "price not available"
+        );
+    });
+}
+
+#[test]
+fn refinance_cannot_drive_total_loan_below_zero() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 500_000));
+
+        // the loan counts for more than the pool thinks it lent
+        <TotalLoan<Test>>::put(100_000);
+        assert_noop!(
+            DepositLoanTest::refinance(Origin::signed(bob()), 0, 100, 50_000),
+            LoanError::Overflow
+        );
+    });
+}
+
+#[test]
+fn merge_loans_folds_into_the_first_loan() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 50, 200_000));
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 50, 200_000));
+
+        assert_ok!(DepositLoanTest::merge_loans(Origin::signed(bob()), vec![0, 1]));
+        let loan = DepositLoanTest::get_loan_by_id(0);
+        assert_eq!(loan.collateral_balance_original, 100);
+        assert_eq!(loan.loan_balance_total, 400_000);
+        assert!(!<Loans<Test>>::contains_key(1));
+        assert_eq!(DepositLoanTest::loans_by_account(bob()), vec![0]);
+        assert_eq!(DepositLoanTest::total_loan(), 400_000);
+    });
+}
+
+#[test]
+fn merge_loans_rejects_duplicates_and_foreign_loans() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 50, 200_000));
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(charlie()), 50, 200_000));
+
+        assert_noop!(
+            DepositLoanTest::merge_loans(Origin::signed(bob()), vec![0, 0]),
+            LoanError::DuplicatedLoanId
+        );
+        // nothing is charged on the first loan before the second one is turned down
+        TimestampTest::set_timestamp(86400);
+        assert_noop!(
+            DepositLoanTest::merge_loans(Origin::signed(bob()), vec![0, 1]),
+            "not owner of the loan"
+        );
+    });
+}
+
+#[test]
+fn merge_loans_is_bounded_and_weighed_by_its_loans() {
+    new_test_ext().execute_with(|| {
+        let loan_ids: Vec<LoanId> = (0..MaxMergeLoans::get() as LoanId + 1).collect();
+        assert_noop!(
+            DepositLoanTest::merge_loans(Origin::signed(bob()), loan_ids.clone()),
+            LoanError::TooManyLoansToMerge
+        );
+        assert!(MergeWeight.weigh_data((&loan_ids,)) > MergeWeight.weigh_data((&vec![0, 1],)));
+    });
+}
+
+#[test]
+fn account_warning_is_emitted_once_per_episode() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::set_margin_mode(Origin::signed(bob()), true));
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 500_000));
+
+        let warnings = || {
+            loan_events()
+                .into_iter()
+                .filter(|e| match e {
+                    RawEvent::AccountWarning(..) => true,
+                    _ => false,
+                })
+                .count()
+        };
+
+        // LTV goes from 5000 to about 8600, between the warning and the liquidation threshold
+        set_collateral_price(5800 * PRICE_PREC as PriceInUSDT);
+        run_blocks(3);
+        assert_eq!(warnings(), 1);
+        match DepositLoanTest::account_health(bob()) {
+            LoanHealth::Warning(_) => {}
+            other => panic!("unexpected health {:?}", other),
+        }
+
+        // back to health, then into warning again
+        set_collateral_price(10000 * PRICE_PREC as PriceInUSDT);
+        run_blocks(1);
+        assert_eq!(DepositLoanTest::account_health(bob()), LoanHealth::Well);
+        set_collateral_price(5800 * PRICE_PREC as PriceInUSDT);
+        run_blocks(2);
+        assert_eq!(warnings(), 2);
+    });
+}
+
+/// open a loan of 500_000 against 100 BTC for bob and crash the price until it is liquidating
+fn liquidating_loan(close_factor: u32) -> LoanId {
+    fund_pool(1_000_000);
+    assert_ok!(DepositLoanTest::set_close_factor(Origin::ROOT, close_factor));
+    assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 500_000));
+    // LTV reaches 10000
+    set_collateral_price(5000 * PRICE_PREC as PriceInUSDT);
+    run_blocks(1);
+    assert!(DepositLoanTest::check_loan_in_liquidation(&0));
+    0
+}
+
+#[test]
+fn proceeds_split_is_reported_without_leftover() {
+    new_test_ext().execute_with(|| {
+        let loan_id = liquidating_loan(0);
+        assert_ok!(DepositLoanTest::mark_liquidated(
+            Origin::signed(liquidation_account()),
+            loan_id,
+            500_000
+        ));
+
+        let split = loan_events().into_iter().find_map(|e| match e {
+            RawEvent::LiquidationProceedsSplit(id, shares) if id == loan_id => Some(shares),
+            _ => None,
+        });
+        assert_eq!(
+            split,
+            Some(vec![
+                (PenaltyDestination::Treasury, DepositLoanTest::profit_pool(), 0),
+                (PenaltyDestination::LoanOwner, bob(), 0),
+            ])
+        );
+    });
+}
+
+#[test]
+fn redeem_queue_enforces_its_limits() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 600_000));
+        assert_ok!(DepositLoanTest::set_redeem_limits(Origin::ROOT, 1_000, 2, 3, 1));
+
+        assert_noop!(
+            DepositLoanTest::redeem(Origin::signed(alice()), USDT, 500),
+            LoanError::RedeemTooSmall
+        );
+        assert_ok!(DepositLoanTest::redeem(Origin::signed(alice()), USDT, 500_000));
+        assert_ok!(DepositLoanTest::redeem(Origin::signed(alice()), USDT, 200_000));
+        assert_noop!(
+            DepositLoanTest::redeem(Origin::signed(alice()), USDT, 100_000),
+            LoanError::TooManyRedeemRequests
+        );
+        assert_eq!(DepositLoanTest::redeem_queue_length(), 2);
+        assert_eq!(DepositLoanTest::queued_redeem_total(), 700_000);
+        assert_eq!(DepositLoanTest::account_redeem_requests(alice()), vec![0, 1]);
+
+        assert_noop!(
+            DepositLoanTest::set_redeem_limits(Origin::ROOT, 1_000, 2, 3, 0),
+            LoanError::InvalidRedeemLimits
+        );
+    });
+}
+
+#[test]
+fn redeem_queue_fills_a_bounded_batch_per_block() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 600_000));
+        assert_ok!(DepositLoanTest::set_redeem_limits(Origin::ROOT, 0, 10, 10, 1));
+
+        assert_ok!(DepositLoanTest::redeem(Origin::signed(alice()), USDT, 450_000));
+        assert_ok!(DepositLoanTest::redeem(Origin::signed(alice()), USDT, 100_000));
+        assert_ok!(DepositLoanTest::redeem(Origin::signed(alice()), USDT, 50_000));
+        assert_ok!(DepositLoanTest::cancel_redeem(Origin::signed(alice()), 1));
+        assert_eq!(DepositLoanTest::redeem_queue_length(), 2);
+
+        // the repayment brings the liquidity back, only one request is filled on the way
+        assert_ok!(DepositLoanTest::repay_loan(Origin::signed(bob()), 0));
+        assert_eq!(DepositLoanTest::redeem_queue_length(), 1);
+        assert_eq!(free(USDT, &alice()), INITIAL_BALANCE - 1_000_000 + 450_000);
+
+        // the cancelled request takes a step of its own
+        run_blocks(1);
+        assert_eq!(DepositLoanTest::redeem_queue_length(), 1);
+        run_blocks(1);
+        assert_eq!(DepositLoanTest::redeem_queue_length(), 0);
+        assert_eq!(DepositLoanTest::queued_redeem_total(), 0);
+        assert_eq!(free(USDT, &alice()), INITIAL_BALANCE - 1_000_000 + 500_000);
+    });
+}
+
+#[test]
+fn unpaid_interest_is_not_recorded_as_paid() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 500_000));
+        // bob can't pay any interest in cash
+        let cash = free(USDT, &bob());
+        assert_ok!(<GenericAssetTest as MultiCurrency<_>>::transfer(&USDT, &bob(), &charlie(), cash));
+        let total_dtoken = DepositLoanTest::total_dtoken();
+
+        TimestampTest::set_timestamp(86400 * 1000);
+        run_blocks(1);
+        TimestampTest::set_timestamp(2 * 86400 * 1000);
+        run_blocks(1);
+
+        assert_eq!(DepositLoanTest::interest_paid(bob()), 0);
+        assert_eq!(DepositLoanTest::loan_interest_paid(0), 0);
+        assert_eq!(DepositLoanTest::total_dtoken(), total_dtoken);
+    });
+}
+
+fn validate_liquidation(block: u64, loan_ids: Vec<LoanId>) -> TransactionValidity {
+    DepositLoanTest::validate_unsigned(&crate::Call::<Test>::liquidate_unsigned(block, loan_ids))
+}
+
+#[test]
+fn unsigned_liquidation_is_only_valid_for_liquidatable_loans_of_a_recent_block() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::set_offchain_liquidation(Origin::ROOT, true));
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 500_000));
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(charlie()), 100, 100_000));
+        run_blocks(1);
+        let now = SystemTest::block_number();
+
+        // healthy
+        assert_eq!(validate_liquidation(now, vec![0]), InvalidTransaction::Call.into());
+
+        set_collateral_price(5000 * PRICE_PREC as PriceInUSDT);
+        run_blocks(1);
+        let now = SystemTest::block_number();
+        // left to the offchain worker
+        assert!(!DepositLoanTest::check_loan_in_liquidation(&0));
+
+        assert!(validate_liquidation(now, vec![0]).is_ok());
+        assert!(validate_liquidation(now - 1, vec![0]).is_ok());
+        assert_eq!(validate_liquidation(now - 2, vec![0]), InvalidTransaction::Stale.into());
+        assert_eq!(validate_liquidation(now + 1, vec![0]), InvalidTransaction::Future.into());
+        assert_eq!(validate_liquidation(now, vec![0, 0]), InvalidTransaction::Call.into());
+        // charlie's loan is still healthy
+        assert_eq!(validate_liquidation(now, vec![0, 1]), InvalidTransaction::Call.into());
+    });
+}
+
+#[test]
+fn warnings_stay_on_chain_with_offchain_liquidation() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::set_offchain_liquidation(Origin::ROOT, true));
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 500_000));
+
+        set_collateral_price(5800 * PRICE_PREC as PriceInUSDT);
+        run_blocks(1);
+        assert!(loan_events().into_iter().any(|e| match e {
+            RawEvent::Warning(0, _) => true,
+            _ => false,
+        }));
+    });
+}
+
+#[test]
+fn loans_at_risk_are_found_through_their_price_bucket() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::set_scan_limit(Origin::ROOT, 1));
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(charlie()), 100, 100_000));
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(charlie()), 100, 100_000));
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 500_000));
+        assert_eq!(DepositLoanTest::loan_break_even_price(2), Some(5000 * PRICE_PREC as PriceInUSDT));
+
+        // the cursor is still on charlie's loans, the bucket scan gets to bob's first
+        set_collateral_price(5000 * PRICE_PREC as PriceInUSDT);
+        run_blocks(1);
+        assert!(DepositLoanTest::check_loan_in_liquidation(&2));
+        assert_eq!(DepositLoanTest::loan_break_even_price(2), None);
+        assert!(!DepositLoanTest::check_loan_in_liquidation(&0));
+    });
+}
+
+#[test]
+fn account_margin_loans_are_judged_with_their_account() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 500_000));
+        assert!(DepositLoanTest::loan_break_even_price(0).is_some());
+
+        assert_ok!(DepositLoanTest::set_margin_mode(Origin::signed(bob()), true));
+        assert_eq!(DepositLoanTest::loan_break_even_price(0), None);
+        assert_eq!(DepositLoanTest::margin_account_count(), 1);
+
+        // loans at risk elsewhere can't take the budget of the account
+        assert_ok!(DepositLoanTest::set_scan_limit(Origin::ROOT, 1));
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(charlie()), 100, 600_000));
+        set_collateral_price(5800 * PRICE_PREC as PriceInUSDT);
+        run_blocks(1);
+        match DepositLoanTest::account_health(bob()) {
+            LoanHealth::Warning(_) => {}
+            other => panic!("unexpected health {:?}", other),
+        }
+
+        set_collateral_price(10000 * PRICE_PREC as PriceInUSDT);
+        assert_ok!(DepositLoanTest::set_margin_mode(Origin::signed(bob()), false));
+        assert!(DepositLoanTest::loan_break_even_price(0).is_some());
+        assert_eq!(DepositLoanTest::margin_account_count(), 0);
+    });
+}
+
+#[test]
+fn account_margin_liquidations_charge_interest_and_report_each_loan() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(charlie()), 100, 300_000));
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 500_000));
+        assert_ok!(DepositLoanTest::set_margin_mode(Origin::signed(bob()), true));
+        // one loan is charged per block, the next block's charge is charlie's
+        assert_ok!(DepositLoanTest::set_scan_limit(Origin::ROOT, 1));
+        run_blocks(2);
+
+        TimestampTest::set_timestamp(86400);
+        set_collateral_price(5000 * PRICE_PREC as PriceInUSDT);
+        run_blocks(1);
+        assert!(DepositLoanTest::check_loan_in_liquidation(&1));
+        assert_eq!(DepositLoanTest::loan_accrued_at(1), Some(86400));
+        assert!(DepositLoanTest::loan_interest_accrued(1) > 0);
+        let events = loan_events();
+        assert!(events.iter().any(|e| match e {
+            RawEvent::Liquidating(1, who, _, _) => *who == bob(),
+            _ => false,
+        }));
+        assert!(events.iter().any(|e| match e {
+            RawEvent::AccountLiquidating(who, _) => *who == bob(),
+            _ => false,
+        }));
+    });
+}
+
+#[test]
+fn interest_is_charged_for_the_time_since_the_last_charge() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 500_000));
+        run_blocks(1);
+        let rate = DepositLoanTest::loan_interest_rate_current();
+        assert!(rate > 0);
+        let total_dtoken = DepositLoanTest::total_dtoken();
+
+        // a year later
+        TimestampTest::set_timestamp(365 * 86400);
+        run_blocks(1);
+        let interest = 500_000 * rate / INTEREST_RATE_PREC as u128;
+        assert!(interest > 0);
+        assert_eq!(DepositLoanTest::get_loan_by_id(0).loan_balance_total, 500_000 + interest);
+        assert_eq!(DepositLoanTest::interest_paid(bob()), interest);
+        assert_eq!(DepositLoanTest::total_dtoken(), total_dtoken + interest);
+        assert_eq!(DepositLoanTest::loan_accrued_at(0), Some(365 * 86400));
+
+        // nothing more without time passing
+        run_blocks(1);
+        assert_eq!(DepositLoanTest::interest_paid(bob()), interest);
+    });
+}
+
+#[test]
+fn interest_is_charged_on_a_few_loans_per_block() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 300_000));
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(charlie()), 100, 300_000));
+        assert_ok!(DepositLoanTest::set_scan_limit(Origin::ROOT, 1));
+        run_blocks(2);
+
+        TimestampTest::set_timestamp(86400);
+        run_blocks(1);
+        assert!(DepositLoanTest::loan_interest_paid(0) > 0);
+        assert_eq!(DepositLoanTest::loan_interest_paid(1), 0);
+        run_blocks(1);
+        assert!(DepositLoanTest::loan_interest_paid(1) > 0);
+    });
+}
+
+#[test]
+fn fixed_rate_loans_are_charged_from_their_own_last_charge() {
+    new_test_ext().execute_with(|| {
+        // 10% a year, the pool itself is empty
+        let rate = INTEREST_RATE_PREC as u64 / 10;
+        assert_ok!(DepositLoanTest::post_offer(Origin::signed(alice()), 600_000, rate, 2 * 365 * 86400));
+        assert_eq!(free(USDT, &DepositLoanTest::offers_account()), 600_000);
+        assert_eq!(free(USDT, &DepositLoanTest::pawn_shop()), 0);
+        assert_ok!(DepositLoanTest::post_bid(Origin::signed(bob()), 100, 500_000, rate, 365 * 86400));
+        assert_ok!(DepositLoanTest::match_orders(Origin::signed(charlie()), 0, 1));
+        assert_eq!(free(USDT, &DepositLoanTest::offers_account()), 100_000);
+        assert_eq!(DepositLoanTest::loan_accrued_at(0), Some(0));
+
+        TimestampTest::set_timestamp(365 * 86400);
+        run_blocks(1);
+        assert_eq!(DepositLoanTest::get_loan_by_id(0).loan_balance_total, 550_000);
+        assert_eq!(DepositLoanTest::loan_interest_accrued(0), 50_000);
+        assert_eq!(DepositLoanTest::total_loan(), 0);
+
+        // nothing more without time passing
+        run_blocks(1);
+        assert_eq!(DepositLoanTest::get_loan_by_id(0).loan_balance_total, 550_000);
+    });
+}
+
+#[test]
+fn order_rates_are_bounded() {
+    new_test_ext().execute_with(|| {
+        assert_noop!(
+            DepositLoanTest::post_offer(Origin::signed(alice()), 600_000, MAX_FIXED_RATE + 1, 86400),
+            LoanError::InvalidOrder
+        );
+        assert_noop!(
+            DepositLoanTest::post_bid(Origin::signed(bob()), 100, 500_000, MAX_FIXED_RATE + 1, 86400),
+            LoanError::InvalidOrder
+        );
+        assert_ok!(DepositLoanTest::post_offer(Origin::signed(alice()), 600_000, MAX_FIXED_RATE, 86400));
+    });
+}
+
+#[test]
+fn liquidating_loans_are_settled_and_savers_redeem_pro_rata() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::set_close_factor(Origin::ROOT, 80));
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 500_000));
+        // LTV about 9260, part of the loan goes to auction
+        set_collateral_price(5400 * PRICE_PREC as PriceInUSDT);
+        run_blocks(1);
+        assert!(DepositLoanTest::partial_liquidations(0).is_some());
+
+        assert_ok!(DepositLoanTest::global_settle(Origin::ROOT, 5000 * PRICE_PREC as PriceInUSDT));
+        // the cash can be taken before the loan is settled, the shares owed by it are kept
+        let cash = free(USDT, &DepositLoanTest::collection_account_id());
+        let outstanding = DepositLoanTest::total_loan();
+        assert!(outstanding > 0);
+        assert_ok!(DepositLoanTest::settlement_redeem(Origin::signed(alice())));
+        assert_eq!(free(USDT, &alice()), INITIAL_BALANCE - 1_000_000 + cash);
+        assert_eq!(
+            DepositLoanTest::user_dtoken(alice()),
+            1_000_000 - (1_000_000 * cash + cash + outstanding - 1) / (cash + outstanding)
+        );
+
+        assert_ok!(DepositLoanTest::settle_loan(Origin::signed(charlie()), 0));
+        assert!(!DepositLoanTest::check_loan_in_liquidation(&0));
+        assert!(DepositLoanTest::partial_liquidations(0).is_none());
+        assert_eq!(DepositLoanTest::total_loan(), 0);
+
+        // the collateral is worth the debt at the settlement price
+        assert_ok!(DepositLoanTest::settlement_redeem(Origin::signed(alice())));
+        assert_eq!(free(USDT, &alice()), INITIAL_BALANCE - 500_000);
+        assert_eq!(free(BTC, &alice()), INITIAL_BALANCE + 100);
+        assert_eq!(DepositLoanTest::settlement_collateral(), 0);
+        assert_eq!(DepositLoanTest::user_dtoken(alice()), 0);
+    });
+}
+
+#[test]
+fn savers_redeeming_before_the_loans_are_settled_keep_their_share_of_them() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::staking(Origin::signed(charlie()), USDT, 1_000_000));
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 500_000));
+        assert_ok!(DepositLoanTest::global_settle(Origin::ROOT, 10000 * PRICE_PREC as PriceInUSDT));
+
+        // 1_500_000 cash and 500_000 lent, alice takes her half of the cash
+        assert_ok!(DepositLoanTest::settlement_redeem(Origin::signed(alice())));
+        assert_eq!(free(USDT, &alice()), INITIAL_BALANCE - 1_000_000 + 750_000);
+        assert_eq!(DepositLoanTest::user_dtoken(alice()), 250_000);
+        assert_eq!(DepositLoanTest::market_dtoken(), 1_250_000);
+
+        // 50 BTC is kept from the loan for the savers
+        assert_ok!(DepositLoanTest::settle_loan(Origin::signed(charlie()), 0));
+        assert_eq!(DepositLoanTest::settlement_collateral(), 50);
+
+        assert_ok!(DepositLoanTest::settlement_redeem(Origin::signed(charlie())));
+        assert_eq!(free(USDT, &charlie()), INITIAL_BALANCE - 1_000_000 + 600_000);
+        assert_eq!(free(BTC, &charlie()), INITIAL_BALANCE + 40);
+
+        assert_ok!(DepositLoanTest::settlement_redeem(Origin::signed(alice())));
+        assert_eq!(free(USDT, &alice()), INITIAL_BALANCE - 1_000_000 + 900_000);
+        assert_eq!(free(BTC, &alice()), INITIAL_BALANCE + 10);
+        assert_eq!(DepositLoanTest::user_dtoken(alice()), 0);
+        assert_eq!(DepositLoanTest::market_dtoken(), 0);
+        assert_eq!(DepositLoanTest::settlement_collateral(), 0);
+    });
+}
+
+#[test]
+fn repay_and_add_collateral_go_on_while_the_module_is_paused() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 300_000));
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 300_000));
+        assert_ok!(DepositLoanTest::pause(Origin::ROOT));
+
+        assert_noop!(
+            DepositLoanTest::merge_loans(Origin::signed(bob()), vec![0, 1]),
+            "module is paused"
+        );
+        assert_noop!(
+            DepositLoanTest::set_margin_mode(Origin::signed(bob()), true),
+            "module is paused"
+        );
+        assert_ok!(DepositLoanTest::add_collateral(Origin::signed(bob()), 0, 10));
+        assert_ok!(DepositLoanTest::repay_loan(Origin::signed(bob()), 1));
+
+        // they can still be stopped one by one
+        assert_ok!(DepositLoanTest::pause_operation(Origin::ROOT, Operation::Repay, b"audit".to_vec()));
+        assert_noop!(
+            DepositLoanTest::repay_loan(Origin::signed(bob()), 0),
+            LoanError::OperationPaused
+        );
+        assert_eq!(
+            DepositLoanTest::paused_operations(Operation::Repay),
+            Some(PauseRecord {
+                reason: b"audit".to_vec(),
+                block_number: 1,
+            })
+        );
+    });
+}
+
+#[test]
+fn pausing_an_operation_is_reported_with_its_reason() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(DepositLoanTest::pause_operation(Origin::ROOT, Operation::Borrow, b"audit".to_vec()));
+        assert_ok!(DepositLoanTest::resume_operation(Origin::ROOT, Operation::Borrow));
+
+        let events = loan_events();
+        assert!(events.iter().any(|e| match e {
+            RawEvent::OperationPaused(Operation::Borrow, reason, 1, _) => reason == b"audit",
+            _ => false,
+        }));
+        assert!(events
+            .iter()
+            .any(|e| *e == RawEvent::OperationResumed(Operation::Borrow)));
+        // the module level event keeps its old shape and is not used for operations
+        assert!(!events.iter().any(|e| match e {
+            RawEvent::Paused(..) => true,
+            _ => false,
+        }));
+    });
+}
+
+#[test]
+fn redeem_and_margin_calls_follow_their_operation_flag() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 600_000));
+        assert_ok!(DepositLoanTest::redeem(Origin::signed(alice()), USDT, 500_000));
+        assert_ok!(DepositLoanTest::pause_operation(Origin::ROOT, Operation::Redeem, vec![]));
+        assert_ok!(DepositLoanTest::pause_operation(Origin::ROOT, Operation::Borrow, vec![]));
+
+        assert_noop!(
+            DepositLoanTest::cancel_redeem(Origin::signed(alice()), 0),
+            LoanError::OperationPaused
+        );
+        assert_noop!(
+            DepositLoanTest::settlement_redeem(Origin::signed(alice())),
+            LoanError::OperationPaused
+        );
+        assert_noop!(
+            DepositLoanTest::set_margin_mode(Origin::signed(bob()), true),
+            LoanError::OperationPaused
+        );
+    });
+}
+
+#[test]
+fn thresholds_are_set_where_they_belong() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(DepositLoanTest::set_global_liquidation_threshold(Origin::ROOT, 9500));
+        assert_ok!(DepositLoanTest::set_global_warning_threshold(Origin::ROOT, 8500));
+        assert_eq!(DepositLoanTest::global_liquidation_threshold(), 9500);
+        assert_eq!(DepositLoanTest::global_warning_threshold(), 8500);
+
+        let changes: Vec<_> = loan_events()
+            .into_iter()
+            .filter_map(|e| match e {
+                RawEvent::ParameterChanged(p) => Some(p),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                PoolParameter::GlobalLiquidationThreshold(9500),
+                PoolParameter::GlobalWarningThreshold(8500),
+            ]
+        );
+    });
+}
+
+#[test]
+fn short_auctions_are_only_written_off_by_root() {
+    new_test_ext().execute_with(|| {
+        let loan_id = liquidating_loan(0);
+        let total_dtoken = DepositLoanTest::total_dtoken();
+
+        assert_noop!(
+            DepositLoanTest::mark_liquidated(Origin::signed(liquidation_account()), loan_id, 400_000),
+            "Not enough for loan liquidate"
+        );
+        assert_noop!(
+            DepositLoanTest::write_off_loan(Origin::ROOT, loan_id, 500_000),
+            LoanError::NoShortfall
+        );
+
+        assert_ok!(DepositLoanTest::write_off_loan(Origin::ROOT, loan_id, 400_000));
+        assert_eq!(
+            DepositLoanTest::archived_loan(loan_id).map(|r| r.status),
+            Some(LoanHealth::Defaulted)
+        );
+        assert_eq!(DepositLoanTest::total_dtoken(), total_dtoken - 100_000);
+        assert_eq!(DepositLoanTest::total_loan(), 0);
+        assert!(!DepositLoanTest::check_loan_in_liquidation(&loan_id));
+    });
+}
+
+#[test]
+fn full_liquidations_hand_the_collateral_over() {
+    new_test_ext().execute_with(|| {
+        // held in the pawnshop
+        let loan_id = liquidating_loan(0);
+        let shop = free(BTC, &DepositLoanTest::pawn_shop());
+        let liquidator = free(BTC, &liquidation_account());
+        assert_ok!(DepositLoanTest::mark_liquidated(
+            Origin::signed(liquidation_account()),
+            loan_id,
+            500_000
+        ));
+        assert_eq!(free(BTC, &DepositLoanTest::pawn_shop()), shop - 100);
+        assert_eq!(free(BTC, &liquidation_account()), liquidator + 100);
+        assert_eq!(DepositLoanTest::total_collateral(), 0);
+    });
+
+    new_test_ext().execute_with(|| {
+        // held in the borrower's reserve and written off
+        assert_ok!(DepositLoanTest::set_reserve_collateral(Origin::ROOT, true));
+        let loan_id = liquidating_loan(0);
+        assert_eq!(GenericAssetTest::reserved_balance(&BTC, &bob()), 100);
+        let liquidator = free(BTC, &liquidation_account());
+        assert_ok!(DepositLoanTest::write_off_loan(Origin::ROOT, loan_id, 400_000));
+        assert_eq!(GenericAssetTest::reserved_balance(&BTC, &bob()), 0);
+        assert_eq!(free(BTC, &liquidation_account()), liquidator + 100);
+        assert_eq!(DepositLoanTest::total_collateral(), 0);
+    });
+}
+
+#[test]
+fn archive_is_pruned_oldest_first() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 100_000));
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(charlie()), 100, 100_000));
+        TimestampTest::set_timestamp(10);
+        assert_ok!(DepositLoanTest::repay_loan(Origin::signed(bob()), 0));
+        TimestampTest::set_timestamp(20);
+        assert_ok!(DepositLoanTest::repay_loan(Origin::signed(charlie()), 1));
+        assert_eq!(DepositLoanTest::next_archive_position(), 2);
+
+        assert_ok!(DepositLoanTest::prune_archive(Origin::ROOT, 15, 10));
+        assert!(DepositLoanTest::archived_loan(0).is_none());
+        assert!(DepositLoanTest::archived_loans_by_account(bob()).is_empty());
+        assert_eq!(DepositLoanTest::archived_loan_at(0), None);
+        assert_eq!(DepositLoanTest::archived_loan_at(1), Some(1));
+        assert_eq!(DepositLoanTest::archive_head(), 1);
+        assert!(DepositLoanTest::archived_loan(1).is_some());
+    });
+}
+
+#[test]
+fn quick_repayments_do_not_build_credit() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::set_min_credited_loan_age(Origin::ROOT, 1_000));
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 100_000));
+        assert_ok!(DepositLoanTest::repay_loan(Origin::signed(bob()), 0));
+        assert_eq!(DepositLoanTest::credit_record(bob()), CreditRecord::default());
+
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 100_000));
+        TimestampTest::set_timestamp(1_000);
+        // a fresh loan merged into an old one doesn't inherit its age
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 100_000));
+        assert_ok!(DepositLoanTest::merge_loans(Origin::signed(bob()), vec![1, 2]));
+        assert_eq!(DepositLoanTest::loan_opened_at(1), 1_000);
+        TimestampTest::set_timestamp(1_500);
+        assert_ok!(DepositLoanTest::repay_loan(Origin::signed(bob()), 1));
+        assert_eq!(DepositLoanTest::credit_record(bob()).repaid, 0);
+
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 100_000));
+        TimestampTest::set_timestamp(2_500);
+        assert_ok!(DepositLoanTest::repay_loan(Origin::signed(bob()), 3));
+        let record = DepositLoanTest::credit_record(bob());
+        assert_eq!(record.repaid, 1);
+        assert_eq!(record.volume, 100_000);
+    });
+}
+
+/// bob borrows 600_000 against 100 BTC of his own and 50 BTC pledged by charlie
+fn guaranteed_loan() -> LoanId {
+    fund_pool(1_000_000);
+    assert_ok!(DepositLoanTest::apply_guaranteed_loan(
+        Origin::signed(bob()),
+        100,
+        600_000,
+        vec![(charlie(), Pledge::Collateral(50))]
+    ));
+    assert_ok!(DepositLoanTest::approve_guarantee(Origin::signed(charlie()), 0));
+    assert!(DepositLoanTest::loan_application(0).is_none());
+    0
+}
+
+#[test]
+fn guaranteed_loans_follow_reserve_mode() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(DepositLoanTest::set_reserve_collateral(Origin::ROOT, true));
+        let loan_id = guaranteed_loan();
+        let reserved = || GenericAssetTest::reserved_balance(&BTC, &bob());
+        assert_eq!(reserved(), 100);
+        assert_eq!(free(BTC, &DepositLoanTest::pawn_shop()), 50);
+
+        // the pledge is seized into bob's reserve
+        set_collateral_price(4000 * PRICE_PREC as PriceInUSDT);
+        run_blocks(1);
+        assert!(DepositLoanTest::check_loan_in_liquidation(&loan_id));
+        assert_eq!(DepositLoanTest::get_loan_by_id(loan_id).collateral_balance_original, 150);
+        assert_eq!(reserved(), 150);
+        assert_eq!(free(BTC, &DepositLoanTest::pawn_shop()), 0);
+        assert_eq!(free(BTC, &charlie()), INITIAL_BALANCE - 50);
+    });
+}
+
+#[test]
+fn pledges_which_cannot_be_released_are_kept_for_a_retry() {
+    new_test_ext().execute_with(|| {
+        let loan_id = guaranteed_loan();
+        let shop = DepositLoanTest::pawn_shop();
+        assert_ok!(<GenericAssetTest as MultiCurrency<_>>::transfer(&BTC, &shop, &alice(), 50));
+
+        assert_ok!(DepositLoanTest::repay_loan(Origin::signed(bob()), loan_id));
+        assert_eq!(free(BTC, &bob()), INITIAL_BALANCE);
+        assert_eq!(DepositLoanTest::loan_guarantees(loan_id).len(), 1);
+        assert!(loan_events().into_iter().any(|e| match e {
+            RawEvent::GuaranteeReleaseFailed(id, _, Pledge::Collateral(50)) => id == loan_id,
+            _ => false,
+        }));
+
+        assert_ok!(<GenericAssetTest as MultiCurrency<_>>::transfer(&BTC, &alice(), &shop, 50));
+        assert_ok!(DepositLoanTest::release_pledges(Origin::signed(alice()), loan_id));
+        assert_eq!(free(BTC, &charlie()), INITIAL_BALANCE);
+        assert!(DepositLoanTest::loan_guarantees(loan_id).is_empty());
+        assert_noop!(
+            DepositLoanTest::release_pledges(Origin::signed(alice()), loan_id),
+            LoanError::NothingToRelease
+        );
+    });
+}
+
+#[test]
+fn bids_follow_reserve_mode() {
+    new_test_ext().execute_with(|| {
+        assert_ok!(DepositLoanTest::set_reserve_collateral(Origin::ROOT, true));
+        let reserved = || GenericAssetTest::reserved_balance(&BTC, &bob());
+        let rate = INTEREST_RATE_PREC as u64 / 10;
+
+        assert_ok!(DepositLoanTest::post_bid(Origin::signed(bob()), 100, 500_000, rate, 86400));
+        assert_eq!(reserved(), 100);
+        assert_eq!(free(BTC, &DepositLoanTest::pawn_shop()), 0);
+        assert_ok!(DepositLoanTest::cancel_order(Origin::signed(bob()), 0));
+        assert_eq!(reserved(), 0);
+        assert_eq!(free(BTC, &bob()), INITIAL_BALANCE);
+
+        // the reserve of the bid is the one of the loan
+        assert_ok!(DepositLoanTest::post_bid(Origin::signed(bob()), 100, 500_000, rate, 86400));
+        assert_ok!(DepositLoanTest::post_offer(Origin::signed(alice()), 500_000, rate, 86400));
+        assert_ok!(DepositLoanTest::match_orders(Origin::signed(charlie()), 2, 1));
+        assert_eq!(reserved(), 100);
+        assert_eq!(DepositLoanTest::collateral_lock(0).map(|(_, locked)| locked), Some(100));
+        assert!(DepositLoanTest::bid_collateral_lock(1).is_none());
+
+        assert_ok!(DepositLoanTest::repay_loan(Origin::signed(bob()), 0));
+        assert_eq!(reserved(), 0);
+        assert!(DepositLoanTest::collateral_lock(0).is_none());
+    });
+}
+
+#[test]
+fn old_accounts_are_emptied_of_free_and_reserved_balances() {
+    new_test_ext().execute_with(|| {
+        <PawnShop<Test>>::put(charlie());
+        assert_ok!(GenericAssetTest::reserve(&BTC, &charlie(), 10));
+        assert!(!DepositLoanTest::accounts_migrated());
+
+        // one account per block, the collection account comes first
+        run_blocks(1);
+        assert_eq!(<PawnShop<Test>>::get(), Some(charlie()));
+        assert_eq!(DepositLoanTest::account_migration_step(), 1);
+        run_blocks(1);
+        assert!(!DepositLoanTest::accounts_migrated());
+        run_blocks(1);
+        let shop = DepositLoanTest::pawn_shop();
+        assert_eq!(free(BTC, &shop), INITIAL_BALANCE);
+        assert_eq!(free(USDT, &shop), INITIAL_BALANCE);
+        assert_eq!(GenericAssetTest::total_balance(&BTC, &charlie()), 0);
+        assert_eq!(GenericAssetTest::total_balance(&USDT, &charlie()), 0);
+        assert_eq!(<PawnShop<Test>>::get(), None);
+        assert!(DepositLoanTest::accounts_migrated());
+
+        assert_noop!(
+            DepositLoanTest::retry_account_migration(Origin::ROOT),
+            LoanError::NothingToMigrate
+        );
+    });
+}
+
+#[test]
+fn a_failed_account_migration_waits_for_a_retry() {
+    new_test_ext().execute_with(|| {
+        <AccountMigrationStep>::put(3);
+        <ProfitPool<Test>>::put(charlie());
+        run_blocks(1);
+        assert_eq!(<ProfitPool<Test>>::get(), Some(charlie()));
+
+        assert_ok!(DepositLoanTest::retry_account_migration(Origin::ROOT));
+        assert_eq!(DepositLoanTest::account_migration_step(), 0);
+        run_blocks(3);
+        assert_eq!(<ProfitPool<Test>>::get(), None);
+        assert_eq!(free(USDT, &DepositLoanTest::profit_pool()), INITIAL_BALANCE);
+        assert!(DepositLoanTest::accounts_migrated());
+    });
+}
+
+#[test]
+fn reward_weights_follow_the_running_debt_through_settlement() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        let shares = DepositLoanTest::user_dtoken(alice());
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 500_000));
+        assert_eq!(DepositLoanTest::account_debt(bob()), 500_000);
+        assert_eq!(DepositLoanTest::reward_weights(bob()), (0, 500_000));
+
+        assert_ok!(DepositLoanTest::repay_for(Origin::signed(charlie()), 0, 100_000));
+        assert_eq!(DepositLoanTest::account_debt(bob()), 400_000);
+        assert_eq!(DepositLoanTest::total_reward_weights(), (shares, 400_000));
+
+        assert_ok!(DepositLoanTest::global_settle(Origin::ROOT, 10000 * PRICE_PREC as PriceInUSDT));
+        assert_ok!(DepositLoanTest::settle_loan(Origin::signed(charlie()), 0));
+        assert_eq!(DepositLoanTest::account_debt(bob()), 0);
+        assert_eq!(DepositLoanTest::reward_weights(bob()), (0, 0));
+
+        assert_ok!(DepositLoanTest::settlement_redeem(Origin::signed(alice())));
+        assert_eq!(DepositLoanTest::reward_weights(alice()), (0, 0));
+        assert_eq!(DepositLoanTest::total_reward_weights(), (0, 0));
+    });
+}
+
+#[test]
+fn savers_and_borrowers_from_before_the_rewards_are_seeded() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 500_000));
+        let shares = DepositLoanTest::user_dtoken(alice());
+
+        // as it was before the rewards kept any weights
+        <RewardWeights<Test>>::remove(alice());
+        <RewardWeights<Test>>::remove(bob());
+        <TotalRewardWeights<Test>>::kill();
+        <AccountDebt<Test>>::remove(bob());
+        <RewardedLoanDebt<Test>>::remove(0);
+
+        <RewardsSeeded>::put(false);
+
+        // one loan or saver per block
+        assert_ok!(DepositLoanTest::set_scan_limit(Origin::ROOT, 1));
+        run_blocks(1);
+        assert_eq!(DepositLoanTest::account_debt(bob()), 500_000);
+        assert_eq!(DepositLoanTest::reward_weights(bob()), (0, 500_000));
+        assert_eq!(DepositLoanTest::reward_weights(alice()), (0, 0));
+        run_blocks(1);
+        assert_eq!(DepositLoanTest::reward_weights(alice()), (shares, 0));
+        assert!(!DepositLoanTest::rewards_seeded());
+        run_blocks(1);
+        assert!(DepositLoanTest::rewards_seeded());
+        assert_eq!(DepositLoanTest::total_reward_weights(), (shares, 500_000));
+    });
+}
+
+#[test]
+fn interest_paid_in_another_asset_reaches_the_savers() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 500_000));
+        run_blocks(1);
+        TimestampTest::set_timestamp(365 * 86400);
+        run_blocks(1);
+        assert!(DepositLoanTest::loan_interest_accrued(0) > 10_000);
+        assert_ok!(DepositLoanTest::set_interest_assets(Origin::ROOT, vec![BTC]));
+
+        // one BTC is worth 10_000 of the loan asset
+        assert_noop!(
+            DepositLoanTest::pay_interest(Origin::signed(bob()), 0, BTC, 1),
+            LoanError::InsufficientProfitPool
+        );
+        let profit_pool = DepositLoanTest::profit_pool();
+        assert_ok!(GenericAssetTest::make_transfer(&USDT, &charlie(), &profit_pool, 10_000));
+
+        let collection_account = DepositLoanTest::collection_account_id();
+        let pool_value = free(USDT, &collection_account) + DepositLoanTest::total_loan();
+        let total_loan = DepositLoanTest::total_loan();
+        assert_ok!(DepositLoanTest::pay_interest(Origin::signed(bob()), 0, BTC, 1));
+        assert_eq!(DepositLoanTest::total_loan(), total_loan - 10_000);
+        assert_eq!(free(USDT, &collection_account) + DepositLoanTest::total_loan(), pool_value);
+        assert_eq!(free(BTC, &profit_pool), 1);
+        assert_eq!(free(USDT, &profit_pool), 0);
+    });
+}
+
+#[test]
+fn interest_in_another_asset_is_priced_on_its_own_and_rounded_up() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 500_000));
+        run_blocks(1);
+        TimestampTest::set_timestamp(365 * 86400);
+        run_blocks(1);
+        let accrued = DepositLoanTest::loan_interest_accrued(0);
+        assert!(accrued > 0);
+        assert_ok!(DepositLoanTest::set_interest_assets(Origin::ROOT, vec![DOT]));
+        let profit_pool = DepositLoanTest::profit_pool();
+        assert_ok!(GenericAssetTest::make_transfer(&USDT, &charlie(), &profit_pool, accrued));
+
+        // the collateral price is not taken for it
+        assert_noop!(
+            DepositLoanTest::pay_interest(Origin::signed(bob()), 0, DOT, 1),
+            "price not available"
+        );
+
+        // 3 of the loan asset per DOT, at most 2 blocks old
+        assert_ok!(DepositLoanTest::set_max_price_age(Origin::ROOT, 2));
+        set_asset_price(DOT, 3 * PRICE_PREC as PriceInUSDT);
+        run_blocks(3);
+        assert_noop!(
+            DepositLoanTest::pay_interest(Origin::signed(bob()), 0, DOT, 1),
+            LoanError::PriceStale
+        );
+
+        set_asset_price(DOT, 3 * PRICE_PREC as PriceInUSDT);
+        let dot = free(DOT, &bob());
+        assert_ok!(DepositLoanTest::pay_interest(Origin::signed(bob()), 0, DOT, 1_000_000));
+        assert_eq!(free(DOT, &bob()), dot - (accrued + 2) / 3);
+        assert_eq!(DepositLoanTest::loan_interest_accrued(0), 0);
+    });
+}
+
+#[test]
+fn fixed_rate_lenders_only_take_the_loan_asset() {
+    new_test_ext().execute_with(|| {
+        let rate = INTEREST_RATE_PREC as u64 / 10;
+        assert_ok!(DepositLoanTest::post_offer(Origin::signed(alice()), 600_000, rate, 2 * 365 * 86400));
+        assert_ok!(DepositLoanTest::post_bid(Origin::signed(bob()), 100, 500_000, rate, 365 * 86400));
+        assert_ok!(DepositLoanTest::match_orders(Origin::signed(charlie()), 0, 1));
+        TimestampTest::set_timestamp(365 * 86400);
+        run_blocks(1);
+        assert_eq!(DepositLoanTest::loan_interest_accrued(0), 50_000);
+        assert_ok!(DepositLoanTest::set_interest_assets(Origin::ROOT, vec![BTC]));
+
+        assert_noop!(
+            DepositLoanTest::pay_interest(Origin::signed(bob()), 0, BTC, 1),
+            LoanError::InterestAssetNotAccepted
+        );
+
+        let lender = free(USDT, &alice());
+        assert_ok!(DepositLoanTest::pay_interest(Origin::signed(bob()), 0, USDT, 50_000));
+        assert_eq!(free(USDT, &alice()), lender + 50_000);
+        assert_eq!(DepositLoanTest::loan_interest_accrued(0), 0);
+    });
+}
+
+#[test]
+fn repaying_for_someone_pays_their_drawn_interest_first() {
+    new_test_ext().execute_with(|| {
+        fund_pool(1_000_000);
+        assert_ok!(DepositLoanTest::apply_loan(Origin::signed(bob()), 100, 500_000));
+        // bob pays no interest in cash, it is all drawn on the loan
+        let cash = free(USDT, &bob());
+        assert_ok!(GenericAssetTest::make_transfer(&USDT, &bob(), &charlie(), cash));
+        run_blocks(1);
+        TimestampTest::set_timestamp(365 * 86400);
+        run_blocks(1);
+        let accrued = DepositLoanTest::loan_interest_accrued(0);
+        assert!(accrued > 0 && accrued < 100_000);
+        assert_eq!(DepositLoanTest::interest_paid(bob()), 0);
+
+        assert_ok!(DepositLoanTest::repay_for(Origin::signed(charlie()), 0, 100_000));
+        assert_eq!(DepositLoanTest::interest_paid(bob()), accrued);
+        assert_eq!(DepositLoanTest::loan_interest_paid(0), accrued);
+        assert_eq!(DepositLoanTest::loan_interest_accrued(0), 0);
+        assert_eq!(DepositLoanTest::get_loan_by_id(0).loan_balance_total, 500_000 + accrued - 100_000);
+
+        // a loan in liquidation is turned down before anything is charged
+        set_collateral_price(4000 * PRICE_PREC as PriceInUSDT);
+        run_blocks(1);
+        assert!(DepositLoanTest::check_loan_in_liquidation(&0));
+        TimestampTest::set_timestamp(2 * 365 * 86400);
+        assert_noop!(
+            DepositLoanTest::repay_for(Origin::signed(charlie()), 0, 10_000_000),
+            "loan is in liquidation"
+        );
+    });
+}
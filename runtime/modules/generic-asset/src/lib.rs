@@ -66,6 +66,87 @@ impl<T: Trait> Subtrait for T {
     type AssetId = T::AssetId;
 }
 
+/// Abstraction over a multi-asset ledger, so that other modules do not depend on how assets
+/// are stored.
+pub trait MultiCurrency<AccountId> {
+    /// The asset identifier.
+    type AssetId: Parameter + Member + AtLeast32Bit + Default + Copy;
+    /// The balance of an account.
+    type Balance: Parameter
+        + Member
+        + AtLeast32Bit
+        + Default
+        + Copy
+        + MaybeSerializeDeserialize
+        + Debug;
+
+    /// Whether `asset_id` has been created.
+    fn asset_exists(asset_id: &Self::AssetId) -> bool;
+
+    /// The combined free and reserved balance of `who`.
+    fn total_balance(asset_id: &Self::AssetId, who: &AccountId) -> Self::Balance;
+
+    /// The free balance of `who`.
+    fn free_balance(asset_id: &Self::AssetId, who: &AccountId) -> Self::Balance;
+
+    /// Transfer `amount` of free balance from `from` to `to`.
+    fn transfer(
+        asset_id: &Self::AssetId,
+        from: &AccountId,
+        to: &AccountId,
+        amount: Self::Balance,
+    ) -> DispatchResult;
+
+    /// Mint `amount` into the free balance of `to`, `minter` must hold the mint permission.
+    fn deposit(
+        asset_id: &Self::AssetId,
+        minter: &AccountId,
+        to: &AccountId,
+        amount: Self::Balance,
+    ) -> DispatchResult;
+
+    /// Burn `amount` from the free balance of `from`, `burner` must hold the burn permission.
+    fn withdraw(
+        asset_id: &Self::AssetId,
+        burner: &AccountId,
+        from: &AccountId,
+        amount: Self::Balance,
+    ) -> DispatchResult;
+}
+
+/// A `MultiCurrency` which can also put balance aside under a lock.
+pub trait MultiReservableCurrency<AccountId>: MultiCurrency<AccountId> {
+    /// The reserved balance of `who`.
+    fn reserved_balance(asset_id: &Self::AssetId, who: &AccountId) -> Self::Balance;
+
+    /// Move `amount` from free to reserved balance, returns the id of the lock created.
+    fn reserve(
+        asset_id: &Self::AssetId,
+        who: &AccountId,
+        amount: Self::Balance,
+    ) -> result::Result<u128, DispatchError>;
+
+    /// Move `amount` from reserved back to free balance, releasing `lock_id` or a lock of
+    /// exactly `amount` when none is given.
+    fn unreserve(
+        asset_id: &Self::AssetId,
+        who: &AccountId,
+        amount: Self::Balance,
+        lock_id: Option<u128>,
+    ) -> DispatchResult;
+
+    /// Move up to `amount` from the reserved balance of `who` to `beneficiary`, returns what
+    /// could not be moved. `lock_id` is shrunk by what was moved and released once empty.
+    fn repatriate_reserved(
+        asset_id: &Self::AssetId,
+        who: &AccountId,
+        beneficiary: &AccountId,
+        amount: Self::Balance,
+        status: BalanceStatus,
+        lock_id: Option<u128>,
+    ) -> Self::Balance;
+}
+
 /// Asset creation options.
 #[derive(Clone, Encode, Decode, PartialEq, Eq, RuntimeDebug)]
 pub struct AssetOptions<Balance: HasCompact, AccountId> {
@@ -399,6 +480,94 @@ decl_event!(
 	}
 );
 
+impl<T: Trait> MultiCurrency<T::AccountId> for Module<T> {
+    type AssetId = T::AssetId;
+    type Balance = T::Balance;
+
+    fn asset_exists(asset_id: &T::AssetId) -> bool {
+        Self::asset_id_exists(*asset_id)
+    }
+
+    fn total_balance(asset_id: &T::AssetId, who: &T::AccountId) -> T::Balance {
+        Self::total_balance(asset_id, who)
+    }
+
+    fn free_balance(asset_id: &T::AssetId, who: &T::AccountId) -> T::Balance {
+        Self::free_balance(asset_id, who)
+    }
+
+    fn transfer(
+        asset_id: &T::AssetId,
+        from: &T::AccountId,
+        to: &T::AccountId,
+        amount: T::Balance,
+    ) -> DispatchResult {
+        Self::make_transfer_with_event(asset_id, from, to, amount)
+    }
+
+    fn deposit(
+        asset_id: &T::AssetId,
+        minter: &T::AccountId,
+        to: &T::AccountId,
+        amount: T::Balance,
+    ) -> DispatchResult {
+        Self::mint_free(asset_id, minter, to, &amount)
+    }
+
+    fn withdraw(
+        asset_id: &T::AssetId,
+        burner: &T::AccountId,
+        from: &T::AccountId,
+        amount: T::Balance,
+    ) -> DispatchResult {
+        Self::burn_free(asset_id, burner, from, &amount)
+    }
+}
+
+impl<T: Trait> MultiReservableCurrency<T::AccountId> for Module<T> {
+    fn reserved_balance(asset_id: &T::AssetId, who: &T::AccountId) -> T::Balance {
+        Self::reserved_balance(asset_id, who)
+    }
+
+    fn reserve(
+        asset_id: &T::AssetId,
+        who: &T::AccountId,
+        amount: T::Balance,
+    ) -> result::Result<u128, DispatchError> {
+        Ok(Self::reserve(asset_id, who, amount)?)
+    }
+
+    fn unreserve(
+        asset_id: &T::AssetId,
+        who: &T::AccountId,
+        amount: T::Balance,
+        lock_id: Option<u128>,
+    ) -> DispatchResult {
+        Ok(Self::unreserve(asset_id, who, amount, lock_id)?)
+    }
+
+    fn repatriate_reserved(
+        asset_id: &T::AssetId,
+        who: &T::AccountId,
+        beneficiary: &T::AccountId,
+        amount: T::Balance,
+        status: BalanceStatus,
+        lock_id: Option<u128>,
+    ) -> T::Balance {
+        let remaining = Self::repatriate_reserved(asset_id, who, beneficiary, amount, status);
+        let moved = amount - remaining;
+        if let Some(lock_id) = lock_id {
+            let identifier = Self::generic_asset_lock_identifier(asset_id);
+            Self::reduce_lock(identifier, who, lock_id, moved);
+        }
+        // a reserve without a lock could never be unreserved by the beneficiary
+        if status == BalanceStatus::Reserved && !moved.is_zero() && who != beneficiary {
+            Self::set_lock(asset_id, beneficiary, moved, WithdrawReason::Reserve.into());
+        }
+        remaining
+    }
+}
+
 impl<T: Trait> Module<T> {
     // PUBLIC IMMUTABLES
 
@@ -694,6 +863,10 @@ impl<T: Trait> Module<T> {
     /// the `remaining` would be returned, else `Zero::zero()`.
     /// NOTE: LOW-LEVEL: This will not attempt to maintain total issuance. It is expected that
     /// the caller will do this.
+    ///
+    /// NOTE: this used to be a stub that moved nothing and always returned zero, so callers
+    /// written against it were told everything had moved while no balance changed hands. It now
+    /// really debits `who` and credits `beneficiary`.
     pub fn repatriate_reserved(
         asset_id: &T::AssetId,
         who: &T::AccountId,
@@ -701,26 +874,25 @@ impl<T: Trait> Module<T> {
         amount: T::Balance,
         status: BalanceStatus,
     ) -> T::Balance {
-        // let b = Self::reserved_balance(asset_id, who);
-        // let slash = sp_std::cmp::min(b, amount);
-
-        // match status {
-        //     BalanceStatus::Free => {
-        //         let original_free_balance = Self::free_balance(asset_id, beneficiary);
-        //         let new_free_balance = original_free_balance + slash;
-        //         Self::set_free_balance(asset_id, beneficiary, new_free_balance);
-        //     }
-        //     BalanceStatus::Reserved => {
-        //         let original_reserved_balance = Self::reserved_balance(asset_id, beneficiary);
-        //         let new_reserved_balance = original_reserved_balance + slash;
-        //         Self::set_reserved_balance(asset_id, beneficiary, new_reserved_balance);
-        //     }
-        // }
+        let b = Self::reserved_balance(asset_id, who);
+        let slash = cmp::min(b, amount);
+
+        // taken off first so that repatriating to oneself is a no-op
+        Self::set_reserved_balance(asset_id, who, b - slash);
+        match status {
+            BalanceStatus::Free => {
+                let original_free_balance = Self::free_balance(asset_id, beneficiary);
+                let new_free_balance = original_free_balance + slash;
+                Self::set_free_balance(asset_id, beneficiary, new_free_balance);
+            }
+            BalanceStatus::Reserved => {
+                let original_reserved_balance = Self::reserved_balance(asset_id, beneficiary);
+                let new_reserved_balance = original_reserved_balance + slash;
+                Self::set_reserved_balance(asset_id, beneficiary, new_reserved_balance);
+            }
+        }
 
-        // let new_reserve_balance = b - slash;
-        // Self::set_reserved_balance(asset_id, who, new_reserve_balance);
-        // amount - slash
-        0.into()
+        amount - slash
     }
 
     /// Check permission to perform burn, mint or update.
@@ -891,6 +1063,23 @@ impl<T: Trait> Module<T> {
         }
     }
 
+    /// take `amount` off lock `lock_id`, the lock is dropped once nothing is left in it
+    fn reduce_lock(identifier: LockIdentifier, who: &T::AccountId, lock_id: u128, amount: T::Balance) {
+        let locks = <Locks<T>>::get(identifier, who)
+            .into_iter()
+            .filter_map(|mut l| {
+                if l.id == lock_id {
+                    l.amount = l.amount.saturating_sub(amount);
+                    if l.amount.is_zero() {
+                        return None;
+                    }
+                }
+                Some(l)
+            })
+            .collect::<Vec<_>>();
+        <Locks<T>>::insert(identifier, who, locks);
+    }
+
     pub fn lock_id_exists(identifier: LockIdentifier, who: &T::AccountId, lock_id: u128) -> bool {
         let locks = <Locks<T>>::get(identifier, who);
         for l in locks {
@@ -644,6 +644,159 @@ fn repatriate_reserved_return_none() {
     });
 }
 
+// Given
+// - reserved_balance = 100.
+// When
+// - After calling repatriate_reserved for 60 into the free balance of another account.
+// Then
+// - 60 leaves the reserve of the owner and lands in the free balance of the beneficiary.
+#[test]
+fn repatriate_reserved_should_move_the_balance_to_free() {
+    ExtBuilder::default().build().execute_with(|| {
+        GenericAsset::set_reserved_balance(&1, &0, 100);
+        assert_eq!(
+            GenericAsset::repatriate_reserved(&1, &0, &2, 60, BalanceStatus::Free),
+            0
+        );
+        assert_eq!(GenericAsset::reserved_balance(&1, &0), 40);
+        assert_eq!(GenericAsset::free_balance(&1, &0), 0);
+        assert_eq!(GenericAsset::free_balance(&1, &2), 60);
+        assert_eq!(GenericAsset::reserved_balance(&1, &2), 0);
+    });
+}
+
+// Given
+// - reserved_balance = 100.
+// When
+// - After calling repatriate_reserved for 130 into the reserved balance of another account.
+// Then
+// - The whole 100 moves into the reserve of the beneficiary and 30 is returned.
+#[test]
+fn repatriate_reserved_should_move_the_balance_to_reserved() {
+    ExtBuilder::default().build().execute_with(|| {
+        GenericAsset::set_reserved_balance(&1, &0, 100);
+        assert_eq!(
+            GenericAsset::repatriate_reserved(&1, &0, &2, 130, BalanceStatus::Reserved),
+            30
+        );
+        assert_eq!(GenericAsset::reserved_balance(&1, &0), 0);
+        assert_eq!(GenericAsset::reserved_balance(&1, &2), 100);
+        assert_eq!(GenericAsset::free_balance(&1, &2), 0);
+    });
+}
+
+// Given
+// - reserved_balance = 100.
+// When
+// - After calling repatriate_reserved to the same account into its reserve.
+// Then
+// - Nothing changes.
+#[test]
+fn repatriate_reserved_to_oneself_should_keep_the_reserve() {
+    ExtBuilder::default().build().execute_with(|| {
+        GenericAsset::set_reserved_balance(&1, &0, 100);
+        assert_eq!(
+            GenericAsset::repatriate_reserved(&1, &0, &0, 60, BalanceStatus::Reserved),
+            0
+        );
+        assert_eq!(GenericAsset::reserved_balance(&1, &0), 100);
+        assert_eq!(GenericAsset::free_balance(&1, &0), 0);
+    });
+}
+
+// Given
+// - 100 reserved under one lock.
+// When
+// - After repatriating 60 of it through "MultiReservableCurrency" naming the lock.
+// Then
+// - The beneficiary gets 60 free and the lock keeps the 40 left.
+#[test]
+fn repatriate_reserved_should_shrink_the_lock() {
+    ExtBuilder::default()
+        .free_balance((1, 0, 100))
+        .build()
+        .execute_with(|| {
+            let lock_id = GenericAsset::reserve(&1, &0, 100).unwrap();
+            assert_eq!(
+                <GenericAsset as MultiReservableCurrency<u64>>::repatriate_reserved(
+                    &1,
+                    &0,
+                    &2,
+                    60,
+                    BalanceStatus::Free,
+                    Some(lock_id)
+                ),
+                0
+            );
+            assert_eq!(GenericAsset::reserved_balance(&1, &0), 40);
+            assert_eq!(GenericAsset::free_balance(&1, &2), 60);
+            assert_eq!(GenericAsset::locked_balance(&1, &0, lock_id), Some(40));
+        });
+}
+
+// Given
+// - 100 reserved under one lock.
+// When
+// - After repatriating 130 of it through "MultiReservableCurrency" naming the lock.
+// Then
+// - 30 is returned as not moved and the emptied lock is released.
+#[test]
+fn repatriate_reserved_short_of_reserve_should_release_the_lock() {
+    ExtBuilder::default()
+        .free_balance((1, 0, 100))
+        .build()
+        .execute_with(|| {
+            let lock_id = GenericAsset::reserve(&1, &0, 100).unwrap();
+            assert_eq!(
+                <GenericAsset as MultiReservableCurrency<u64>>::repatriate_reserved(
+                    &1,
+                    &0,
+                    &2,
+                    130,
+                    BalanceStatus::Free,
+                    Some(lock_id)
+                ),
+                30
+            );
+            assert_eq!(GenericAsset::reserved_balance(&1, &0), 0);
+            assert_eq!(GenericAsset::free_balance(&1, &2), 100);
+            assert_eq!(GenericAsset::locked_balance(&1, &0, lock_id), None);
+        });
+}
+
+// Given
+// - 100 reserved under one lock.
+// When
+// - After repatriating 60 of it into the reserve of the beneficiary.
+// Then
+// - The beneficiary holds 60 reserved under a lock of its own and can unreserve it.
+#[test]
+fn repatriate_reserved_to_reserved_should_lock_it_for_the_beneficiary() {
+    ExtBuilder::default()
+        .free_balance((1, 0, 100))
+        .build()
+        .execute_with(|| {
+            let lock_id = GenericAsset::reserve(&1, &0, 100).unwrap();
+            assert_eq!(
+                <GenericAsset as MultiReservableCurrency<u64>>::repatriate_reserved(
+                    &1,
+                    &0,
+                    &2,
+                    60,
+                    BalanceStatus::Reserved,
+                    Some(lock_id)
+                ),
+                0
+            );
+            assert_eq!(GenericAsset::reserved_balance(&1, &2), 60);
+            assert_eq!(GenericAsset::free_balance(&1, &2), 0);
+            assert_ok!(<GenericAsset as MultiReservableCurrency<u64>>::unreserve(
+                &1, &2, 60, None
+            ));
+            assert_eq!(GenericAsset::free_balance(&1, &2), 60);
+        });
+}
+
 // Given
 // - An asset with all permissions
 // When
@@ -87,6 +87,8 @@ decl_storage! {
         pub CryptoPriceSources get(crypto_price_sources) config() : linked_map hasher(blake2_256) StrBytes => Vec<(StrBytes, StrBytes, Vec<StrBytes>)>;
         pub PriceCandidates get(price_candidates) : linked_map hasher(blake2_256) StrBytes => Vec<T::PriceInUSDT>;
         pub CurrentPrice get(current_price) config() : linked_map hasher(blake2_256) StrBytes => T::PriceInUSDT;
+        /// (block, moment) at which "CurrentPrice" of a token was last aggregated
+        pub PriceUpdatedAt get(price_updated_at) : map hasher(blake2_256) StrBytes => (T::BlockNumber, T::Moment);
         pub NextAggregateAt get(next_aggregate_at) : T::BlockNumber;
     }
 }
@@ -109,6 +111,7 @@ decl_module! {
                     } else {
                         <CurrentPrice<T>>::insert(&k, mean);
                     }
+                    <PriceUpdatedAt<T>>::insert(&k, (bn, <timestamp::Module<T>>::get()));
                 }
                 <NextAggregateAt<T>>::put(bn + T::AggregateInterval::get());
             }
@@ -35,7 +35,8 @@ use sp_runtime::traits::{
 };
 use sp_runtime::transaction_validity::TransactionValidity;
 use sp_runtime::{
-    create_runtime_str, generic, impl_opaque_keys, ApplyExtrinsicResult, Perbill, Percent, Permill,
+    create_runtime_str, generic, impl_opaque_keys, ApplyExtrinsicResult, ModuleId, Perbill, Percent,
+    Permill,
 };
 use sp_std::prelude::*;
 use sp_version::RuntimeVersion;
@@ -627,6 +628,7 @@ impl generic_asset::Trait for Runtime {
 
 // impl bridge::Trait for Runtime {
 //     type Event = Event;
+//     type Currency = GenericAsset;
 // }
 
 type SubmitOracleTransaction =
@@ -645,8 +647,24 @@ impl new_oracle::Trait for Runtime {
     type PriceInUSDT = u64;
 }
 
+type SubmitLiquidationTransaction =
+    TransactionSubmitter<deposit_loan::crypto::Public, Runtime, UncheckedExtrinsic>;
+
+parameter_types! {
+    pub const MaxLiquidationBatch: u32 = 20;
+    pub const MaxMergeLoans: u32 = 8;
+    pub const DepositLoanModuleId: ModuleId = ModuleId(*b"dfx/loan");
+}
+
 impl deposit_loan::Trait for Runtime {
     type Event = Event;
+    type Currency = GenericAsset;
+    type PriceProvider = deposit_loan::NewOracleProvider<Runtime>;
+    type Call = Call;
+    type SubmitUnsignedTransaction = SubmitLiquidationTransaction;
+    type MaxLiquidationBatch = MaxLiquidationBatch;
+    type MaxMergeLoans = MaxMergeLoans;
+    type ModuleId = DepositLoanModuleId;
 }
 
 construct_runtime!(
@@ -685,7 +703,7 @@ construct_runtime!(
 		Vesting: pallet_vesting::{Module, Call, Storage, Event<T>, Config<T>},
 
         // Bridge: bridge::{Module, Call, Storage, Event<T>, Config<T>},
-        DepositLoan: deposit_loan::{Module, Call, Storage, Event<T>, Config<T>},
+        DepositLoan: deposit_loan::{Module, Call, Storage, Event<T>, Config<T>, ValidateUnsigned},
         GenericAsset: generic_asset::{Module, Call, Storage, Event<T>, Config<T>},
         NewOracle: new_oracle::{Module, Call, Storage, Config<T>, Event<T>, ValidateUnsigned},
 	}
@@ -870,6 +888,14 @@ impl_runtime_apis! {
         }
     }
 
+    impl deposit_loan_rpc_runtime_api::DepositLoanApi<Block, AccountId, Balance, Moment> for Runtime {
+        fn interest_summary(
+            who: AccountId,
+        ) -> deposit_loan_rpc_runtime_api::InterestSummary<Balance, Moment> {
+            DepositLoan::interest_summary(who)
+        }
+    }
+
     impl sp_session::SessionKeys<Block> for Runtime {
         fn generate_session_keys(seed: Option<Vec<u8>>) -> Vec<u8> {
             SessionKeys::generate(seed)